@@ -11,6 +11,11 @@ fn main() {
         return;
     }
 
+    // The resources are Windows-specific.
+    if std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default() != "windows" {
+        return;
+    }
+
     embed_resource::compile("resources/app.rc", embed_resource::NONE)
         .manifest_optional()
         .unwrap();
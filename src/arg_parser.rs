@@ -26,8 +26,12 @@ pub enum ServiceSubcommands {
     /// This is not intended to be invoked directly by users.
     /// It is automatically passed as a launch argument when the service is installed,
     /// so that the SCM starts the process with `BarvazDNS.exe service run-as-service`.
+    #[cfg(windows)]
     #[clap(hide = true)]
     RunAsService,
+    /// Runs the service in the foreground until it receives SIGTERM or SIGINT.
+    #[cfg(unix)]
+    Run(RunArgs),
 }
 
 #[derive(Args, Debug)]
@@ -48,6 +52,15 @@ pub struct StartArgs {
     pub no_web: bool,
 }
 
+#[cfg(unix)]
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Runs without the web dashboard
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub no_web: bool,
+}
+
+#[cfg(windows)]
 #[derive(Args, Debug)]
 pub struct TrayArgs {
     /// Starts without the web dashboard
@@ -103,7 +116,7 @@ pub enum Command {
     /// Adds or removes a DuckDNS domain name from the service.
     #[command(subcommand)]
     Domain(DomainSubCommands),
-    /// Sets the DuckDNS token.
+    /// Sets the DuckDNS token (or the API token of the configured provider).
     Token {
         #[arg(value_parser)]
         token: String,
@@ -122,6 +135,12 @@ pub enum Command {
     Config,
     /// Displays the time of the last successful update.
     Status,
+    /// Displays the most recent update attempts.
+    History {
+        /// Number of attempts to show.
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
     /// Checks if a newer version is available.
     CheckUpdate,
     /// Deletes all log files.
@@ -131,6 +150,12 @@ pub enum Command {
         #[arg(value_parser)]
         port: u16,
     },
+    /// Prints the secret that signs in to the dashboard API.
+    DashboardSecret {
+        /// Replaces the secret with a new one, signing out every browser.
+        #[arg(long)]
+        rotate: bool,
+    },
     /// Dynamically changes the service log level.
     #[clap(hide = true)]
     Debug {
@@ -138,6 +163,7 @@ pub enum Command {
         level: DebugLevelOption,
     },
     /// Internal entry point for the system tray icon process.
+    #[cfg(windows)]
     #[clap(hide = true)]
     Tray(TrayArgs),
 }
@@ -149,6 +175,7 @@ pub struct Cli {
     pub command: Command,
 
     /// Internal flag set when the process re-launches itself elevated.
+    #[cfg(windows)]
     #[arg(long, hide = true, global = true)]
     pub elevated: bool,
 }
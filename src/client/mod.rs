@@ -4,7 +4,9 @@ use anyhow::{Result, anyhow};
 use chrono::{DateTime, Local};
 
 use crate::common;
-use crate::common::message::{Request, Response, Token};
+use crate::common::history::{AttemptResult, HistoryEntry};
+use crate::common::message::{DomainOutcome, Request, Response, Token, UpdateStatus};
+use crate::dashboard::auth::Secret;
 
 fn expect_ok(response: Response) -> Result<()> {
     match response {
@@ -24,18 +26,21 @@ fn set_dashboard_port(port: u16) -> Result<()> {
     Ok(())
 }
 
-fn get_effective_dashboard_port() -> u16 {
+fn get_dashboard_url() -> String {
     common::config::Config::get_config_file_path()
         .ok()
         .and_then(|p| std::fs::read_to_string(p).ok())
         .and_then(|s| toml::from_str::<common::config::Config>(&s).ok())
-        .map(|c| c.effective_dashboard_port())
-        .unwrap_or(common::consts::WEB_DASHBOARD_PORT)
+        .map(|c| c.dashboard_local_url())
+        .unwrap_or_else(|| format!("http://127.0.0.1:{}", common::consts::WEB_DASHBOARD_PORT))
 }
 
-fn reload_dashboard(port: u16) {
-    let url = format!("http://127.0.0.1:{port}/api/reload");
-    let _ = minreq::post(&url).with_timeout(3).send();
+fn reload_dashboard(dashboard_url: &str, secret: &Secret) {
+    let url = format!("{dashboard_url}/api/reload");
+    let _ = minreq::post(&url)
+        .with_header("Authorization", format!("Bearer {}", secret.as_str()))
+        .with_timeout(3)
+        .send();
 }
 
 /// Sets the DuckDNS update interval on the service.
@@ -179,7 +184,7 @@ pub async fn update_debug_level(level: String) -> Result<()> {
 /// * `Ok(())` if the port was changed or the user cancelled.
 /// * `Err(e)` if writing the configuration failed.
 pub fn change_dashboard_port(port: u16) -> Result<()> {
-    let old_port = get_effective_dashboard_port();
+    let old_url = get_dashboard_url();
     loop {
         match common::prompt::yes_no_question(&format!(
             "Change the dashboard port to {port}? This will reload the dashboard."
@@ -193,11 +198,33 @@ pub fn change_dashboard_port(port: u16) -> Result<()> {
         }
     }
     set_dashboard_port(port)?;
-    reload_dashboard(old_port);
+    if let Ok(secret) = Secret::read() {
+        reload_dashboard(&old_url, &secret);
+    }
     println!("Dashboard port set to {port}.");
     Ok(())
 }
 
+/// Prints the dashboard secret, creating one if the dashboard never ran.
+///
+/// With `rotate`, the secret is replaced first and the running dashboard is
+/// reloaded so that it only accepts the new one.
+pub fn dashboard_secret(rotate: bool) -> Result<()> {
+    if !rotate {
+        println!("{}", Secret::load_or_create()?.as_str());
+        return Ok(());
+    }
+
+    let old_secret = Secret::read().ok();
+    let secret = Secret::rotate()?;
+    if let Some(old_secret) = old_secret {
+        reload_dashboard(&get_dashboard_url(), &old_secret);
+    }
+    println!("Dashboard secret rotated. Browsers have to sign in again with:");
+    println!("{}", secret.as_str());
+    Ok(())
+}
+
 /// Prints the service's current configuration to the console.
 ///
 /// Sends a request to the service to retrieve its current configuration and then
@@ -218,10 +245,11 @@ pub async fn print_configuration() -> Result<()> {
     Ok(())
 }
 
-/// Prints the time of the last successful DuckDNS update.
+/// Prints the time of the last successful DuckDNS update, along with the last
+/// attempt and its outcome for each domain.
 ///
-/// Sends a request to the service to retrieve the timestamp of the last successful
-/// DuckDNS update and prints it to the console.
+/// Sends a request to the service to retrieve the update status and prints it to
+/// the console.
 ///
 /// # Returns
 ///
@@ -230,14 +258,100 @@ pub async fn print_configuration() -> Result<()> {
 pub async fn get_last_status() -> Result<()> {
     let msg = Request::GetStatus;
     match msg.send().await? {
-        Response::Status(status) => {
-            if let Some((time, domains)) = status.last_success {
-                let datetime: DateTime<Local> = time.into();
-                let formatted_time = datetime.format("%Y-%m-%d %H:%M:%S");
-                println!("Last successful update: {formatted_time}");
-                println!("Updated domains: {}", domains.join(", "));
-            } else {
-                println!("No successful updates yet.");
+        Response::Status(status) => print!("{}", format_status(&status)),
+        Response::Err(e) => return Err(anyhow!("Bad response: {e}")),
+        _ => return Err(anyhow!("Failed to send request")),
+    }
+
+    Ok(())
+}
+
+fn format_time(time: std::time::SystemTime) -> String {
+    let datetime: DateTime<Local> = time.into();
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn format_status(status: &UpdateStatus) -> String {
+    let mut out = String::new();
+    match &status.last_success {
+        Some((time, domains)) => {
+            out += &format!("Last successful update: {}\n", format_time(*time));
+            out += &format!("Updated domains: {}\n", domains.join(", "));
+        }
+        None => out += "No successful updates yet.\n",
+    }
+
+    let Some(attempt) = status.last_attempt else {
+        return out;
+    };
+    out += &format!("Last attempt: {}\n", format_time(attempt));
+    if let Some(error) = &status.last_error {
+        out += &format!(
+            "Last error: {error} ({} consecutive failure(s))\n",
+            status.consecutive_failures
+        );
+    }
+    for (domain, outcome) in &status.domains {
+        let outcome = match outcome {
+            DomainOutcome::Updated => "updated".to_string(),
+            DomainOutcome::Unchanged => "unchanged".to_string(),
+            DomainOutcome::Failed(e) => format!("failed: {e}"),
+        };
+        out += &format!("  {domain}: {outcome}\n");
+    }
+    out
+}
+
+fn format_history_entry(entry: &HistoryEntry) -> String {
+    let addresses: Vec<String> = entry
+        .ipv4
+        .map(|a| a.to_string())
+        .into_iter()
+        .chain(entry.ipv6.map(|a| a.to_string()))
+        .collect();
+    let (status, detail) = match &entry.result {
+        AttemptResult::Published { response } => ("published", response.as_str()),
+        AttemptResult::Unchanged => ("unchanged", ""),
+        AttemptResult::Failed { error } => ("failed", error.as_str()),
+    };
+
+    let mut line = format!(
+        "{}  {status:<9}  {}  [{}]  {}  {}ms",
+        format_time(entry.time),
+        entry.provider,
+        entry.domains.join(", "),
+        if addresses.is_empty() {
+            "-".to_string()
+        } else {
+            addresses.join(", ")
+        },
+        entry.duration.as_millis()
+    );
+    if !detail.is_empty() {
+        line.push_str(&format!("  {detail}"));
+    }
+    line
+}
+
+/// Prints the most recent update attempts recorded by the service.
+///
+/// # Arguments
+///
+/// * `limit`: The maximum number of attempts to print.
+///
+/// # Returns
+///
+/// * `Ok(())` if the history was successfully retrieved and printed.
+/// * `Err(e)` if an error occurred while retrieving the history.
+pub async fn print_history(limit: usize) -> Result<()> {
+    let msg = Request::GetHistory(limit);
+    match msg.send().await? {
+        Response::History(entries) => {
+            if entries.is_empty() {
+                println!("No update attempts recorded yet.");
+            }
+            for entry in &entries {
+                println!("{}", format_history_entry(entry));
             }
         }
         Response::Err(e) => return Err(anyhow!("Bad response: {e}")),
@@ -279,7 +393,7 @@ async fn check_service_version_mismatch() {
         eprintln!(
             "Warning: version mismatch — CLI is v{cli_version} \
              but the running service is v{sv}. \
-             Reinstall the service to ensure both use the same version."
+             Commands the service does not support yet will be refused until it is reinstalled."
         );
     }
 }
@@ -314,6 +428,7 @@ pub fn clear_logs() -> Result<usize> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
 
     #[test]
     fn expect_ok_with_ok_response() {
@@ -327,6 +442,58 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("something failed"));
     }
 
+    #[test]
+    fn status_formatting_shows_failures() {
+        let mut status = UpdateStatus::default();
+        assert_eq!(format_status(&status), "No successful updates yet.\n");
+
+        let failed = DomainOutcome::Failed("timeout".to_string());
+        status.record_attempt(
+            BTreeMap::from([("home".to_string(), DomainOutcome::Updated)]),
+            std::time::SystemTime::now(),
+        );
+        status.record_attempt(
+            BTreeMap::from([
+                ("home".to_string(), failed.clone()),
+                ("work".to_string(), failed),
+            ]),
+            std::time::SystemTime::now(),
+        );
+        let text = format_status(&status);
+        assert!(text.contains("Last successful update:"));
+        assert!(text.contains("Last attempt:"));
+        assert!(text.contains("Last error: timeout (1 consecutive failure(s))"));
+        assert!(text.contains("  work: failed: timeout"));
+    }
+
+    #[test]
+    fn history_entry_formatting() {
+        let entry = HistoryEntry {
+            time: std::time::SystemTime::now(),
+            provider: "duckdns".to_string(),
+            domains: vec!["home".to_string(), "work".to_string()],
+            ipv4: Some("1.2.3.4".parse().unwrap()),
+            ipv6: None,
+            result: AttemptResult::Failed {
+                error: "DuckDNS responded with 'KO'".to_string(),
+            },
+            duration: Duration::from_millis(120),
+        };
+        let line = format_history_entry(&entry);
+        assert!(line.contains("failed"));
+        assert!(line.contains("[home, work]"));
+        assert!(line.contains("1.2.3.4"));
+        assert!(line.contains("120ms"));
+        assert!(line.ends_with("DuckDNS responded with 'KO'"));
+
+        let unchanged = HistoryEntry {
+            ipv4: None,
+            result: AttemptResult::Unchanged,
+            ..entry
+        };
+        assert!(format_history_entry(&unchanged).ends_with("-  120ms"));
+    }
+
     #[test]
     fn expect_ok_with_unexpected_response() {
         let result = expect_ok(Response::Version("1.0.0".to_string()));
@@ -1,15 +1,17 @@
-use std::io::Write;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use std::{collections::BTreeSet, fmt};
-use std::{env, fs};
 
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 
 use crate::common;
+use crate::common::files;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Token(String);
 
 impl Token {
@@ -34,24 +36,216 @@ impl fmt::Display for Token {
     }
 }
 
+/// The dynamic DNS backend the service publishes addresses to.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    DuckDns,
+    Cloudflare,
+    Rfc2136,
+    Dyndns2,
+    Webhook,
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuckDns => write!(f, "duckdns"),
+            Self::Cloudflare => write!(f, "cloudflare"),
+            Self::Rfc2136 => write!(f, "rfc2136"),
+            Self::Dyndns2 => write!(f, "dyndns2"),
+            Self::Webhook => write!(f, "webhook"),
+        }
+    }
+}
+
+impl ProviderKind {
+    /// Whether the provider authenticates with `ServiceConfig::token`. RFC 2136
+    /// signs updates with its TSIG key instead, and a webhook may not need one.
+    pub fn requires_token(self) -> bool {
+        !matches!(self, Self::Rfc2136 | Self::Webhook)
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ServiceConfig {
+    #[serde(default)]
+    pub provider: ProviderKind,
     pub token: Option<Token>,
     #[serde(default)]
     pub domain: BTreeSet<String>,
     #[serde(with = "humantime_serde")]
     pub interval: Duration,
+    /// Addresses are republished after this long even if they did not change.
+    #[serde(with = "humantime_serde", default = "default_refresh_interval")]
+    pub refresh_interval: Duration,
+    /// Failed updates are retried with exponential backoff up to this delay.
+    #[serde(with = "humantime_serde", default = "default_max_retry_delay")]
+    pub max_retry_delay: Duration,
+    /// Adding a domain is refused once this many are configured.
+    #[serde(default = "default_max_domains")]
+    pub max_domains: usize,
+    /// The most domains sent to the provider in a single call. Larger groups
+    /// are split into several calls that succeed or fail on their own.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// IPv4 updates are enabled unless this is set to `false`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<bool>,
     pub ipv6: Option<bool>,
     #[serde(default = "default_log_level")]
     pub log_level: String,
     #[serde(skip, default)]
     pub clear_ip_addresses: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloudflare: Option<CloudflareConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rfc2136: Option<Rfc2136Config>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dyndns2: Option<Dyndns2Config>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<WebhookConfig>,
+}
+
+/// Settings for the Cloudflare provider. The API token is taken from
+/// `ServiceConfig::token` and each configured domain is a record name.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CloudflareConfig {
+    pub zone_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsTransport {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    #[default]
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+    #[serde(rename = "hmac-sha512")]
+    HmacSha512,
+}
+
+/// A TSIG key as produced by `tsig-keygen`. The secret is the base64 encoded key
+/// material and is masked the same way as the provider token.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TsigKey {
+    pub name: String,
+    #[serde(default)]
+    pub algorithm: TsigAlgorithm,
+    pub secret: Token,
+}
+
+/// Settings for the RFC 2136 (DNS UPDATE) provider. Each configured domain is
+/// a fully qualified name inside `zone`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Rfc2136Config {
+    pub server: String,
+    #[serde(default = "default_dns_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub transport: DnsTransport,
+    pub zone: String,
+    #[serde(default = "default_record_ttl")]
+    pub ttl: u32,
+    pub tsig: Option<TsigKey>,
+}
+
+/// Settings for the dyndns2 provider (No-IP, Dynu and other ddclient-compatible
+/// servers). The password is taken from `ServiceConfig::token`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Dyndns2Config {
+    pub server: String,
+    pub username: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HttpMethod {
+    #[default]
+    Get,
+    Post,
+    Put,
+    Patch,
+}
+
+/// Settings for the webhook provider, which calls a user defined endpoint.
+///
+/// `url`, `body` and header values are templates where `{domain}`, `{domains}`,
+/// `{ipv4}`, `{ipv6}` and `{token}` are substituted before sending. When `success`
+/// is set, the response body must match it for the update to count as successful.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub method: HttpMethod,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<String>,
+}
+
+fn default_dns_port() -> u16 {
+    53
+}
+
+fn default_record_ttl() -> u32 {
+    300
+}
+
+fn default_refresh_interval() -> Duration {
+    common::consts::DEFAULT_REFRESH_INTERVAL
+}
+
+fn default_max_retry_delay() -> Duration {
+    common::consts::DEFAULT_MAX_RETRY_DELAY
+}
+
+fn default_max_domains() -> usize {
+    common::consts::DEFAULT_MAX_DOMAINS
+}
+
+fn default_batch_size() -> usize {
+    common::consts::DEFAULT_BATCH_SIZE
 }
 
 fn default_log_level() -> String {
     "info".to_string()
 }
 
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            provider: ProviderKind::default(),
+            token: None,
+            domain: BTreeSet::new(),
+            interval: Duration::from_secs(24 * 60 * 60),
+            refresh_interval: default_refresh_interval(),
+            max_retry_delay: default_max_retry_delay(),
+            max_domains: default_max_domains(),
+            batch_size: default_batch_size(),
+            ipv4: None,
+            ipv6: None,
+            log_level: default_log_level(),
+            clear_ip_addresses: false,
+            cloudflare: None,
+            rfc2136: None,
+            dyndns2: None,
+            webhook: None,
+        }
+    }
+}
+
 impl ServiceConfig {
     pub fn domains_csv(&self) -> String {
         self.domain
@@ -65,10 +259,12 @@ impl ServiceConfig {
     /// Only use this for direct display to an authenticated, privileged user.
     pub fn to_string_with_token(&self) -> String {
         format!(
-            "token: {}\ndomains: {}\ninterval: {}\nipv6: {}",
+            "provider: {}\ntoken: {}\ndomains: {}\ninterval: {}\nrefresh interval: {}\nipv6: {}",
+            self.provider,
             self.token.as_ref().map_or("<not set>", |t| t.as_str()),
             self.domains_csv(),
             humantime::format_duration(self.interval),
+            humantime::format_duration(self.refresh_interval),
             if self.ipv6 == Some(true) {
                 "enabled"
             } else {
@@ -82,12 +278,14 @@ impl fmt::Display for ServiceConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "token: {}\ndomains: {}\ninterval: {}\nipv6: {}",
+            "provider: {}\ntoken: {}\ndomains: {}\ninterval: {}\nrefresh interval: {}\nipv6: {}",
+            self.provider,
             self.token
                 .as_ref()
                 .map_or("<not set>".to_string(), |t| t.to_string()),
             self.domains_csv(),
             humantime::format_duration(self.interval),
+            humantime::format_duration(self.refresh_interval),
             if self.ipv6 == Some(true) {
                 "enabled"
             } else {
@@ -97,23 +295,162 @@ impl fmt::Display for ServiceConfig {
     }
 }
 
+/// A `[[domains]]` entry. Settings that are left out fall back to the
+/// `[service]` section, so an entry with only a `name` behaves like a name
+/// listed in `service.domain`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DomainConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<ProviderKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Token>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<bool>,
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub interval: Option<Duration>,
+}
+
+/// A domain with every setting resolved against the `[service]` section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedDomain {
+    pub name: String,
+    pub provider: ProviderKind,
+    pub token: Option<Token>,
+    pub ipv4: bool,
+    pub ipv6: bool,
+    pub interval: Duration,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
 pub struct DashboardConfig {
     #[serde(default, skip_serializing_if = "is_false")]
     pub disabled: bool,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub port: Option<u16>,
+    /// The address the dashboard listens on. Any address other than a
+    /// loopback address exposes it to the network, and it is then served
+    /// over HTTPS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind: Option<IpAddr>,
+    /// Host names the dashboard is reached by on the network. Other names
+    /// are rejected, except for `localhost` and plain IP addresses.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    /// PEM certificate chain and private key served on a network address.
+    /// Without them, a self-signed certificate is generated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+}
+
+impl DashboardConfig {
+    pub fn bind_address(&self) -> IpAddr {
+        self.bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    }
+
+    /// Whether the dashboard is reachable from other machines, in which case
+    /// it is served over HTTPS.
+    pub fn is_exposed(&self) -> bool {
+        !self.bind_address().is_loopback()
+    }
+
+    /// The address this machine reaches the dashboard on.
+    pub fn local_address(&self) -> IpAddr {
+        match self.bind_address() {
+            IpAddr::V4(address) if address.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(address) if address.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            address => address,
+        }
+    }
 }
 
 fn is_false(v: &bool) -> bool {
     !*v
 }
 
+/// Where the public addresses are looked up. Sources are queried in order
+/// until `require_agreement` of them returned the same address. When no
+/// source is configured for an address family, the built-in resolvers are used.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct IpDiscoveryConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ipv4: Vec<IpSource>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ipv6: Vec<IpSource>,
+    #[serde(with = "humantime_serde", default = "default_source_timeout")]
+    pub timeout: Duration,
+    #[serde(default = "default_require_agreement")]
+    pub require_agreement: usize,
+}
+
+impl Default for IpDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            ipv4: Vec::new(),
+            ipv6: Vec::new(),
+            timeout: default_source_timeout(),
+            require_agreement: default_require_agreement(),
+        }
+    }
+}
+
+/// A single public address source. `timeout` overrides the section default.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IpSource {
+    /// An echo service that returns the caller's address as plain text.
+    Http {
+        url: String,
+        #[serde(
+            default,
+            with = "humantime_serde",
+            skip_serializing_if = "Option::is_none"
+        )]
+        timeout: Option<Duration>,
+    },
+    /// A resolver that answers an A/AAAA query for `name` with the caller's
+    /// address, e.g. `myip.opendns.com` at `resolver1.opendns.com`.
+    Dns {
+        server: String,
+        name: String,
+        #[serde(
+            default,
+            with = "humantime_serde",
+            skip_serializing_if = "Option::is_none"
+        )]
+        timeout: Option<Duration>,
+    },
+    /// The address assigned to a local interface, for hosts with a directly
+    /// assigned public address. Link-local, private, ULA and temporary
+    /// addresses are skipped.
+    Interface { name: String },
+}
+
+fn default_source_timeout() -> Duration {
+    common::consts::IP_SOURCE_TIMEOUT
+}
+
+fn default_require_agreement() -> usize {
+    1
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub service: ServiceConfig,
     #[serde(default)]
     pub dashboard: Option<DashboardConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_discovery: Option<IpDiscoveryConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<DomainConfig>,
 }
 
 impl fmt::Display for Config {
@@ -121,7 +458,10 @@ impl fmt::Display for Config {
         write!(
             f,
             "domains: {}\ninterval: {}\nipv6: {}",
-            self.service.domains_csv(),
+            self.domain_names()
+                .into_iter()
+                .collect::<Vec<String>>()
+                .join(","),
             humantime::format_duration(self.service.interval),
             if self.service.ipv6 == Some(true) {
                 "enabled"
@@ -140,12 +480,142 @@ impl Config {
             .unwrap_or(crate::common::consts::WEB_DASHBOARD_PORT)
     }
 
+    /// The URL this machine opens the dashboard at. Connections from this
+    /// machine may use plain HTTP even when the dashboard is exposed.
+    pub fn dashboard_local_url(&self) -> String {
+        let address = self
+            .dashboard
+            .as_ref()
+            .map_or(IpAddr::V4(Ipv4Addr::LOCALHOST), |d| d.local_address());
+        let address = std::net::SocketAddr::new(address, self.effective_dashboard_port());
+        format!("http://{address}")
+    }
+
+    /// Every configured domain, sorted by name. The names in `service.domain`
+    /// take all their settings from `[service]`, and a `[[domains]]` entry
+    /// overrides a name it repeats.
+    pub fn resolved_domains(&self) -> Vec<ResolvedDomain> {
+        let service = &self.service;
+        let mut resolved: BTreeMap<String, ResolvedDomain> = service
+            .domain
+            .iter()
+            .map(|name| {
+                let domain = ResolvedDomain {
+                    name: name.clone(),
+                    provider: service.provider,
+                    token: service.token.clone(),
+                    ipv4: service.ipv4 != Some(false),
+                    ipv6: service.ipv6 == Some(true),
+                    interval: service.interval,
+                };
+                (name.clone(), domain)
+            })
+            .collect();
+
+        for entry in &self.domains {
+            let domain = ResolvedDomain {
+                name: entry.name.clone(),
+                provider: entry.provider.unwrap_or(service.provider),
+                token: entry.token.clone().or_else(|| service.token.clone()),
+                ipv4: entry.ipv4.unwrap_or(service.ipv4 != Some(false)),
+                ipv6: entry.ipv6.unwrap_or(service.ipv6 == Some(true)),
+                interval: entry.interval.unwrap_or(service.interval),
+            };
+            resolved.insert(entry.name.clone(), domain);
+        }
+
+        resolved.into_values().collect()
+    }
+
+    /// The names of every configured domain.
+    pub fn domain_names(&self) -> BTreeSet<String> {
+        self.service
+            .domain
+            .iter()
+            .chain(self.domains.iter().map(|d| &d.name))
+            .cloned()
+            .collect()
+    }
+
+    /// Groups `domains` by provider, token and address families, so that each
+    /// group is published with a single provider call, and splits groups larger
+    /// than `batch_size`. Each group is the `[service]` section with those
+    /// settings and its domain names filled in.
+    pub fn group_domains<'a>(
+        &self,
+        domains: impl IntoIterator<Item = &'a ResolvedDomain>,
+    ) -> Vec<ServiceConfig> {
+        let mut groups: BTreeMap<_, ServiceConfig> = BTreeMap::new();
+
+        for domain in domains {
+            let key = (
+                domain.provider,
+                domain.token.as_ref().map(|t| t.as_str().to_string()),
+                domain.ipv4,
+                domain.ipv6,
+            );
+            groups
+                .entry(key)
+                .or_insert_with(|| ServiceConfig {
+                    provider: domain.provider,
+                    token: domain.token.clone(),
+                    domain: BTreeSet::new(),
+                    ipv4: Some(domain.ipv4),
+                    ipv6: Some(domain.ipv6),
+                    ..self.service.clone()
+                })
+                .domain
+                .insert(domain.name.clone());
+        }
+
+        let batch_size = self.service.batch_size.max(1);
+        groups
+            .into_values()
+            .flat_map(|group| {
+                let names: Vec<String> = group.domain.iter().cloned().collect();
+                names
+                    .chunks(batch_size)
+                    .map(|batch| ServiceConfig {
+                        domain: batch.iter().cloned().collect(),
+                        ..group.clone()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Whether there is anything to publish: at least one domain with a token,
+    /// or whose provider needs none. Domains missing a token fail on their own
+    /// without holding back the others.
+    pub fn is_ready(&self) -> bool {
+        self.resolved_domains()
+            .iter()
+            .any(|d| d.token.is_some() || !d.provider.requires_token())
+    }
+
+    /// How often the update loop checks for changes, which is the shortest
+    /// interval of any domain.
+    pub fn update_interval(&self) -> Duration {
+        self.domains
+            .iter()
+            .filter_map(|d| d.interval)
+            .fold(self.service.interval, Duration::min)
+    }
+
+    #[cfg(windows)]
     fn get_programdata_path() -> Result<PathBuf> {
-        env::var("ProgramData")
+        std::env::var("ProgramData")
             .map(PathBuf::from)
             .map_err(|e| anyhow!("Failed to get ProgramData environment variable: {e}"))
     }
 
+    /// The machine-wide state directory, which holds the configuration as well
+    /// as the logs, like `%ProgramData%` does on Windows.
+    #[cfg(unix)]
+    fn get_programdata_path() -> Result<PathBuf> {
+        Ok(PathBuf::from("/var/lib"))
+    }
+
     pub fn get_config_directory_path() -> Result<PathBuf> {
         let mut path = Self::get_programdata_path()?;
         path.push(common::strings::CONFIG_DIR);
@@ -160,20 +630,13 @@ impl Config {
     }
 
     pub fn store(&self) -> Result<()> {
-        let config_file_path = Self::get_config_file_path()?;
-
-        // write the current configuration to disk, creating the file if needed
-        let mut config_file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&config_file_path)?;
-
-        config_file
-            .write_all(toml::to_string_pretty(self)?.as_bytes())
-            .map_err(|e| anyhow!("Failed to write config file: {e}"))?;
+        self.store_at(&Self::get_config_file_path()?)
+    }
 
-        Ok(())
+    /// Writes the configuration to `path`, readable only by its owner since it
+    /// holds the tokens.
+    fn store_at(&self, path: &Path) -> Result<()> {
+        files::write_private_file(path, toml::to_string_pretty(self)?.as_bytes())
     }
 
     pub fn read() -> Result<Self> {
@@ -196,17 +659,23 @@ impl Config {
     }
 
     fn clamp_interval(config: &mut Config) {
-        if config.service.interval < common::consts::MINIMAL_INTERVAL {
-            let min_human_time = humantime::format_duration(common::consts::MINIMAL_INTERVAL);
-            let interval_human_time = humantime::format_duration(config.service.interval);
-
-            log::info!(
-                "Interval must be at least {}, got {}, using {}",
-                min_human_time,
-                interval_human_time,
-                min_human_time
-            );
-            config.service.interval = common::consts::MINIMAL_INTERVAL;
+        let domain_intervals = config
+            .domains
+            .iter_mut()
+            .filter_map(|d| d.interval.as_mut());
+        for interval in std::iter::once(&mut config.service.interval).chain(domain_intervals) {
+            if *interval < common::consts::MINIMAL_INTERVAL {
+                let min_human_time = humantime::format_duration(common::consts::MINIMAL_INTERVAL);
+                let interval_human_time = humantime::format_duration(*interval);
+
+                log::info!(
+                    "Interval must be at least {}, got {}, using {}",
+                    min_human_time,
+                    interval_human_time,
+                    min_human_time
+                );
+                *interval = common::consts::MINIMAL_INTERVAL;
+            }
         }
     }
 
@@ -234,12 +703,22 @@ mod tests {
         ipv6: Option<bool>,
     ) -> ServiceConfig {
         ServiceConfig {
+            provider: ProviderKind::default(),
             token: token.map(|t| Token::new(t.to_string())),
             domain: domains.iter().map(|d| d.to_string()).collect(),
             interval: Duration::from_secs(interval_secs),
+            refresh_interval: default_refresh_interval(),
+            max_retry_delay: default_max_retry_delay(),
+            max_domains: default_max_domains(),
+            batch_size: default_batch_size(),
+            ipv4: None,
             ipv6,
             log_level: "info".to_string(),
             clear_ip_addresses: false,
+            cloudflare: None,
+            rfc2136: None,
+            dyndns2: None,
+            webhook: None,
         }
     }
 
@@ -247,6 +726,8 @@ mod tests {
         Config {
             service: make_service_config(None, &[], 60, None),
             dashboard: None,
+            ip_discovery: None,
+            domains: Vec::new(),
         }
     }
 
@@ -338,6 +819,7 @@ mod tests {
         config.dashboard = Some(DashboardConfig {
             disabled: false,
             port: None,
+            ..Default::default()
         });
         assert_eq!(
             config.effective_dashboard_port(),
@@ -351,10 +833,53 @@ mod tests {
         config.dashboard = Some(DashboardConfig {
             disabled: false,
             port: Some(9999),
+            ..Default::default()
         });
         assert_eq!(config.effective_dashboard_port(), 9999);
     }
 
+    #[test]
+    fn dashboard_binds_loopback_by_default() {
+        let config = make_config();
+        assert_eq!(config.dashboard_local_url(), "http://127.0.0.1:18733");
+        assert!(!DashboardConfig::default().is_exposed());
+    }
+
+    #[test]
+    fn dashboard_bind_address_deserialized_when_present() {
+        let toml_str = r#"
+[service]
+interval = "1 day"
+
+[dashboard]
+bind = "0.0.0.0"
+hosts = ["nas.lan"]
+tls_cert = "/etc/barvazdns/cert.pem"
+tls_key = "/etc/barvazdns/key.pem"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let dashboard = config.dashboard.as_ref().unwrap();
+        assert!(dashboard.is_exposed());
+        assert_eq!(dashboard.hosts, ["nas.lan"]);
+        assert_eq!(
+            dashboard.tls_key.as_deref(),
+            Some(std::path::Path::new("/etc/barvazdns/key.pem"))
+        );
+        // this machine still reaches an unspecified address over loopback
+        assert_eq!(config.dashboard_local_url(), "http://127.0.0.1:18733");
+    }
+
+    #[test]
+    fn dashboard_local_url_uses_specific_bind_address() {
+        let mut config = make_config();
+        config.dashboard = Some(DashboardConfig {
+            bind: Some("fd00::10".parse().unwrap()),
+            port: Some(9000),
+            ..Default::default()
+        });
+        assert_eq!(config.dashboard_local_url(), "http://[fd00::10]:9000");
+    }
+
     #[test]
     fn dashboard_not_serialized_when_none() {
         let config = make_config();
@@ -377,6 +902,448 @@ port = 8080
         assert_eq!(config.effective_dashboard_port(), 8080);
     }
 
+    #[test]
+    fn provider_defaults_to_duckdns() {
+        let toml_str = r#"
+[service]
+interval = "1 day"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.provider, ProviderKind::DuckDns);
+    }
+
+    #[test]
+    fn provider_deserialized_when_present() {
+        let toml_str = r#"
+[service]
+provider = "duckdns"
+interval = "1 day"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.provider, ProviderKind::DuckDns);
+    }
+
+    #[test]
+    fn cloudflare_section_deserialized() {
+        let toml_str = r#"
+[service]
+provider = "cloudflare"
+token = "cf-token"
+domain = ["home.example.com"]
+interval = "1 day"
+
+[service.cloudflare]
+zone_id = "023e105f4ecef8ad9ca31a8372d0c353"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.provider, ProviderKind::Cloudflare);
+        let cloudflare = config.service.cloudflare.unwrap();
+        assert_eq!(cloudflare.zone_id, "023e105f4ecef8ad9ca31a8372d0c353");
+        assert!(cloudflare.api_url.is_none());
+    }
+
+    #[test]
+    fn cloudflare_section_roundtrips() {
+        let mut config = make_config();
+        config.service.provider = ProviderKind::Cloudflare;
+        config.service.cloudflare = Some(CloudflareConfig {
+            zone_id: "zone".to_string(),
+            api_url: Some("http://127.0.0.1:8080".to_string()),
+        });
+        let serialized = toml::to_string_pretty(&config).unwrap();
+        let parsed: Config = toml::from_str(&serialized).unwrap();
+        let cloudflare = parsed.service.cloudflare.unwrap();
+        assert_eq!(cloudflare.zone_id, "zone");
+        assert_eq!(cloudflare.api_url.as_deref(), Some("http://127.0.0.1:8080"));
+    }
+
+    #[test]
+    fn rfc2136_section_deserialized_with_defaults() {
+        let toml_str = r#"
+[service]
+provider = "rfc2136"
+domain = ["host.example.com"]
+interval = "1 day"
+
+[service.rfc2136]
+server = "192.0.2.53"
+zone = "example.com"
+
+[service.rfc2136.tsig]
+name = "ddns-key"
+secret = "c2VjcmV0"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.provider, ProviderKind::Rfc2136);
+        let rfc2136 = config.service.rfc2136.unwrap();
+        assert_eq!(rfc2136.port, 53);
+        assert_eq!(rfc2136.ttl, 300);
+        assert_eq!(rfc2136.transport, DnsTransport::Udp);
+        let tsig = rfc2136.tsig.unwrap();
+        assert_eq!(tsig.algorithm, TsigAlgorithm::HmacSha256);
+        assert_eq!(tsig.secret.as_str(), "c2VjcmV0");
+    }
+
+    #[test]
+    fn refresh_interval_defaults_and_parses() {
+        let toml_str = r#"
+[service]
+interval = "1 day"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.service.refresh_interval,
+            common::consts::DEFAULT_REFRESH_INTERVAL
+        );
+
+        let toml_str = r#"
+[service]
+interval = "1 day"
+refresh_interval = "12h"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.service.refresh_interval,
+            Duration::from_secs(12 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn ip_discovery_section_deserialized() {
+        let toml_str = r#"
+[service]
+interval = "1 day"
+
+[ip_discovery]
+require_agreement = 2
+ipv4 = [
+    { type = "http", url = "https://api.ipify.org", timeout = "3s" },
+    { type = "dns", server = "resolver1.opendns.com", name = "myip.opendns.com" },
+]
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let discovery = config.ip_discovery.unwrap();
+        assert_eq!(discovery.require_agreement, 2);
+        assert_eq!(discovery.timeout, common::consts::IP_SOURCE_TIMEOUT);
+        assert!(discovery.ipv6.is_empty());
+        assert_eq!(
+            discovery.ipv4,
+            vec![
+                IpSource::Http {
+                    url: "https://api.ipify.org".to_string(),
+                    timeout: Some(Duration::from_secs(3)),
+                },
+                IpSource::Dns {
+                    server: "resolver1.opendns.com".to_string(),
+                    name: "myip.opendns.com".to_string(),
+                    timeout: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn interface_source_deserialized() {
+        let toml_str = r#"
+[service]
+interval = "1 day"
+
+[[ip_discovery.ipv6]]
+type = "interface"
+name = "Ethernet"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            config.ip_discovery.unwrap().ipv6,
+            vec![IpSource::Interface {
+                name: "Ethernet".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn ip_discovery_section_is_optional() {
+        let config: Config = toml::from_str(common::strings::DEFAULT_CONFIG_CONTENT).unwrap();
+        assert!(config.ip_discovery.is_none());
+    }
+
+    #[test]
+    fn max_retry_delay_defaults_and_parses() {
+        let config: Config = toml::from_str(common::strings::DEFAULT_CONFIG_CONTENT).unwrap();
+        assert_eq!(
+            config.service.max_retry_delay,
+            common::consts::DEFAULT_MAX_RETRY_DELAY
+        );
+
+        let toml_str = r#"
+[service]
+interval = "1 day"
+max_retry_delay = "10m"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.max_retry_delay, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn dyndns2_section_deserialized() {
+        let toml_str = r#"
+[service]
+provider = "dyndns2"
+token = "password"
+domain = ["home.ddns.net"]
+interval = "1 day"
+
+[service.dyndns2]
+server = "https://dynupdate.no-ip.com"
+username = "user"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.provider, ProviderKind::Dyndns2);
+        let dyndns2 = config.service.dyndns2.unwrap();
+        assert_eq!(dyndns2.server, "https://dynupdate.no-ip.com");
+        assert_eq!(dyndns2.username, "user");
+    }
+
+    #[test]
+    fn webhook_section_deserialized() {
+        let toml_str = r#"
+[service]
+provider = "webhook"
+domain = ["home.example.com"]
+interval = "1 day"
+
+[service.webhook]
+url = "https://example.com/update?host={domain}&ip={ipv4}"
+method = "POST"
+success = "^OK"
+
+[service.webhook.headers]
+Authorization = "Bearer {token}"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.provider, ProviderKind::Webhook);
+        let webhook = config.service.webhook.unwrap();
+        assert_eq!(webhook.method, HttpMethod::Post);
+        assert_eq!(webhook.headers["Authorization"], "Bearer {token}");
+        assert_eq!(webhook.success.as_deref(), Some("^OK"));
+        assert!(webhook.body.is_none());
+    }
+
+    #[test]
+    fn webhook_method_defaults_to_get() {
+        let webhook: WebhookConfig = toml::from_str(r#"url = "https://example.com""#).unwrap();
+        assert_eq!(webhook.method, HttpMethod::Get);
+        assert!(webhook.headers.is_empty());
+    }
+
+    #[test]
+    fn tsig_secret_is_masked() {
+        let key = TsigKey {
+            name: "ddns-key".to_string(),
+            algorithm: TsigAlgorithm::HmacSha512,
+            secret: Token::new("c2VjcmV0".to_string()),
+        };
+        let debug = format!("{key:?}");
+        assert!(!debug.contains("c2VjcmV0"));
+        assert!(debug.contains("***"));
+    }
+
+    #[test]
+    fn unknown_provider_is_rejected() {
+        let toml_str = r#"
+[service]
+provider = "nope"
+interval = "1 day"
+"#;
+        assert!(toml::from_str::<Config>(toml_str).is_err());
+    }
+
+    const DOMAINS_CONFIG: &str = r#"
+[service]
+token = "shared"
+domain = ["home", "office"]
+interval = "1h"
+ipv6 = true
+
+[[domains]]
+name = "office"
+token = "work"
+
+[[domains]]
+name = "lab"
+token = "work"
+ipv6 = false
+interval = "10m"
+
+[[domains]]
+name = "nas"
+token = "work"
+"#;
+
+    #[test]
+    fn domain_entries_fall_back_to_service_settings() {
+        let config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        let domains = config.resolved_domains();
+        let names: Vec<&str> = domains.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["home", "lab", "nas", "office"]);
+
+        let home = &domains[0];
+        assert_eq!(home.token.as_ref().unwrap().as_str(), "shared");
+        assert!(home.ipv4 && home.ipv6);
+        assert_eq!(home.interval, Duration::from_secs(3600));
+
+        let lab = &domains[1];
+        assert_eq!(lab.token.as_ref().unwrap().as_str(), "work");
+        assert_eq!(lab.provider, ProviderKind::DuckDns);
+        assert!(lab.ipv4 && !lab.ipv6);
+        assert_eq!(lab.interval, Duration::from_secs(600));
+
+        // The entry overrides the name listed in `service.domain`.
+        let office = &domains[3];
+        assert_eq!(office.token.as_ref().unwrap().as_str(), "work");
+    }
+
+    #[test]
+    fn domains_sharing_settings_are_grouped() {
+        let config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        let groups = config.group_domains(&config.resolved_domains());
+        let grouped: Vec<(String, Option<bool>)> =
+            groups.iter().map(|g| (g.domains_csv(), g.ipv6)).collect();
+        assert_eq!(
+            grouped,
+            vec![
+                ("home".to_string(), Some(true)),
+                ("lab".to_string(), Some(false)),
+                ("nas,office".to_string(), Some(true)),
+            ]
+        );
+        assert_eq!(groups[2].token.as_ref().unwrap().as_str(), "work");
+        assert_eq!(groups[2].interval, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn large_groups_are_split_into_batches() {
+        let mut config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        config.service.domain = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        config.domains.clear();
+        config.service.batch_size = 2;
+
+        let batches: Vec<String> = config
+            .group_domains(&config.resolved_domains())
+            .iter()
+            .map(|g| g.domains_csv())
+            .collect();
+        assert_eq!(batches, vec!["a,b", "c,d", "e"]);
+
+        // A zero batch size still sends every domain.
+        config.service.batch_size = 0;
+        assert_eq!(config.group_domains(&config.resolved_domains()).len(), 5);
+    }
+
+    #[test]
+    fn domain_limit_and_batch_size_default_and_parse() {
+        let config: Config = toml::from_str(common::strings::DEFAULT_CONFIG_CONTENT).unwrap();
+        assert_eq!(
+            config.service.max_domains,
+            common::consts::DEFAULT_MAX_DOMAINS
+        );
+        assert_eq!(
+            config.service.batch_size,
+            common::consts::DEFAULT_BATCH_SIZE
+        );
+
+        let toml_str = r#"
+[service]
+interval = "1 day"
+max_domains = 20
+batch_size = 10
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.service.max_domains, 20);
+        assert_eq!(config.service.batch_size, 10);
+    }
+
+    #[test]
+    fn update_interval_is_the_shortest_domain_interval() {
+        let mut config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        assert_eq!(config.update_interval(), Duration::from_secs(600));
+
+        config.domains.clear();
+        assert_eq!(config.update_interval(), Duration::from_secs(3600));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stored_config_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path =
+            std::env::temp_dir().join(format!("barvazdns-{}-config.toml", std::process::id()));
+        let config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        config.store_at(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn readiness_requires_a_domain_with_a_token() {
+        let mut config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        assert!(config.is_ready());
+
+        // The `[[domains]]` entries still have their own token.
+        config.service.token = None;
+        assert!(config.is_ready());
+
+        config.domains.clear();
+        assert!(!config.is_ready());
+
+        config.service.token = Some(Token::new("shared".to_string()));
+        config.service.domain.clear();
+        assert!(!config.is_ready());
+    }
+
+    #[test]
+    fn readiness_ignores_the_token_when_the_provider_needs_none() {
+        let mut config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        config.domains.clear();
+        config.service.token = None;
+        assert!(!config.is_ready());
+
+        config.service.provider = ProviderKind::Rfc2136;
+        assert!(config.is_ready());
+
+        config.service.provider = ProviderKind::Webhook;
+        assert!(config.is_ready());
+    }
+
+    #[test]
+    fn clamp_interval_applies_to_domain_entries() {
+        let mut config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        config.domains[1].interval = Some(Duration::from_secs(1));
+        Config::clamp_interval(&mut config);
+        assert_eq!(
+            config.domains[1].interval,
+            Some(crate::common::consts::MINIMAL_INTERVAL)
+        );
+    }
+
+    #[test]
+    fn domain_entries_not_serialized_when_empty() {
+        let config = make_config();
+        let serialized = toml::to_string_pretty(&config).unwrap();
+        assert!(!serialized.contains("[[domains]]"));
+        assert!(!serialized.contains("ipv4"));
+
+        let config: Config = toml::from_str(DOMAINS_CONFIG).unwrap();
+        let serialized = toml::to_string_pretty(&config).unwrap();
+        let parsed: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(parsed.resolved_domains(), config.resolved_domains());
+    }
+
     #[test]
     fn dashboard_port_defaults_when_section_absent() {
         let toml_str = r#"
@@ -1,15 +1,39 @@
 use std::time::Duration;
 
-pub const MAX_DOMAIN_COUNT: usize = 5;
-pub const PIPE_BUFFER_SIZE: usize = 4096;
+pub const DEFAULT_MAX_DOMAINS: usize = 5;
+pub const DEFAULT_BATCH_SIZE: usize = 5;
+pub const HISTORY_CAPACITY: usize = 200;
 pub const PIPE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Events a subscribed client may fall behind by before it misses some.
+pub const EVENT_BACKLOG: usize = 256;
+/// Frames beyond this are rejected before their payload is read.
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024; // 1MB
 pub const MINIMAL_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+pub const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
 pub const MAX_STARTUP_BOOT_DELAY: Duration = Duration::from_secs(30);
 pub const LOG_ROTATION_SIZE: u64 = 5 * 1024 * 1024; // 5MB
 pub const LOG_KEEP_FILES: usize = 5;
+#[cfg(windows)]
 pub const SERVICE_POLL_TIMEOUT: Duration = Duration::from_secs(5);
-pub const TRAY_POLL_INTERVAL_MS: u32 = 5000;
+/// Updates run on a blocking thread while the update loop keeps pinging the
+/// watchdog, so only a loop that stopped being scheduled gets restarted.
+#[cfg(unix)]
+pub const SYSTEMD_WATCHDOG_SEC: Duration = Duration::from_secs(5 * 60);
+/// How long the tray waits before subscribing again to a service that is
+/// not running.
+#[cfg(windows)]
+pub const TRAY_RECONNECT_DELAY: Duration = Duration::from_secs(5);
 pub const LATEST_RELEASE_URL: &str =
     "https://api.github.com/repos/acamol/BarvazDNS/releases/latest";
 pub const RELEASES_PAGE_URL: &str = "https://github.com/Acamol/BarvazDNS/releases";
+pub const HTTP_TIMEOUT_SECS: u64 = 10;
+pub const IP_SOURCE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DUCKDNS_UPDATE_URL: &str = "https://www.duckdns.org/update";
+pub const CLOUDFLARE_API_URL: &str = "https://api.cloudflare.com/client/v4";
 pub const WEB_DASHBOARD_PORT: u16 = 18733;
+pub const DASHBOARD_SESSION_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+pub const DASHBOARD_LOGIN_TICKET_LIFETIME: Duration = Duration::from_secs(2 * 60);
+pub const DASHBOARD_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
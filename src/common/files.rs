@@ -0,0 +1,200 @@
+//! Writing files in the configuration directory without ever leaving a
+//! partly written file behind.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+
+#[cfg(unix)]
+const PRIVATE_FILE_MODE: u32 = 0o600;
+#[cfg(unix)]
+const PRIVATE_DIRECTORY_MODE: u32 = 0o700;
+
+/// Protected DACL granting full access to Local System, which the service runs
+/// as, and to elevated Administrators, which the CLI needs to be and the tray
+/// icon is when started by its logon task. Other accounts, and Administrators
+/// running without elevation, get no access, and nothing is inherited from the
+/// configuration directory.
+#[cfg(windows)]
+const PRIVATE_FILE_SDDL: &str = "D:P(A;;FA;;;SY)(A;;FA;;;BA)";
+
+/// The sibling of `path` that its new contents are written to first.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over
+/// `path`, so that a reader or a crash never sees a truncated file.
+fn replace(path: &Path, contents: &[u8], private: bool) -> Result<()> {
+    let temporary = temporary_path(path);
+    let _ = fs::remove_file(&temporary);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(PRIVATE_FILE_MODE);
+    }
+    #[cfg(windows)]
+    if private {
+        use std::os::windows::fs::OpenOptionsExt;
+        // Nobody else can open the file before its DACL is in place.
+        options.share_mode(0);
+    }
+
+    let result = options
+        .open(&temporary)
+        .and_then(|mut file| {
+            #[cfg(windows)]
+            if private {
+                restrict_to_administrators(&temporary)?;
+            }
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result.map_err(|e| anyhow!("Failed to write {path:?}: {e}"))
+}
+
+/// Replaces the contents of `path`.
+pub fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    replace(path, contents, false)
+}
+
+/// Replaces the contents of `path` with a file that only the account the
+/// service runs as may read, and on Windows also Administrators.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    replace(path, contents, true)
+}
+
+/// Creates the directory `path` if needed. On Unix, only its owner may enter
+/// it, which also covers a directory created by an older version.
+pub fn create_private_directory(path: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        builder.mode(PRIVATE_DIRECTORY_MODE);
+        builder
+            .create(path)
+            .and_then(|()| {
+                fs::set_permissions(path, fs::Permissions::from_mode(PRIVATE_DIRECTORY_MODE))
+            })
+            .map_err(|e| anyhow!("Failed to create {path:?}: {e}"))
+    }
+    #[cfg(windows)]
+    builder
+        .create(path)
+        .map_err(|e| anyhow!("Failed to create {path:?}: {e}"))
+}
+
+/// Replaces the DACL of `path` with [`PRIVATE_FILE_SDDL`].
+#[cfg(windows)]
+fn restrict_to_administrators(path: &Path) -> std::io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SE_FILE_OBJECT, SetNamedSecurityInfoW,
+    };
+    use windows_sys::Win32::Security::{
+        DACL_SECURITY_INFORMATION, GetSecurityDescriptorDacl, PROTECTED_DACL_SECURITY_INFORMATION,
+    };
+
+    let sddl: Vec<u16> = PRIVATE_FILE_SDDL.encode_utf16().chain([0]).collect();
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+
+    let mut sd = std::ptr::null_mut();
+    if unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            1,
+            &mut sd,
+            std::ptr::null_mut(),
+        )
+    } == 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut present = 0;
+    let mut defaulted = 0;
+    let mut dacl = std::ptr::null_mut();
+    let result =
+        if unsafe { GetSecurityDescriptorDacl(sd, &mut present, &mut dacl, &mut defaulted) } == 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            match unsafe {
+                SetNamedSecurityInfoW(
+                    wide_path.as_ptr(),
+                    SE_FILE_OBJECT,
+                    DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    dacl,
+                    std::ptr::null(),
+                )
+            } {
+                0 => Ok(()),
+                error => Err(std::io::Error::from_raw_os_error(error as i32)),
+            }
+        };
+
+    unsafe { LocalFree(sd) };
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("barvazdns-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn write_replaces_contents() {
+        let path = scratch_path("replace");
+        write(&path, b"a longer first version").unwrap();
+        write(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert!(!temporary_path(&path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_file_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = scratch_path("private");
+        fs::write(&path, b"public").unwrap();
+        write_private_file(&path, b"secret").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, PRIVATE_FILE_MODE);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_directory_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = scratch_path("directory");
+        fs::create_dir(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        create_private_directory(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, PRIVATE_DIRECTORY_MODE);
+        fs::remove_dir(&path).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! A bounded, persisted record of every update attempt, newest last.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::config::Config;
+use super::{consts, files, strings};
+
+/// How an update attempt ended.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AttemptResult {
+    /// The provider accepted the update and responded with `response`.
+    Published {
+        response: String,
+    },
+    /// The addresses were unchanged, so the provider was not contacted.
+    Unchanged,
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    #[serde(with = "humantime_serde")]
+    pub time: SystemTime,
+    pub provider: String,
+    pub domains: Vec<String>,
+    /// The addresses sent, or `None` if discovery failed.
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub result: AttemptResult,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct History {
+    #[serde(default)]
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl History {
+    fn file_path() -> Result<PathBuf> {
+        let mut path = Config::get_config_directory_path()?;
+        path.push(strings::HISTORY_FILE_NAME);
+        Ok(path)
+    }
+
+    /// Reads the history from disk. A missing or unreadable file yields an
+    /// empty history.
+    pub fn read() -> Self {
+        let Ok(path) = Self::file_path() else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("Ignoring malformed {path:?}: {e}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn store(&self) -> Result<()> {
+        let path = Self::file_path()?;
+        files::write(&path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Adds `entry`, dropping the oldest entries beyond `capacity`.
+    pub fn push(&mut self, entry: HistoryEntry, capacity: usize) {
+        self.entries.push_back(entry);
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns up to `limit` of the most recent entries, oldest first.
+    pub fn latest(&self, limit: usize) -> Vec<HistoryEntry> {
+        let skip = self.entries.len().saturating_sub(limit);
+        self.entries.iter().skip(skip).cloned().collect()
+    }
+
+    /// Appends `entry` to the history file. Only the update loop appends, so
+    /// the read-modify-write cycle never races with another one.
+    pub fn append(entry: HistoryEntry) -> Result<()> {
+        let mut history = Self::read();
+        history.push(entry, consts::HISTORY_CAPACITY);
+        history.store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(secs: u64) -> HistoryEntry {
+        HistoryEntry {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            provider: "duckdns".to_string(),
+            domains: vec!["home".to_string()],
+            ipv4: Some(Ipv4Addr::new(1, 2, 3, 4)),
+            ipv6: None,
+            result: AttemptResult::Published {
+                response: "OK".to_string(),
+            },
+            duration: Duration::from_millis(250),
+        }
+    }
+
+    #[test]
+    fn push_drops_oldest_beyond_capacity() {
+        let mut history = History::default();
+        for secs in 0..5 {
+            history.push(entry(secs), 3);
+        }
+        let times: Vec<SystemTime> = history.latest(10).iter().map(|e| e.time).collect();
+        assert_eq!(
+            times,
+            (2..5)
+                .map(|s| SystemTime::UNIX_EPOCH + Duration::from_secs(s))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn latest_returns_newest_entries_in_order() {
+        let mut history = History::default();
+        for secs in 0..5 {
+            history.push(entry(secs), 10);
+        }
+        let latest = history.latest(2);
+        assert_eq!(latest, vec![entry(3), entry(4)]);
+        assert!(history.latest(0).is_empty());
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let mut history = History::default();
+        history.push(entry(1), 10);
+        let mut failed = entry(2);
+        failed.ipv4 = None;
+        failed.result = AttemptResult::Failed {
+            error: "timeout".to_string(),
+        };
+        history.push(failed.clone(), 10);
+
+        let json = serde_json::to_string(&history).unwrap();
+        let decoded: History = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.latest(10), vec![entry(1), failed]);
+    }
+}
@@ -1,26 +1,87 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::{Duration, SystemTime};
 
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWriteExt;
-use tokio::{io::AsyncReadExt, net::windows::named_pipe::ClientOptions};
 
+use super::history::HistoryEntry;
+use super::metrics::Metrics;
+use super::transport::Transport;
 use super::{config, strings};
 
 pub use config::Token;
 
+/// What the last update attempt did for a single domain.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum DomainOutcome {
+    Updated,
+    /// The addresses were unchanged, so the provider was not contacted.
+    Unchanged,
+    Failed(String),
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct UpdateStatus {
     /// Timestamp + domains of the last successful update, if any.
     pub last_success: Option<(SystemTime, Vec<String>)>,
+    /// Timestamp of the last update attempt, successful or not.
+    pub last_attempt: Option<SystemTime>,
+    /// Error of the last attempt, cleared once an attempt succeeds.
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    /// Outcome of the last attempt for each domain it covered.
+    pub domains: BTreeMap<String, DomainOutcome>,
+}
+
+impl UpdateStatus {
+    /// Records an attempt whose domains succeeded or failed on their own, e.g.
+    /// because they were sent in separate batches. Domains the attempt did not
+    /// cover keep their previous outcome.
+    pub fn record_attempt(&mut self, outcomes: BTreeMap<String, DomainOutcome>, now: SystemTime) {
+        let updated: Vec<String> = outcomes
+            .iter()
+            .filter(|(_, outcome)| **outcome == DomainOutcome::Updated)
+            .map(|(domain, _)| domain.clone())
+            .collect();
+        let errors: BTreeSet<&str> = outcomes
+            .values()
+            .filter_map(|outcome| match outcome {
+                DomainOutcome::Failed(e) => Some(e.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        if !updated.is_empty() {
+            self.last_success = Some((now, updated));
+        }
+        self.last_attempt = Some(now);
+        if errors.is_empty() {
+            self.last_error = None;
+            self.consecutive_failures = 0;
+        } else {
+            self.last_error = Some(errors.into_iter().collect::<Vec<_>>().join("; "));
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        }
+        self.domains.extend(outcomes);
+    }
 }
 
+/// The version of the control protocol spoken by this build. Bump it for
+/// changes that capabilities cannot express, such as a change in the meaning
+/// of an existing request.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest protocol version of a peer this build can still talk to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Messages are encoded as JSON, so that a peer ignores fields it does not
+/// know and only fails on requests it cannot handle, which capabilities let
+/// clients avoid sending.
 pub fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
-    Ok(bincode::serialize(value)?)
+    Ok(serde_json::to_vec(value)?)
 }
 
 pub fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T> {
-    Ok(bincode::deserialize(bytes)?)
+    Ok(serde_json::from_slice(bytes)?)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,71 +95,331 @@ pub enum Request {
     DebugLevel(String),
     GetConfig,
     GetStatus,
+    /// The given number of most recent update attempts.
+    GetHistory(usize),
     Version,
+    GetMetrics,
+    /// Turns the connection into a stream of [`Event`]s. The service answers
+    /// with the current status and configuration, then with every change as
+    /// it happens, until the client hangs up.
+    Subscribe,
+}
+
+/// A connection to the service.
+#[cfg(windows)]
+pub type Connection = tokio::net::windows::named_pipe::NamedPipeClient;
+#[cfg(unix)]
+pub type Connection = tokio::net::UnixStream;
+
+#[cfg(windows)]
+async fn connect() -> std::io::Result<Connection> {
+    use windows_sys::Win32::Foundation::ERROR_PIPE_BUSY;
+
+    // Every pipe instance can be taken for a moment while the service creates
+    // the next one, so keep trying for a while.
+    let deadline = tokio::time::Instant::now() + super::consts::PIPE_TIMEOUT;
+    loop {
+        match tokio::net::windows::named_pipe::ClientOptions::new().open(strings::PIPE_NAME) {
+            Err(e)
+                if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32)
+                    && tokio::time::Instant::now() < deadline =>
+            {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn connect() -> std::io::Result<Connection> {
+    tokio::net::UnixStream::connect(strings::SOCKET_PATH).await
 }
 
 impl Request {
+    /// The requests this build can serve, advertised to clients in [`Welcome`].
+    pub const CAPABILITIES: &[&str] = &[
+        "interval",
+        "token",
+        "add_domain",
+        "remove_domain",
+        "ipv6",
+        "force_update",
+        "debug_level",
+        "get_config",
+        "get_status",
+        "get_history",
+        "version",
+        "get_metrics",
+        "subscribe",
+    ];
+
+    /// The capability a service must advertise to accept this request.
+    pub fn capability(&self) -> &'static str {
+        match self {
+            Request::Interval(_) => "interval",
+            Request::Token(_) => "token",
+            Request::AddDomain(_) => "add_domain",
+            Request::RemoveDomain(_) => "remove_domain",
+            Request::Ipv6(_) => "ipv6",
+            Request::ForceUpdate => "force_update",
+            Request::DebugLevel(_) => "debug_level",
+            Request::GetConfig => "get_config",
+            Request::GetStatus => "get_status",
+            Request::GetHistory(_) => "get_history",
+            Request::Version => "version",
+            Request::GetMetrics => "get_metrics",
+            Request::Subscribe => "subscribe",
+        }
+    }
+
     pub async fn send(self) -> Result<Response> {
-        // Try to connect to the named pipe
-        let mut client = ClientOptions::new().open(strings::PIPE_NAME).map_err(|_| {
+        // Try to connect to the service's named pipe or Unix socket
+        let mut client = connect().await.map_err(|_| {
             anyhow!("Failed to communicate with the service. Verify it is running.")
         })?;
 
-        let service_request = ServiceRequest::new(self);
-        let encoded = encode(&service_request)?;
-        client.write_all(&encoded).await?;
+        let welcome = handshake(&mut client).await?;
+        self.send_over(&mut client, &welcome).await
+    }
+
+    /// Sends the request over a `transport` that completed the handshake with
+    /// `welcome`, and waits for the response.
+    pub async fn send_over(
+        self,
+        transport: &mut impl Transport,
+        welcome: &Welcome,
+    ) -> Result<Response> {
+        if !welcome.supports(self.capability()) {
+            return Err(anyhow!(
+                "The service (version {}) does not support this request. Update the service to use it.",
+                welcome.version
+            ));
+        }
 
-        let mut buf = Vec::new();
-        client.read_to_end(&mut buf).await?;
-        decode(&buf)
+        transport.write_message(&self).await?;
+        transport
+            .read_message()
+            .await?
+            .ok_or_else(|| anyhow!("The service closed the connection without responding"))
     }
 }
 
+/// The first message a client sends on a connection.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct ServiceRequest {
-    version: String,
-    request: Request,
+pub struct Hello {
+    pub protocol: u32,
+    pub version: String,
 }
 
-impl ServiceRequest {
-    fn new(request: Request) -> Self {
-        ServiceRequest {
+impl Hello {
+    /// The hello of this build.
+    pub fn current() -> Self {
+        Hello {
+            protocol: PROTOCOL_VERSION,
             version: strings::VERSION.to_string(),
-            request,
         }
     }
 
     pub fn is_compatible(&self) -> bool {
-        if let Request::Version = self.request {
-            // Always allow Version requests through, even when versions differ,
-            // since the purpose of this request is to query a potentially-mismatched service.
-            return true;
+        self.protocol >= MIN_PROTOCOL_VERSION
+    }
+}
+
+/// The service's answer to [`Hello`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Welcome {
+    pub protocol: u32,
+    /// The oldest client protocol the service accepts.
+    pub min_protocol: u32,
+    pub version: String,
+    pub capabilities: Vec<String>,
+}
+
+impl Welcome {
+    /// The welcome of this build.
+    pub fn current() -> Self {
+        Welcome {
+            protocol: PROTOCOL_VERSION,
+            min_protocol: MIN_PROTOCOL_VERSION,
+            version: strings::VERSION.to_string(),
+            capabilities: Request::CAPABILITIES
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
         }
-        self.version == strings::VERSION
     }
 
-    pub fn version(&self) -> &str {
-        &self.version
+    /// Checks that both sides can talk to each other, from the client's side.
+    fn check_compatible(&self) -> Result<()> {
+        if PROTOCOL_VERSION < self.min_protocol {
+            Err(anyhow!(
+                "The service (version {}) requires a newer client. Update BarvazDNS.",
+                self.version
+            ))
+        } else if self.protocol < MIN_PROTOCOL_VERSION {
+            Err(anyhow!(
+                "The service (version {}) is too old for this client. Reinstall the service.",
+                self.version
+            ))
+        } else {
+            Ok(())
+        }
     }
 
-    pub fn request(&self) -> &Request {
-        &self.request
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
     }
 }
 
+/// Exchanges [`Hello`] and [`Welcome`] over a freshly connected `transport`.
+pub async fn handshake(transport: &mut impl Transport) -> Result<Welcome> {
+    transport.write_message(&Hello::current()).await?;
+    let welcome: Welcome = transport
+        .read_message()
+        .await?
+        .ok_or_else(|| anyhow!("The service closed the connection during the handshake"))?;
+    welcome.check_compatible()?;
+    Ok(welcome)
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Response {
     Ok,
     Err(String),
-    Config(config::ServiceConfig),
+    Config(Box<config::ServiceConfig>),
     Status(UpdateStatus),
+    History(Vec<HistoryEntry>),
     Version(String),
+    Metrics(Box<Metrics>),
+    Event(Event),
+}
+
+/// Something that changed in the service, pushed to subscribed clients.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Event {
+    /// The status after an update attempt.
+    Status(UpdateStatus),
+    /// The configuration after a change, listed like [`Request::GetConfig`]
+    /// lists it.
+    Config(Box<config::ServiceConfig>),
+    /// A line written to the service log.
+    Log(String),
+}
+
+/// The events of a connection that sent [`Request::Subscribe`].
+pub struct Subscription<T> {
+    transport: T,
+}
+
+/// Subscribes to the events of the running service.
+pub async fn subscribe() -> Result<Subscription<Connection>> {
+    let mut client = connect()
+        .await
+        .map_err(|_| anyhow!("Failed to communicate with the service. Verify it is running."))?;
+
+    let welcome = handshake(&mut client).await?;
+    Subscription::over(client, &welcome).await
+}
+
+impl<T: Transport> Subscription<T> {
+    /// Subscribes over a `transport` that completed the handshake with
+    /// `welcome`.
+    pub async fn over(mut transport: T, welcome: &Welcome) -> Result<Self> {
+        let request = Request::Subscribe;
+        if !welcome.supports(request.capability()) {
+            return Err(anyhow!(
+                "The service (version {}) does not support live updates. Update the service to use them.",
+                welcome.version
+            ));
+        }
+
+        transport.write_message(&request).await?;
+        Ok(Self { transport })
+    }
+
+    /// Waits for the next event. Returns `None` once the service closed the
+    /// connection, e.g. because it stopped.
+    pub async fn next(&mut self) -> Result<Option<Event>> {
+        match self.transport.read_message().await? {
+            None => Ok(None),
+            Some(Response::Event(event)) => Ok(Some(event)),
+            Some(Response::Err(e)) => Err(anyhow!(e)),
+            Some(response) => Err(anyhow!("Unexpected response: {response:?}")),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn every_request_has_an_advertised_capability() {
+        let requests = [
+            Request::Interval(Duration::from_secs(300)),
+            Request::Token(Token::new("t".to_string())),
+            Request::AddDomain("a".to_string()),
+            Request::RemoveDomain("a".to_string()),
+            Request::Ipv6(true),
+            Request::ForceUpdate,
+            Request::DebugLevel("info".to_string()),
+            Request::GetConfig,
+            Request::GetStatus,
+            Request::GetHistory(1),
+            Request::Version,
+            Request::GetMetrics,
+            Request::Subscribe,
+        ];
+        assert_eq!(requests.len(), Request::CAPABILITIES.len());
+        for request in requests {
+            assert!(Request::CAPABILITIES.contains(&request.capability()));
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored() {
+        let json = r#"{"protocol":9,"min_protocol":1,"version":"9.0.0","capabilities":["version"],"motd":"hi"}"#;
+        let welcome: Welcome = decode(json.as_bytes()).unwrap();
+        assert_eq!(welcome.protocol, 9);
+        assert!(welcome.check_compatible().is_ok());
+        assert!(welcome.supports("version"));
+        assert!(!welcome.supports("get_history"));
+    }
+
+    #[test]
+    fn unknown_request_is_rejected() {
+        assert!(decode::<Request>(br#"{"Frobnicate":1}"#).is_err());
+    }
+
+    #[test]
+    fn welcome_compatibility() {
+        let mut welcome = Welcome::current();
+        assert!(welcome.check_compatible().is_ok());
+
+        welcome.min_protocol = PROTOCOL_VERSION + 1;
+        assert!(welcome.check_compatible().is_err());
+
+        welcome.min_protocol = MIN_PROTOCOL_VERSION;
+        welcome.protocol = MIN_PROTOCOL_VERSION - 1;
+        assert!(welcome.check_compatible().is_err());
+    }
+
+    #[tokio::test]
+    async fn unsupported_request_is_not_sent() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let mut welcome = Welcome::current();
+        welcome.capabilities.retain(|c| c != "get_history");
+
+        let result = Request::GetHistory(5)
+            .send_over(&mut client, &welcome)
+            .await;
+        assert!(result.is_err());
+
+        drop(client);
+        assert!(server.read_message::<Request>().await.unwrap().is_none());
+    }
+
     fn roundtrip_request(request: Request) {
         let encoded = encode(&request).expect("encode failed");
         let decoded: Request = decode(&encoded).expect("decode failed");
@@ -143,6 +464,64 @@ mod tests {
         roundtrip_request(Request::Version);
     }
 
+    #[test]
+    fn encode_decode_request_get_history() {
+        roundtrip_request(Request::GetHistory(20));
+    }
+
+    #[test]
+    fn encode_decode_response_event() {
+        let encoded = encode(&Response::Event(Event::Log("line".to_string()))).unwrap();
+        let decoded: Response = decode(&encoded).unwrap();
+        assert!(matches!(decoded, Response::Event(Event::Log(line)) if line == "line"));
+    }
+
+    #[tokio::test]
+    async fn subscription_reads_events_until_closed() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut subscription = Subscription::over(client, &Welcome::current())
+            .await
+            .unwrap();
+        assert!(matches!(
+            server.read_message::<Request>().await.unwrap(),
+            Some(Request::Subscribe)
+        ));
+
+        server
+            .write_message(&Response::Event(Event::Status(UpdateStatus::default())))
+            .await
+            .unwrap();
+        server.write_message(&Response::Ok).await.unwrap();
+        drop(server);
+
+        assert!(matches!(
+            subscription.next().await.unwrap(),
+            Some(Event::Status(_))
+        ));
+        assert!(subscription.next().await.is_err());
+        assert!(subscription.next().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn encode_decode_response_history() {
+        use super::super::history::AttemptResult;
+
+        let entry = HistoryEntry {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            provider: "duckdns".to_string(),
+            domains: vec!["home".to_string()],
+            ipv4: Some("1.2.3.4".parse().unwrap()),
+            ipv6: None,
+            result: AttemptResult::Failed {
+                error: "timeout".to_string(),
+            },
+            duration: Duration::from_millis(1500),
+        };
+        let encoded = encode(&Response::History(vec![entry.clone()])).unwrap();
+        let decoded: Response = decode(&encoded).unwrap();
+        assert!(matches!(decoded, Response::History(entries) if entries == vec![entry]));
+    }
+
     #[test]
     fn encode_decode_response_ok() {
         let encoded = encode(&Response::Ok).unwrap();
@@ -164,6 +543,77 @@ mod tests {
         assert!(matches!(decoded, Response::Version(v) if v == "1.0.0"));
     }
 
+    /// The outcome of an attempt that only covered `home`.
+    fn home(outcome: DomainOutcome) -> BTreeMap<String, DomainOutcome> {
+        BTreeMap::from([("home".to_string(), outcome)])
+    }
+
+    fn failed(error: &str) -> DomainOutcome {
+        DomainOutcome::Failed(error.to_string())
+    }
+
+    #[test]
+    fn status_counts_consecutive_failures() {
+        let mut status = UpdateStatus::default();
+        status.record_attempt(home(failed("timeout")), SystemTime::now());
+        status.record_attempt(home(failed("refused")), SystemTime::now());
+        assert_eq!(status.consecutive_failures, 2);
+        assert_eq!(status.last_error.as_deref(), Some("refused"));
+        assert_eq!(
+            status.domains["home"],
+            DomainOutcome::Failed("refused".to_string())
+        );
+        assert!(status.last_success.is_none());
+
+        status.record_attempt(home(DomainOutcome::Updated), SystemTime::now());
+        assert_eq!(status.consecutive_failures, 0);
+        assert!(status.last_error.is_none());
+        assert_eq!(status.domains["home"], DomainOutcome::Updated);
+        assert!(status.last_success.is_some());
+    }
+
+    #[test]
+    fn unchanged_attempt_keeps_last_success() {
+        let published_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut status = UpdateStatus::default();
+        status.record_attempt(home(DomainOutcome::Updated), published_at);
+        status.record_attempt(home(failed("timeout")), SystemTime::now());
+        status.record_attempt(home(DomainOutcome::Unchanged), SystemTime::now());
+
+        assert_eq!(status.last_success.as_ref().unwrap().0, published_at);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.domains["home"], DomainOutcome::Unchanged);
+    }
+
+    #[test]
+    fn partial_failure_keeps_outcome_per_domain() {
+        let mut status = UpdateStatus::default();
+        status.record_attempt(home(DomainOutcome::Updated), SystemTime::now());
+
+        let outcomes = BTreeMap::from([
+            ("a".to_string(), DomainOutcome::Updated),
+            ("b".to_string(), failed("KO")),
+        ]);
+        status.record_attempt(outcomes, SystemTime::now());
+
+        assert_eq!(status.consecutive_failures, 1);
+        assert_eq!(status.last_error.as_deref(), Some("KO"));
+        assert_eq!(status.last_success.as_ref().unwrap().1, vec!["a"]);
+        assert_eq!(status.domains["a"], DomainOutcome::Updated);
+        assert_eq!(status.domains["b"], DomainOutcome::Failed("KO".to_string()));
+        // Not covered by the attempt.
+        assert_eq!(status.domains["home"], DomainOutcome::Updated);
+    }
+
+    #[test]
+    fn encode_decode_response_status() {
+        let mut status = UpdateStatus::default();
+        status.record_attempt(home(failed("timeout")), SystemTime::now());
+        let encoded = encode(&Response::Status(status)).unwrap();
+        let decoded: Response = decode(&encoded).unwrap();
+        assert!(matches!(decoded, Response::Status(s) if s.consecutive_failures == 1));
+    }
+
     #[test]
     fn decode_corrupted_data_fails() {
         let result: Result<Request> = decode(&[0xFF, 0xFF, 0xFF]);
@@ -171,17 +621,17 @@ mod tests {
     }
 
     #[test]
-    fn is_compatible_version_request_always_passes() {
-        let mut req = ServiceRequest::new(Request::Version);
-        req.version = "0.0.0-mismatch".to_string();
-        // Version requests bypass the version check, even with a mismatched version
-        assert!(req.is_compatible());
+    fn is_compatible_across_release_versions() {
+        let mut hello = Hello::current();
+        hello.version = "0.0.0-mismatch".to_string();
+        // Only the protocol version matters, not the release.
+        assert!(hello.is_compatible());
     }
 
     #[test]
-    fn is_compatible_mismatched_version_fails() {
-        let mut req = ServiceRequest::new(Request::ForceUpdate);
-        req.version = "0.0.0-mismatch".to_string();
-        assert!(!req.is_compatible());
+    fn is_compatible_outdated_protocol_fails() {
+        let mut hello = Hello::current();
+        hello.protocol = MIN_PROTOCOL_VERSION - 1;
+        assert!(!hello.is_compatible());
     }
 }
@@ -0,0 +1,235 @@
+//! Counters of the update attempts made since the service started, which the
+//! dashboard exposes to Prometheus.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use super::history::{AttemptResult, HistoryEntry};
+
+/// Upper bounds, in seconds, of the update latency histogram buckets.
+pub const LATENCY_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// The attempts for one domain through one provider.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DomainMetrics {
+    pub domain: String,
+    pub provider: String,
+    pub attempts: u64,
+    /// Attempts that published the addresses or found them unchanged.
+    pub successes: u64,
+    pub failures: u64,
+    pub last_success: Option<SystemTime>,
+    /// The addresses the domain points to as of its last successful attempt.
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    /// How many times the published addresses differed from the previous ones.
+    pub ip_changes: u64,
+}
+
+/// A histogram of durations, in seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Observations at or below each bound of [`LATENCY_BUCKETS`], so the
+    /// counts are cumulative like Prometheus expects.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Whether an address was replaced by another one. The first address seen
+/// since the service started is not a change, as there is nothing to compare
+/// it with.
+fn address_changed<T: PartialEq>(old: Option<T>, new: Option<T>) -> bool {
+    old.is_some() && new.is_some() && old != new
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Metrics {
+    pub started: SystemTime,
+    pub domains: Vec<DomainMetrics>,
+    /// The duration of update attempts, by provider.
+    pub latency: BTreeMap<String, Histogram>,
+}
+
+impl Metrics {
+    pub fn new(started: SystemTime) -> Self {
+        Self {
+            started,
+            domains: Vec::new(),
+            latency: BTreeMap::new(),
+        }
+    }
+
+    fn domain(&mut self, domain: &str, provider: &str) -> &mut DomainMetrics {
+        let index = match self
+            .domains
+            .iter()
+            .position(|d| d.domain == domain && d.provider == provider)
+        {
+            Some(index) => index,
+            None => {
+                self.domains.push(DomainMetrics {
+                    domain: domain.to_string(),
+                    provider: provider.to_string(),
+                    attempts: 0,
+                    successes: 0,
+                    failures: 0,
+                    last_success: None,
+                    ipv4: None,
+                    ipv6: None,
+                    ip_changes: 0,
+                });
+                self.domains.len() - 1
+            }
+        };
+        &mut self.domains[index]
+    }
+
+    /// Counts an attempt as recorded in the update history.
+    pub fn record(&mut self, attempt: &HistoryEntry) {
+        self.latency
+            .entry(attempt.provider.clone())
+            .or_default()
+            .observe(attempt.duration.as_secs_f64());
+
+        let succeeded = !matches!(attempt.result, AttemptResult::Failed { .. });
+        for name in &attempt.domains {
+            let domain = self.domain(name, &attempt.provider);
+            domain.attempts += 1;
+            if !succeeded {
+                domain.failures += 1;
+                continue;
+            }
+
+            domain.successes += 1;
+            domain.last_success = Some(attempt.time);
+            if address_changed(domain.ipv4, attempt.ipv4)
+                || address_changed(domain.ipv6, attempt.ipv6)
+            {
+                domain.ip_changes += 1;
+            }
+            domain.ipv4 = attempt.ipv4;
+            domain.ipv6 = attempt.ipv6;
+        }
+    }
+
+    /// Drops the series of domains that are no longer configured.
+    pub fn retain(&mut self, configured: &BTreeSet<String>) {
+        self.domains.retain(|d| configured.contains(&d.domain));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn attempt(ipv4: [u8; 4], result: AttemptResult, millis: u64) -> HistoryEntry {
+        HistoryEntry {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_000),
+            provider: "duckdns".to_string(),
+            domains: vec!["home".to_string(), "office".to_string()],
+            ipv4: Some(Ipv4Addr::from(ipv4)),
+            ipv6: None,
+            result,
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    fn published() -> AttemptResult {
+        AttemptResult::Published {
+            response: "OK".to_string(),
+        }
+    }
+
+    #[test]
+    fn counts_attempts_per_domain() {
+        let mut metrics = Metrics::new(SystemTime::UNIX_EPOCH);
+        metrics.record(&attempt([1, 2, 3, 4], published(), 200));
+        metrics.record(&attempt(
+            [1, 2, 3, 4],
+            AttemptResult::Failed {
+                error: "timeout".to_string(),
+            },
+            5_000,
+        ));
+        metrics.record(&attempt([1, 2, 3, 4], AttemptResult::Unchanged, 50));
+
+        assert_eq!(metrics.domains.len(), 2);
+        let home = &metrics.domains[0];
+        assert_eq!(home.domain, "home");
+        assert_eq!((home.attempts, home.successes, home.failures), (3, 2, 1));
+        assert_eq!(
+            home.last_success,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000))
+        );
+        assert_eq!(home.ipv4, Some(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(home.ip_changes, 0);
+    }
+
+    #[test]
+    fn counts_address_changes() {
+        let mut metrics = Metrics::new(SystemTime::UNIX_EPOCH);
+        metrics.record(&attempt([1, 2, 3, 4], published(), 200));
+        metrics.record(&attempt([5, 6, 7, 8], published(), 200));
+        // A failed attempt does not change what the domain points to.
+        metrics.record(&attempt(
+            [9, 9, 9, 9],
+            AttemptResult::Failed {
+                error: "timeout".to_string(),
+            },
+            200,
+        ));
+        metrics.record(&attempt([5, 6, 7, 8], AttemptResult::Unchanged, 200));
+
+        assert_eq!(metrics.domains[0].ip_changes, 1);
+        assert_eq!(metrics.domains[0].ipv4, Some(Ipv4Addr::new(5, 6, 7, 8)));
+    }
+
+    #[test]
+    fn retain_drops_removed_domains() {
+        let mut metrics = Metrics::new(SystemTime::UNIX_EPOCH);
+        metrics.record(&attempt([1, 2, 3, 4], published(), 200));
+
+        metrics.retain(&BTreeSet::from(["office".to_string()]));
+        let domains: Vec<&str> = metrics.domains.iter().map(|d| d.domain.as_str()).collect();
+        assert_eq!(domains, vec!["office"]);
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.2);
+        histogram.observe(3.0);
+        histogram.observe(100.0);
+
+        assert_eq!(histogram.buckets, vec![0, 1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.sum, 103.2);
+    }
+}
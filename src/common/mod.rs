@@ -1,6 +1,10 @@
 pub mod config;
 pub mod consts;
+pub mod files;
+pub mod history;
 pub mod message;
+pub mod metrics;
 pub mod prompt;
 pub mod strings;
+pub mod transport;
 pub mod version_check;
@@ -22,14 +22,33 @@ pub const LOGO: &str = "⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀
 ⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠉⠉⠛⠛⠻⠿⠿⠿⢿⣿⣿⡿⠿⠿⠿⠿⠛⠛⠋⠉⠁";
 
 // service
+#[cfg(windows)]
 pub const PIPE_NAME: &str = r"\\.\pipe\barvaz-dns-service";
+#[cfg(unix)]
+pub const SOCKET_PATH: &str = "/run/barvazdns.sock";
+#[cfg(unix)]
+pub const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+#[cfg(unix)]
+pub const SYSTEMD_SERVICE_UNIT: &str = "barvazdns.service";
+#[cfg(unix)]
+pub const SYSTEMD_SOCKET_UNIT: &str = "barvazdns.socket";
+#[cfg(windows)]
 pub const SERVICE_NAME: &str = "BarvazDNSSvc";
 pub const SERVICE_DISPLAY_NAME: &str = "BarvazDNS";
 pub const SERVICE_DESCRIPTION: &str = "Automatically updates your DuckDNS record to ensure your hostname always points to your current public IP.";
 
 // config
+#[cfg(windows)]
 pub const CONFIG_DIR: &str = "BarvazDNS";
+#[cfg(unix)]
+pub const CONFIG_DIR: &str = "barvazdns";
 pub const CONFIG_FILE_NAME: &str = "config.toml";
+pub const PUBLISHED_STATE_FILE_NAME: &str = "published.json";
+pub const HISTORY_FILE_NAME: &str = "history.json";
+pub const DASHBOARD_SECRET_FILE_NAME: &str = "dashboard.secret";
+pub const DASHBOARD_SESSION_COOKIE: &str = "barvazdns_session";
+pub const DASHBOARD_CERT_FILE_NAME: &str = "dashboard.crt";
+pub const DASHBOARD_KEY_FILE_NAME: &str = "dashboard.key";
 pub const DEFAULT_CONFIG_CONTENT: &str = r#"
 [service]
 interval = "1 day"
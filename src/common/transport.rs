@@ -0,0 +1,120 @@
+//! Carries control protocol messages over any byte stream, so the same
+//! request/response flow runs over a named pipe, a Unix socket, TCP or an
+//! in-memory duplex stream in tests.
+//!
+//! Each message is sent as a frame: its encoded length as a big-endian `u32`,
+//! followed by the encoded message. Frames are limited to
+//! [`MAX_FRAME_SIZE`], and any number of them can be exchanged over one
+//! connection.
+
+use std::io::ErrorKind;
+
+use anyhow::{Result, anyhow};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::consts::MAX_FRAME_SIZE;
+use super::message::{decode, encode};
+
+/// A stream that control protocol messages can be exchanged over.
+#[allow(async_fn_in_trait)]
+pub trait Transport: AsyncRead + AsyncWrite + Unpin {
+    /// Writes `message` as a single frame.
+    async fn write_message<T: Serialize>(&mut self, message: &T) -> Result<()> {
+        let payload = encode(message)?;
+        check_frame_size(payload.len())?;
+        let len = payload.len() as u32;
+
+        self.write_all(&len.to_be_bytes()).await?;
+        self.write_all(&payload).await?;
+        self.flush().await?;
+        Ok(())
+    }
+
+    /// Reads the next frame. Returns `None` if the peer closed the stream
+    /// before sending one.
+    async fn read_message<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        let mut len = [0; 4];
+        match self.read_exact(&mut len).await {
+            Ok(_) => {}
+            // A named pipe reports a client that hung up as a broken pipe.
+            Err(e) if matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let len = u32::from_be_bytes(len) as usize;
+        check_frame_size(len)?;
+
+        let mut payload = vec![0; len];
+        self.read_exact(&mut payload).await?;
+        decode(&payload).map(Some)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + ?Sized> Transport for S {}
+
+fn check_frame_size(len: usize) -> Result<()> {
+    if len > MAX_FRAME_SIZE {
+        Err(anyhow!(
+            "Message of {len} bytes exceeds the limit of {MAX_FRAME_SIZE} bytes"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn messages_roundtrip_in_order() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+
+        let writer = tokio::spawn(async move {
+            a.write_message(&"first".to_string()).await.unwrap();
+            // Larger than the duplex buffer, so it is written in pieces.
+            a.write_message(&vec![7u8; 1000]).await.unwrap();
+        });
+
+        assert_eq!(
+            b.read_message::<String>().await.unwrap().as_deref(),
+            Some("first")
+        );
+        assert_eq!(
+            b.read_message::<Vec<u8>>().await.unwrap(),
+            Some(vec![7u8; 1000])
+        );
+        writer.await.unwrap();
+        assert!(b.read_message::<String>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_is_an_error() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        a.write_all(&10u32.to_be_bytes()).await.unwrap();
+        a.write_all(b"short").await.unwrap();
+        drop(a);
+
+        assert!(b.read_message::<String>().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected_before_reading_it() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let len = (MAX_FRAME_SIZE + 1) as u32;
+        a.write_all(&len.to_be_bytes()).await.unwrap();
+
+        // No payload follows, so this would hang if the frame were read.
+        assert!(b.read_message::<String>().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_message_is_not_sent() {
+        let (mut a, _b) = tokio::io::duplex(64);
+        let message = vec![0u8; MAX_FRAME_SIZE];
+        assert!(a.write_message(&message).await.is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Authentication for the dashboard API.
+//!
+//! Every install has a random secret stored in the configuration directory.
+//! Scripts present it as a bearer token; browsers exchange it once for a
+//! signed session cookie and send a CSRF token derived from that session with
+//! every request that changes something.
+
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::common;
+use crate::common::config::Config;
+use crate::common::consts::{DASHBOARD_LOGIN_TICKET_LIFETIME, DASHBOARD_SESSION_LIFETIME};
+use crate::common::files;
+
+const SECRET_LENGTH: usize = 32;
+
+/// What a signed token grants, so that a token issued for one purpose is
+/// never accepted for another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Purpose {
+    /// A browser session, carried in a cookie.
+    Session,
+    /// A short-lived link that signs a browser in, used by the tray icon.
+    LoginTicket,
+}
+
+impl Purpose {
+    fn label(self) -> &'static str {
+        match self {
+            Purpose::Session => "session",
+            Purpose::LoginTicket => "ticket",
+        }
+    }
+
+    fn lifetime(self) -> Duration {
+        match self {
+            Purpose::Session => DASHBOARD_SESSION_LIFETIME,
+            Purpose::LoginTicket => DASHBOARD_LOGIN_TICKET_LIFETIME,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+fn secret_file_path() -> Result<PathBuf> {
+    let mut path = Config::get_config_directory_path()?;
+    path.push(common::strings::DASHBOARD_SECRET_FILE_NAME);
+    Ok(path)
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Compares two strings in time that does not depend on where they differ.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+impl Secret {
+    pub fn generate() -> Result<Self> {
+        let mut bytes = [0u8; SECRET_LENGTH];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|e| anyhow!("Failed to generate the dashboard secret: {e}"))?;
+        Ok(Self(BASE64.encode(bytes)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Reads the secret of this install.
+    pub fn read() -> Result<Self> {
+        let path = secret_file_path()?;
+        let content =
+            fs::read_to_string(&path).map_err(|e| anyhow!("Failed to read {path:?}: {e}"))?;
+        let secret = content.trim();
+        if secret.is_empty() {
+            return Err(anyhow!("{path:?} is empty"));
+        }
+        Ok(Self(secret.to_string()))
+    }
+
+    /// Reads the secret of this install, creating one on first use.
+    pub fn load_or_create() -> Result<Self> {
+        match Self::read() {
+            Ok(secret) => Ok(secret),
+            Err(_) => Self::rotate(),
+        }
+    }
+
+    /// Replaces the secret with a new one, which invalidates every session
+    /// and bearer token issued so far.
+    pub fn rotate() -> Result<Self> {
+        let secret = Self::generate()?;
+        secret.store()?;
+        Ok(secret)
+    }
+
+    fn store(&self) -> Result<()> {
+        files::write_private_file(&secret_file_path()?, self.0.as_bytes())
+    }
+
+    /// Whether `candidate` is this secret.
+    pub fn matches(&self, candidate: &str) -> bool {
+        constant_time_eq(&self.0, candidate)
+    }
+
+    fn mac(&self, message: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        BASE64.encode(mac.finalize().into_bytes())
+    }
+
+    /// Issues a token of the form `<issued at>.<signature>`.
+    pub fn issue(&self, purpose: Purpose, now: SystemTime) -> String {
+        let issued_at = unix_seconds(now);
+        let signature = self.mac(&format!("{}:{issued_at}", purpose.label()));
+        format!("{issued_at}.{signature}")
+    }
+
+    /// Whether `token` was issued for `purpose` with this secret and has not
+    /// expired yet.
+    pub fn verify(&self, purpose: Purpose, token: &str, now: SystemTime) -> bool {
+        let Some((issued_at, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let Ok(issued_seconds) = issued_at.parse::<u64>() else {
+            return false;
+        };
+
+        let now = unix_seconds(now);
+        let fresh = issued_seconds <= now && now - issued_seconds < purpose.lifetime().as_secs();
+        let expected = self.mac(&format!("{}:{issued_seconds}", purpose.label()));
+        fresh && constant_time_eq(&expected, signature)
+    }
+
+    /// The CSRF token that goes with a session. It is bound to the session,
+    /// so a token read from one session is useless in another.
+    pub fn csrf_token(&self, session: &str) -> String {
+        self.mac(&format!("csrf:{session}"))
+    }
+
+    pub fn verify_csrf(&self, session: &str, token: &str) -> bool {
+        constant_time_eq(&self.csrf_token(session), token)
+    }
+}
+
+/// Whether the `Host` header names this machine: an IP address, `localhost`
+/// or one of the configured `names`. Requests for any other name reached the
+/// dashboard through DNS rebinding and are rejected.
+pub fn is_allowed_host(host: &str, names: &[String]) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(rest, |(name, _)| name),
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+
+    name.parse::<IpAddr>().is_ok()
+        || name.eq_ignore_ascii_case("localhost")
+        || names.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// Finds the value of the cookie called `name` in a `Cookie` header.
+pub fn cookie_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> Secret {
+        Secret("test-secret".to_string())
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn generated_secrets_differ() {
+        let a = Secret::generate().unwrap();
+        let b = Secret::generate().unwrap();
+        assert_eq!(a.as_str().len(), 43);
+        assert!(!a.matches(b.as_str()));
+        assert!(a.matches(a.as_str()));
+    }
+
+    #[test]
+    fn sessions_verify_until_they_expire() {
+        let secret = secret();
+        let token = secret.issue(Purpose::Session, at(1_000));
+
+        assert!(secret.verify(Purpose::Session, &token, at(1_000)));
+        assert!(secret.verify(
+            Purpose::Session,
+            &token,
+            at(1_000) + DASHBOARD_SESSION_LIFETIME - Duration::from_secs(1)
+        ));
+        assert!(!secret.verify(
+            Purpose::Session,
+            &token,
+            at(1_000) + DASHBOARD_SESSION_LIFETIME
+        ));
+        assert!(!secret.verify(Purpose::Session, &token, at(999)));
+    }
+
+    #[test]
+    fn tokens_are_bound_to_secret_and_purpose() {
+        let secret = secret();
+        let token = secret.issue(Purpose::LoginTicket, at(1_000));
+
+        assert!(secret.verify(Purpose::LoginTicket, &token, at(1_000)));
+        assert!(!secret.verify(Purpose::Session, &token, at(1_000)));
+        assert!(!Secret("other".to_string()).verify(Purpose::LoginTicket, &token, at(1_000)));
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let secret = secret();
+        let token = secret.issue(Purpose::Session, at(1_000));
+        let (_, signature) = token.split_once('.').unwrap();
+
+        assert!(!secret.verify(Purpose::Session, &format!("2000.{signature}"), at(2_000)));
+        assert!(!secret.verify(Purpose::Session, "garbage", at(1_000)));
+        assert!(!secret.verify(Purpose::Session, "", at(1_000)));
+    }
+
+    #[test]
+    fn csrf_token_is_bound_to_session() {
+        let secret = secret();
+        let session = secret.issue(Purpose::Session, at(1_000));
+        let other = secret.issue(Purpose::Session, at(2_000));
+        let csrf = secret.csrf_token(&session);
+
+        assert!(secret.verify_csrf(&session, &csrf));
+        assert!(!secret.verify_csrf(&other, &csrf));
+        assert!(!secret.verify_csrf(&session, ""));
+    }
+
+    #[test]
+    fn only_known_hosts_are_accepted() {
+        let names = ["nas.lan".to_string()];
+        assert!(is_allowed_host("127.0.0.1:18733", &[]));
+        assert!(is_allowed_host("localhost:18733", &[]));
+        assert!(is_allowed_host("LOCALHOST", &[]));
+        assert!(is_allowed_host("[::1]:18733", &[]));
+        assert!(is_allowed_host("192.168.1.10:18733", &[]));
+        assert!(is_allowed_host("NAS.lan:18733", &names));
+        assert!(!is_allowed_host("nas.lan:18733", &[]));
+        assert!(!is_allowed_host("evil.example:18733", &names));
+        assert!(!is_allowed_host("127.0.0.1.evil.example", &names));
+    }
+
+    #[test]
+    fn cookie_values_are_found_by_name() {
+        let header = "theme=dark; barvazdns_session=1.abc; other=x";
+        assert_eq!(cookie_value(header, "barvazdns_session"), Some("1.abc"));
+        assert_eq!(cookie_value(header, "missing"), None);
+    }
+}
@@ -15,30 +15,165 @@ function toggleDetail(el) {
   el.nextElementSibling.classList.toggle('open');
 }
 
+var csrfToken = null;
+var events = null;
+var live = false;
+var lastStatus = null;
+var lastConfig = null;
+var MAX_LOG_LINES = 1000;
+
+// Calls the dashboard API. Requests that change something carry the CSRF
+// token of the session, and an expired session brings back the sign-in form.
+async function api(url, options) {
+  options = options || {};
+  if (options.method && options.method !== 'GET') {
+    options.headers = Object.assign({ 'X-CSRF-Token': csrfToken || '' }, options.headers);
+  }
+  var res = await fetch(url, options);
+  if (res.status === 401) showLogin();
+  return res;
+}
+
+function showLogin() {
+  stopEvents();
+  document.getElementById('loginOverlay').classList.remove('hidden');
+  document.getElementById('btnSignOut').classList.add('hidden');
+  document.getElementById('secretInput').focus();
+}
+
+function startDashboard() {
+  document.getElementById('loginOverlay').classList.add('hidden');
+  document.getElementById('btnSignOut').classList.remove('hidden');
+  live = true;
+  connectEvents();
+}
+
+// Follows the service over server-sent events: the current status and
+// configuration first, then every change and log line as it happens. The
+// browser reconnects on its own when the stream drops, but gives up on an
+// error response, e.g. while the service is stopped, so those are retried
+// here.
+function connectEvents() {
+  if (events) events.close();
+  var source = new EventSource('/api/events');
+  events = source;
+  source.onopen = fetchLogs;
+  source.addEventListener('status', function(e) {
+    lastStatus = JSON.parse(e.data);
+    renderLatest();
+  });
+  source.addEventListener('config', function(e) {
+    lastConfig = JSON.parse(e.data);
+    renderLatest();
+  });
+  source.addEventListener('log', function(e) {
+    appendLogLine(JSON.parse(e.data).line);
+  });
+  source.onerror = function() {
+    if (source.readyState !== EventSource.CLOSED) return;
+    if (events === source) events = null;
+    // shows why it failed, or the sign-in form if the session expired
+    fetchData();
+    setTimeout(function() {
+      if (live && !events) connectEvents();
+    }, 5000);
+  };
+}
+
+function stopEvents() {
+  live = false;
+  if (events) {
+    events.close();
+    events = null;
+  }
+}
+
+async function signIn(body) {
+  clearFieldError('secret');
+  try {
+    var res = await fetch('/api/login', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    });
+    var data = await res.json();
+    if (data.ok) {
+      csrfToken = data.csrf_token;
+      startDashboard();
+      return true;
+    }
+    showFieldError('secret', data.error || 'Sign in failed');
+  } catch (e) {
+    showFieldError('secret', 'Cannot reach the dashboard');
+  }
+  return false;
+}
+
+async function login(event) {
+  event.preventDefault();
+  var input = document.getElementById('secretInput');
+  if (await signIn({ secret: input.value })) {
+    input.value = '';
+  }
+}
+
+async function logout() {
+  try {
+    await api('/api/logout', { method: 'POST' });
+  } catch (e) { /* ignore */ }
+  csrfToken = null;
+  showLogin();
+}
+
+// The tray icon opens the dashboard with a short-lived, single-use login ticket in the
+// URL fragment. Otherwise an existing session cookie is reused.
+async function init() {
+  var ticket = /^#ticket=(.+)$/.exec(location.hash);
+  if (ticket) {
+    history.replaceState(null, '', location.pathname);
+    if (await signIn({ ticket: decodeURIComponent(ticket[1]) })) return;
+  }
+  try {
+    var res = await fetch('/api/session');
+    if (res.ok) {
+      csrfToken = (await res.json()).csrf_token;
+      startDashboard();
+      return;
+    }
+  } catch (e) { /* fall through to the sign-in form */ }
+  showLogin();
+}
+
 async function fetchData() {
   try {
-    const statusRes = await fetch('/api/status');
+    const statusRes = await api('/api/status');
     if (!statusRes.ok) {
       if (statusRes.status === 503) {
         setBanner('error', 'Service is not running', '');
       }
       return;
     }
-    const configRes = await fetch('/api/config');
+    const configRes = await api('/api/config');
     if (!configRes.ok) {
       if (configRes.status === 503) {
         setBanner('error', 'Service is not running', '');
       }
       return;
     }
-    const status = await statusRes.json();
-    const config = await configRes.json();
-    render(status, config);
+    lastStatus = await statusRes.json();
+    lastConfig = await configRes.json();
+    renderLatest();
   } catch (e) {
     setBanner('error', 'Cannot reach service', '');
   }
 }
 
+var ipv6Enabled = false;
+
+function renderLatest() {
+  if (lastStatus && lastConfig) render(lastStatus, lastConfig);
+}
+
 function render(status, config) {
   document.getElementById('version').textContent = 'v' + (config.version || '?');
 
@@ -49,7 +184,13 @@ function render(status, config) {
     status.updated_domains.forEach(function(dd) { updatedSet[dd] = true; });
   }
 
-  if (hasSuccess) {
+  var outcomes = status.domains || {};
+  if (status.consecutive_failures > 0) {
+    var attempt = status.last_attempt ? new Date(status.last_attempt) : null;
+    var since = hasSuccess ? ' \u2014 last success ' + timeAgo(new Date(status.last_update)) : '';
+    setBanner('error', 'Last ' + status.consecutive_failures + ' update attempt(s) failed' + since,
+      attempt ? formatDate(attempt) : '', status.last_error);
+  } else if (hasSuccess) {
     var d = new Date(status.last_update);
     var ago = timeAgo(d);
     var failed = domains.filter(function(dd) { return !updatedSet[dd]; });
@@ -66,7 +207,10 @@ function render(status, config) {
   document.getElementById('ipv6').textContent = config.ipv6 ? 'Enabled' : 'Disabled';
   document.getElementById('token').textContent = config.token_set ? '\u2022\u2022\u2022\u2022\u2022\u2022\u2022\u2022' : 'Not set';
 
-  document.getElementById('domainCount').textContent = domains.length + ' / 5';
+  document.getElementById('domainCount').textContent = domains.length + ' / ' + (config.max_domains || 5);
+
+  ipv6Enabled = !!config.ipv6;
+  document.querySelector('#btnIpv6 .btn-label').textContent = ipv6Enabled ? 'Disable IPv6' : 'Enable IPv6';
 
   // About
   document.getElementById('aboutDesc').textContent = config.description || '';
@@ -77,24 +221,31 @@ function render(status, config) {
     repoLink.href = config.repository;
   }
 
+  var suffix = config.provider === 'duckdns' ? '.duckdns.org' : '';
   var list = document.getElementById('domainList');
   if (domains.length === 0) {
     list.innerHTML = '<span class="no-domains">No domains configured</span>';
   } else {
     list.innerHTML = domains.map(function(d) {
-      var failed = hasSuccess && !updatedSet[d];
-      return '<li' + (failed ? ' class="domain-failed"' : '') + '>' +
-        escHtml(d) + '<span class="suffix">.duckdns.org</span>' +
-        (failed ? '<span class="domain-error" title="Not in last successful update">\u26a0</span>' : '') +
+      var outcome = outcomes[d];
+      var error = outcome && outcome.status === 'failed' ? outcome.error : null;
+      if (!error && hasSuccess && !updatedSet[d]) {
+        error = 'Not in last successful update';
+      }
+      return '<li' + (error ? ' class="domain-failed"' : '') + '>' +
+        escHtml(d) + '<span class="suffix">' + suffix + '</span>' +
+        (error ? '<span class="domain-error" title="' + escHtml(error).replace(/"/g, '&quot;') + '">\u26a0</span>' : '') +
+        '<button class="domain-remove" title="Remove" data-domain="' + escHtml(d).replace(/"/g, '&quot;') + '">\u00d7</button>' +
         '</li>';
     }).join('');
   }
 }
 
-function setBanner(cls, text, time) {
+function setBanner(cls, text, time, detail) {
   const b = document.getElementById('statusBanner');
   b.className = 'status-banner ' + cls;
-  document.getElementById('statusText').innerHTML = '<strong>' + escHtml(text) + '</strong>';
+  document.getElementById('statusText').innerHTML = '<strong>' + escHtml(text) + '</strong>' +
+    (detail ? '<div class="status-detail">' + escHtml(detail) + '</div>' : '');
   document.getElementById('statusTime').textContent = time;
 }
 
@@ -103,11 +254,10 @@ async function forceUpdate() {
   btn.classList.add('loading');
   btn.disabled = true;
   try {
-    const res = await fetch('/api/update', { method: 'POST' });
+    const res = await api('/api/update', { method: 'POST' });
     const data = await res.json();
     if (data.ok) {
       toast('Update succeeded');
-      fetchData();
     } else {
       toast(data.error || 'Update failed', 'error');
     }
@@ -119,12 +269,95 @@ async function forceUpdate() {
   }
 }
 
+function showFieldError(field, msg) {
+  var el = document.getElementById('error-' + field);
+  if (!el) {
+    toast(msg, 'error');
+    return;
+  }
+  el.textContent = msg;
+  el.classList.remove('hidden');
+}
+
+function clearFieldError(field) {
+  var el = document.getElementById('error-' + field);
+  if (el) el.classList.add('hidden');
+}
+
+// Sends a settings change. Rejected values are shown next to the input of
+// the field the service reported.
+async function sendSetting(method, url, body, field) {
+  clearFieldError(field);
+  try {
+    var options = { method: method };
+    if (body) {
+      options.headers = { 'Content-Type': 'application/json' };
+      options.body = JSON.stringify(body);
+    }
+    var res = await api(url, options);
+    var data = await res.json();
+    if (data.ok) {
+      return true;
+    }
+    showFieldError(data.field || field, data.error || 'Request failed');
+  } catch (e) {
+    toast('Request failed', 'error');
+  }
+  return false;
+}
+
+async function addDomain(event) {
+  event.preventDefault();
+  var input = document.getElementById('domainInput');
+  if (await sendSetting('POST', '/api/domains', { domain: input.value }, 'domain')) {
+    input.value = '';
+    toast('Domain added');
+  }
+}
+
+async function removeDomain(domain) {
+  if (!confirm('Remove ' + domain + '?')) return;
+  if (await sendSetting('DELETE', '/api/domains/' + encodeURIComponent(domain), null, 'domain')) {
+    toast('Domain removed');
+  }
+}
+
+async function saveInterval(event) {
+  event.preventDefault();
+  var input = document.getElementById('intervalInput');
+  if (await sendSetting('POST', '/api/interval', { interval: input.value }, 'interval')) {
+    input.value = '';
+    toast('Interval saved');
+  }
+}
+
+async function saveToken(event) {
+  event.preventDefault();
+  var input = document.getElementById('tokenInput');
+  if (await sendSetting('POST', '/api/token', { token: input.value }, 'token')) {
+    input.value = '';
+    toast('Token saved');
+  }
+}
+
+async function toggleIpv6() {
+  var enable = !ipv6Enabled;
+  if (await sendSetting('POST', '/api/ipv6', { enabled: enable }, 'ipv6')) {
+    toast(enable ? 'IPv6 enabled' : 'IPv6 disabled');
+  }
+}
+
+document.getElementById('domainList').addEventListener('click', function(event) {
+  var btn = event.target.closest('.domain-remove');
+  if (btn) removeDomain(btn.dataset.domain);
+});
+
 async function checkForUpdate() {
   var btn = document.getElementById('btnCheckUpdate');
   btn.classList.add('loading');
   btn.disabled = true;
   try {
-    var res = await fetch('/api/check-update');
+    var res = await api('/api/check-update');
     var data = await res.json();
     if (data.available) {
       document.getElementById('updateTag').textContent = data.tag;
@@ -201,7 +434,7 @@ function renderLogLine(parsed) {
 
 async function fetchLogs() {
   try {
-    var res = await fetch('/api/logs');
+    var res = await api('/api/logs');
     if (!res.ok) return;
     var data = await res.json();
     var viewer = document.getElementById('logViewer');
@@ -223,7 +456,18 @@ async function fetchLogs() {
   } catch (e) { /* ignore */ }
 }
 
-fetchData();
-fetchLogs();
-setInterval(fetchData, 10000);
-setInterval(fetchLogs, 10000);
+// Adds a line pushed by the service on top, where the newest entries are
+// shown, and drops the oldest ones beyond MAX_LOG_LINES.
+function appendLogLine(line) {
+  var viewer = document.getElementById('logViewer');
+  var empty = viewer.querySelector('.log-empty');
+  if (empty) empty.remove();
+  line.split('\n').forEach(function(part) {
+    viewer.insertBefore(renderLogLine(parseLogLine(part)), viewer.firstChild);
+  });
+  while (viewer.childElementCount > MAX_LOG_LINES) {
+    viewer.lastElementChild.remove();
+  }
+}
+
+init();
@@ -0,0 +1,211 @@
+//! Renders the service's metrics in the Prometheus text exposition format.
+
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use crate::common::metrics::{DomainMetrics, LATENCY_BUCKETS, Metrics};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// A counter kept for every domain: its name, help text and value.
+type Counter = (&'static str, &'static str, fn(&DomainMetrics) -> u64);
+
+fn seconds_since_epoch(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Escapes a label value, which is written between double quotes.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Renders `metrics`, with the uptime as of `now`.
+pub fn render(metrics: &Metrics, now: SystemTime) -> String {
+    let mut out = String::new();
+    let labels = |domain: &str, provider: &str| {
+        format!(
+            "domain=\"{}\",provider=\"{}\"",
+            escape(domain),
+            escape(provider)
+        )
+    };
+
+    let counters: [Counter; 4] = [
+        (
+            "barvazdns_update_attempts_total",
+            "Update attempts since the service started.",
+            |d| d.attempts,
+        ),
+        (
+            "barvazdns_update_successes_total",
+            "Update attempts that published the addresses or found them unchanged.",
+            |d| d.successes,
+        ),
+        (
+            "barvazdns_update_failures_total",
+            "Update attempts that failed.",
+            |d| d.failures,
+        ),
+        (
+            "barvazdns_ip_changes_total",
+            "Times the published addresses changed since the service started.",
+            |d| d.ip_changes,
+        ),
+    ];
+    for (name, help, value) in counters {
+        header(&mut out, name, "counter", help);
+        for domain in &metrics.domains {
+            let _ = writeln!(
+                out,
+                "{name}{{{}}} {}",
+                labels(&domain.domain, &domain.provider),
+                value(domain)
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "barvazdns_last_success_timestamp_seconds",
+        "gauge",
+        "Time of the last successful update attempt.",
+    );
+    for domain in &metrics.domains {
+        if let Some(time) = domain.last_success {
+            let _ = writeln!(
+                out,
+                "barvazdns_last_success_timestamp_seconds{{{}}} {}",
+                labels(&domain.domain, &domain.provider),
+                seconds_since_epoch(time)
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "barvazdns_published_address_info",
+        "gauge",
+        "The addresses each domain points to, as labels.",
+    );
+    for domain in &metrics.domains {
+        let addresses = [
+            ("ipv4", domain.ipv4.map(|a| a.to_string())),
+            ("ipv6", domain.ipv6.map(|a| a.to_string())),
+        ];
+        for (family, address) in addresses {
+            if let Some(address) = address {
+                let _ = writeln!(
+                    out,
+                    "barvazdns_published_address_info{{{},family=\"{family}\",address=\"{address}\"}} 1",
+                    labels(&domain.domain, &domain.provider)
+                );
+            }
+        }
+    }
+
+    header(
+        &mut out,
+        "barvazdns_update_duration_seconds",
+        "histogram",
+        "Duration of update attempts, including address discovery.",
+    );
+    for (provider, histogram) in &metrics.latency {
+        let provider = escape(provider);
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            let _ = writeln!(
+                out,
+                "barvazdns_update_duration_seconds_bucket{{provider=\"{provider}\",le=\"{bound}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "barvazdns_update_duration_seconds_bucket{{provider=\"{provider}\",le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "barvazdns_update_duration_seconds_sum{{provider=\"{provider}\"}} {}",
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "barvazdns_update_duration_seconds_count{{provider=\"{provider}\"}} {}",
+            histogram.count
+        );
+    }
+
+    header(
+        &mut out,
+        "barvazdns_uptime_seconds",
+        "gauge",
+        "Time since the service started.",
+    );
+    let uptime = now
+        .duration_since(metrics.started)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let _ = writeln!(out, "barvazdns_uptime_seconds {uptime}");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::history::{AttemptResult, HistoryEntry};
+    use std::time::Duration;
+
+    #[test]
+    fn renders_every_metric() {
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut metrics = Metrics::new(started);
+        metrics.record(&HistoryEntry {
+            time: started + Duration::from_secs(10),
+            provider: "duckdns".to_string(),
+            domains: vec!["home".to_string()],
+            ipv4: Some("1.2.3.4".parse().unwrap()),
+            ipv6: Some("2001:db8::1".parse().unwrap()),
+            result: AttemptResult::Published {
+                response: "OK".to_string(),
+            },
+            duration: Duration::from_millis(750),
+        });
+
+        let text = render(&metrics, started + Duration::from_secs(60));
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            r#"barvazdns_update_attempts_total{domain="home",provider="duckdns"} 1"#,
+            r#"barvazdns_update_successes_total{domain="home",provider="duckdns"} 1"#,
+            r#"barvazdns_update_failures_total{domain="home",provider="duckdns"} 0"#,
+            r#"barvazdns_ip_changes_total{domain="home",provider="duckdns"} 0"#,
+            r#"barvazdns_last_success_timestamp_seconds{domain="home",provider="duckdns"} 1010"#,
+            r#"barvazdns_published_address_info{domain="home",provider="duckdns",family="ipv4",address="1.2.3.4"} 1"#,
+            r#"barvazdns_published_address_info{domain="home",provider="duckdns",family="ipv6",address="2001:db8::1"} 1"#,
+            r#"barvazdns_update_duration_seconds_bucket{provider="duckdns",le="0.5"} 0"#,
+            r#"barvazdns_update_duration_seconds_bucket{provider="duckdns",le="1"} 1"#,
+            r#"barvazdns_update_duration_seconds_bucket{provider="duckdns",le="+Inf"} 1"#,
+            r#"barvazdns_update_duration_seconds_sum{provider="duckdns"} 0.75"#,
+            r#"barvazdns_update_duration_seconds_count{provider="duckdns"} 1"#,
+            "# TYPE barvazdns_update_duration_seconds histogram",
+            "barvazdns_uptime_seconds 60",
+        ] {
+            assert!(lines.contains(&expected), "missing {expected}\n{text}");
+        }
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape("a\nb"), r"a\nb");
+    }
+}
@@ -1,71 +1,272 @@
-use std::sync::OnceLock;
+pub mod auth;
+mod metrics;
+mod tls;
 
-use axum::http::{StatusCode, header};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::{self, Next};
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use tokio::sync::oneshot;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use tokio::sync::{Notify, oneshot};
 
-use crate::common::config::Config;
-use crate::common::consts::WEB_DASHBOARD_PORT;
-use crate::common::message::{Request, Response};
+use self::auth::{Purpose, Secret};
+use self::tls::{DashboardListener, Peer};
+use crate::common::config::{Config, ServiceConfig};
+use crate::common::consts::{
+    DASHBOARD_LOGIN_TICKET_LIFETIME, DASHBOARD_SESSION_LIFETIME, WEB_DASHBOARD_PORT,
+};
+use crate::common::history::AttemptResult;
+use crate::common::message::{self, DomainOutcome, Event, Request, Response, Token, UpdateStatus};
 use crate::common::strings::{
-    AUTHORS, DESCRIPTION, LICENSE, LOG_FILE_BASENAME, REPOSITORY, VERSION,
+    AUTHORS, DASHBOARD_SESSION_COOKIE, DESCRIPTION, LICENSE, LOG_FILE_BASENAME, REPOSITORY, VERSION,
 };
 use crate::common::version_check;
 
 const DASHBOARD_HTML: &str = include_str!("dashboard.html");
 const DASHBOARD_CSS: &str = include_str!("style.css");
 const DASHBOARD_JS: &str = include_str!("dashboard.js");
+const HISTORY_LIMIT: usize = 50;
 
 static SHUTDOWN_TX: OnceLock<tokio::sync::Mutex<Option<oneshot::Sender<()>>>> = OnceLock::new();
+/// Ends the open event streams, which would otherwise hold up a reload.
+static EVENT_STREAMS_CLOSING: Notify = Notify::const_new();
 
-fn read_port_from_config() -> u16 {
+fn read_config() -> Option<Config> {
     Config::get_config_file_path()
         .ok()
         .and_then(|p| std::fs::read_to_string(p).ok())
         .and_then(|s| toml::from_str::<Config>(&s).ok())
-        .map(|c| c.effective_dashboard_port())
-        .unwrap_or(WEB_DASHBOARD_PORT)
 }
 
-pub fn router() -> Router {
+/// What the request guard checks against.
+struct DashboardState {
+    secret: Secret,
+    /// Host names the dashboard may be reached by, besides IP addresses and
+    /// `localhost`.
+    hosts: Vec<String>,
+    /// Login tickets already exchanged for a session, with the time they
+    /// expire, so that each ticket signs in only once.
+    redeemed_tickets: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl DashboardState {
+    /// Marks `ticket` as used, returning false if it already was.
+    fn redeem_ticket(&self, ticket: &str, now: SystemTime) -> bool {
+        let mut redeemed = self
+            .redeemed_tickets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        redeemed.retain(|_, expires| *expires > now);
+        redeemed
+            .insert(ticket.to_string(), now + DASHBOARD_LOGIN_TICKET_LIFETIME)
+            .is_none()
+    }
+}
+
+pub fn router(secret: Secret, hosts: Vec<String>) -> Router {
+    let state = Arc::new(DashboardState {
+        secret,
+        hosts,
+        redeemed_tickets: Mutex::new(HashMap::new()),
+    });
+
     Router::new()
         .route("/", get(dashboard))
         .route("/style.css", get(stylesheet))
         .route("/dashboard.js", get(script))
         .route("/api/status", get(api_status))
         .route("/api/config", get(api_config))
+        .route("/api/history", get(api_history))
+        .route("/api/events", get(api_events))
         .route("/api/update", post(api_force_update))
         .route("/api/check-update", get(api_check_update))
         .route("/api/logs", get(api_logs))
         .route("/api/reload", post(api_reload))
+        .route("/api/domains", post(api_add_domain))
+        .route("/api/domains/{domain}", delete(api_remove_domain))
+        .route("/api/token", post(api_set_token))
+        .route("/api/interval", post(api_set_interval))
+        .route("/api/ipv6", post(api_set_ipv6))
+        .route("/api/login", post(api_login))
+        .route("/api/logout", post(api_logout))
+        .route("/api/session", get(api_session))
+        .route("/metrics", get(prometheus_metrics))
+        .layer(middleware::from_fn_with_state(state.clone(), guard))
+        .with_state(state)
+}
+
+/// Whether a request comes from the dashboard itself rather than from another
+/// site open in the browser. Browsers send `Origin` on cross-origin requests
+/// and `Sec-Fetch-Site` on all of them; clients such as curl send neither.
+fn is_same_origin(headers: &HeaderMap) -> bool {
+    let value = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(site) = value(header::HeaderName::from_static("sec-fetch-site"))
+        && site != "same-origin"
+        && site != "none"
+    {
+        return false;
+    }
+
+    match value(header::ORIGIN) {
+        None => true,
+        Some(origin) => {
+            let origin = origin
+                .strip_prefix("http://")
+                .or_else(|| origin.strip_prefix("https://"));
+            matches!(
+                (origin, value(header::HOST)),
+                (Some(origin), Some(host)) if origin.eq_ignore_ascii_case(host)
+            )
+        }
+    }
+}
+
+/// How a request proved it may use the API.
+enum Credentials {
+    /// The install secret as a bearer token, as sent by scripts and the CLI.
+    Bearer,
+    /// A session cookie, as sent by the browser after logging in.
+    Session(String),
+}
+
+fn credentials(secret: &Secret, headers: &HeaderMap, now: SystemTime) -> Option<Credentials> {
+    let value = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(token) = value(header::AUTHORIZATION).and_then(|v| v.strip_prefix("Bearer "))
+        && secret.matches(token.trim())
+    {
+        return Some(Credentials::Bearer);
+    }
+
+    value(header::COOKIE)
+        .and_then(|cookies| auth::cookie_value(cookies, DASHBOARD_SESSION_COOKIE))
+        .filter(|session| secret.verify(Purpose::Session, session, now))
+        .map(|session| Credentials::Session(session.to_string()))
+}
+
+fn rejection(status: StatusCode, error: &str) -> axum::response::Response {
+    (
+        status,
+        Json(serde_json::json!({ "ok": false, "error": error })),
+    )
+        .into_response()
+}
+
+/// Checks every request before it reaches a handler:
+///
+/// * the `Host` header must name this machine, which defeats DNS rebinding;
+/// * API calls other than logging in, and the metrics, need the bearer secret
+///   or a session;
+/// * requests that change something must come from the dashboard itself and,
+///   when authenticated by a cookie, carry the session's CSRF token.
+async fn guard(
+    State(state): State<Arc<DashboardState>>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let secret = &state.secret;
+    let headers = request.headers();
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    if !host.is_some_and(|host| auth::is_allowed_host(host, &state.hosts)) {
+        return rejection(StatusCode::FORBIDDEN, "Unknown host");
+    }
+
+    let path = request.uri().path();
+    let credentials = credentials(secret, headers, SystemTime::now());
+    let protected = (path.starts_with("/api/") && path != "/api/login") || path == "/metrics";
+    if protected && credentials.is_none() {
+        return rejection(StatusCode::UNAUTHORIZED, "Authentication required");
+    }
+
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        if !is_same_origin(headers) {
+            return rejection(
+                StatusCode::FORBIDDEN,
+                "Cross-origin requests are not allowed",
+            );
+        }
+
+        if let Some(Credentials::Session(session)) = &credentials {
+            let token = headers
+                .get("x-csrf-token")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            if !secret.verify_csrf(session, token) {
+                return rejection(StatusCode::FORBIDDEN, "Missing or invalid CSRF token");
+            }
+        }
+    }
+
+    next.run(request).await
 }
 
 pub async fn start() {
     let _ = SHUTDOWN_TX.get_or_init(|| tokio::sync::Mutex::new(None));
 
     loop {
-        let port = read_port_from_config();
-        let app = router();
-        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
-        let listener = match tokio::net::TcpListener::bind(addr).await {
+        let config = read_config();
+        let port = config
+            .as_ref()
+            .map_or(WEB_DASHBOARD_PORT, Config::effective_dashboard_port);
+        let dashboard = config.and_then(|c| c.dashboard).unwrap_or_default();
+
+        // read on every start, so that rotating the secret takes effect on reload
+        let secret = match Secret::load_or_create() {
+            Ok(secret) => secret,
+            Err(e) => {
+                log::error!("Failed to load the dashboard secret: {e}");
+                return;
+            }
+        };
+
+        // anything reachable from the network is only served over HTTPS
+        let tls = if dashboard.is_exposed() {
+            match tls::acceptor(&dashboard) {
+                Ok(acceptor) => Some(acceptor),
+                Err(e) => {
+                    log::error!("Failed to set up HTTPS for the web dashboard: {e}");
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        let scheme = if tls.is_some() { "https" } else { "http" };
+
+        let app = router(secret, dashboard.hosts.clone());
+        let addr = std::net::SocketAddr::new(dashboard.bind_address(), port);
+        let listener = match tokio::net::TcpListener::bind(addr)
+            .await
+            .and_then(|l| DashboardListener::new(l, tls))
+        {
             Ok(l) => l,
             Err(e) => {
                 log::error!("Failed to bind web server on {addr}: {e}");
                 return;
             }
         };
-        log::info!("Web dashboard listening on http://{addr}");
+        log::info!("Web dashboard listening on {scheme}://{addr}");
 
         let (tx, rx) = oneshot::channel::<()>();
         if let Some(lock) = SHUTDOWN_TX.get() {
             *lock.lock().await = Some(tx);
         }
 
-        if let Err(e) = axum::serve(listener, app)
+        if let Err(e) = axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
             .with_graceful_shutdown(async {
                 rx.await.ok();
+                EVENT_STREAMS_CLOSING.notify_waiters();
             })
             .await
         {
@@ -104,26 +305,61 @@ async fn script() -> impl IntoResponse {
     )
 }
 
+fn status_json(status: &UpdateStatus) -> serde_json::Value {
+    let to_millis = |t: std::time::SystemTime| {
+        t.duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    };
+    let (last_update, updated_domains) = match &status.last_success {
+        Some((t, domains)) => (Some(to_millis(*t)), Some(domains.clone())),
+        None => (None, None),
+    };
+    let domains: serde_json::Map<String, serde_json::Value> = status
+        .domains
+        .iter()
+        .map(|(domain, outcome)| {
+            let value = match outcome {
+                DomainOutcome::Updated => serde_json::json!({ "status": "updated" }),
+                DomainOutcome::Unchanged => serde_json::json!({ "status": "unchanged" }),
+                DomainOutcome::Failed(e) => {
+                    serde_json::json!({ "status": "failed", "error": e })
+                }
+            };
+            (domain.clone(), value)
+        })
+        .collect();
+    serde_json::json!({
+        "last_update": last_update,
+        "updated_domains": updated_domains,
+        "last_attempt": status.last_attempt.map(to_millis),
+        "last_error": status.last_error,
+        "consecutive_failures": status.consecutive_failures,
+        "domains": domains,
+    })
+}
+
+fn config_json(config: &ServiceConfig) -> serde_json::Value {
+    let domains: Vec<&String> = config.domain.iter().collect();
+    serde_json::json!({
+        "version": VERSION,
+        "authors": AUTHORS,
+        "description": DESCRIPTION,
+        "license": LICENSE,
+        "repository": REPOSITORY,
+        "provider": config.provider.to_string(),
+        "interval": humantime::format_duration(config.interval).to_string(),
+        "refresh_interval": humantime::format_duration(config.refresh_interval).to_string(),
+        "ipv6": config.ipv6 == Some(true),
+        "token_set": config.token.is_some(),
+        "domains": domains,
+        "max_domains": config.max_domains,
+    })
+}
+
 async fn api_status() -> impl IntoResponse {
     match Request::GetStatus.send().await {
-        Ok(Response::Status(status)) => {
-            let to_millis = |t: std::time::SystemTime| {
-                t.duration_since(std::time::SystemTime::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_millis() as u64
-            };
-            let (last_update, updated_domains) = match &status.last_success {
-                Some((t, domains)) => (Some(to_millis(*t)), Some(domains.clone())),
-                None => (None, None),
-            };
-            (
-                StatusCode::OK,
-                Json(serde_json::json!({
-                    "last_update": last_update,
-                    "updated_domains": updated_domains,
-                })),
-            )
-        }
+        Ok(Response::Status(status)) => (StatusCode::OK, Json(status_json(&status))),
         Ok(Response::Err(e)) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": e })),
@@ -141,21 +377,112 @@ async fn api_status() -> impl IntoResponse {
 
 async fn api_config() -> impl IntoResponse {
     match Request::GetConfig.send().await {
-        Ok(Response::Config(config)) => {
-            let domains: Vec<&String> = config.domain.iter().collect();
+        Ok(Response::Config(config)) => (StatusCode::OK, Json(config_json(&config))),
+        Ok(Response::Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e })),
+        ),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        ),
+        _ => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": "unexpected response" })),
+        ),
+    }
+}
+
+/// An event from the service as the dashboard receives it: named after what
+/// changed, with the same JSON as the matching endpoint.
+fn sse_event(event: Event) -> sse::Event {
+    let (name, data) = match event {
+        Event::Status(status) => ("status", status_json(&status)),
+        Event::Config(config) => ("config", config_json(&config)),
+        Event::Log(line) => ("log", serde_json::json!({ "line": line })),
+    };
+    sse::Event::default().event(name).data(data.to_string())
+}
+
+/// Streams changes in the service as server-sent events, starting with the
+/// current status and configuration. The stream ends when the service stops.
+async fn api_events() -> axum::response::Response {
+    let subscription = match message::subscribe().await {
+        Ok(subscription) => subscription,
+        Err(e) => return rejection(StatusCode::SERVICE_UNAVAILABLE, &e.to_string()),
+    };
+
+    // registered right away, so that a reload that starts before the stream
+    // is first polled still ends it
+    let mut closing = Box::pin(EVENT_STREAMS_CLOSING.notified());
+    closing.as_mut().enable();
+
+    let events = futures_util::stream::unfold(subscription, |mut subscription| async move {
+        let event = subscription.next().await.ok().flatten()?;
+        Some((Ok::<_, Infallible>(sse_event(event)), subscription))
+    })
+    .take_until(closing);
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Exposes the service's metrics to Prometheus.
+async fn prometheus_metrics() -> impl IntoResponse {
+    match Request::GetMetrics.send().await {
+        Ok(Response::Metrics(m)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+            metrics::render(&m, SystemTime::now()),
+        ),
+        Ok(Response::Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+            e,
+        ),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+            e.to_string(),
+        ),
+        _ => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+            "unexpected response".to_string(),
+        ),
+    }
+}
+
+async fn api_history() -> impl IntoResponse {
+    match Request::GetHistory(HISTORY_LIMIT).send().await {
+        Ok(Response::History(entries)) => {
+            let entries: Vec<serde_json::Value> = entries
+                .iter()
+                .map(|entry| {
+                    let (status, detail) = match &entry.result {
+                        AttemptResult::Published { response } => ("published", Some(response)),
+                        AttemptResult::Unchanged => ("unchanged", None),
+                        AttemptResult::Failed { error } => ("failed", Some(error)),
+                    };
+                    serde_json::json!({
+                        "time": entry
+                            .time
+                            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_millis() as u64,
+                        "provider": entry.provider,
+                        "domains": entry.domains,
+                        "ipv4": entry.ipv4,
+                        "ipv6": entry.ipv6,
+                        "status": status,
+                        "detail": detail,
+                        "duration_ms": entry.duration.as_millis() as u64,
+                    })
+                })
+                .collect();
             (
                 StatusCode::OK,
-                Json(serde_json::json!({
-                    "version": VERSION,
-                    "authors": AUTHORS,
-                    "description": DESCRIPTION,
-                    "license": LICENSE,
-                    "repository": REPOSITORY,
-                    "interval": humantime::format_duration(config.interval).to_string(),
-                    "ipv6": config.ipv6 == Some(true),
-                    "token_set": config.token.is_some(),
-                    "domains": domains,
-                })),
+                Json(serde_json::json!({ "entries": entries })),
             )
         }
         Ok(Response::Err(e)) => (
@@ -182,6 +509,183 @@ async fn api_force_update() -> impl IntoResponse {
     }
 }
 
+type ApiResult = (StatusCode, Json<serde_json::Value>);
+
+/// A rejected setting, reported with the `field` it concerns so the dashboard
+/// can show the error next to the matching input.
+fn invalid(field: &str, error: impl std::fmt::Display) -> ApiResult {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(serde_json::json!({ "ok": false, "field": field, "error": error.to_string() })),
+    )
+}
+
+/// Sends a configuration change to the service, which validates and stores it.
+async fn change_setting(field: &str, request: Request) -> ApiResult {
+    match request.send().await {
+        Ok(Response::Ok) => (StatusCode::OK, Json(serde_json::json!({ "ok": true }))),
+        Ok(Response::Err(e)) => invalid(field, e),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "ok": false, "error": e.to_string() })),
+        ),
+        _ => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "ok": false, "error": "unexpected response" })),
+        ),
+    }
+}
+
+/// Parses a JSON body, reporting a malformed one as a rejected `field`.
+fn parse_body<T>(field: &str, body: Result<Json<T>, JsonRejection>) -> Result<T, ApiResult> {
+    match body {
+        Ok(Json(value)) => Ok(value),
+        Err(e) => Err((
+            e.status(),
+            Json(serde_json::json!({ "ok": false, "field": field, "error": e.body_text() })),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct LoginBody {
+    #[serde(default)]
+    secret: Option<String>,
+    #[serde(default)]
+    ticket: Option<String>,
+}
+
+fn session_cookie(value: &str, max_age: u64, peer: &Peer) -> String {
+    let secure = if peer.tls { "; Secure" } else { "" };
+    format!(
+        "{DASHBOARD_SESSION_COOKIE}={value}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Strict{secure}"
+    )
+}
+
+/// Exchanges the install secret, or a single-use login ticket from the tray
+/// icon, for a session cookie.
+async fn api_login(
+    State(state): State<Arc<DashboardState>>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    body: Result<Json<LoginBody>, JsonRejection>,
+) -> axum::response::Response {
+    let secret = &state.secret;
+    let body = match parse_body("secret", body) {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+
+    let now = SystemTime::now();
+    let valid = match (&body.secret, &body.ticket) {
+        (Some(candidate), _) => secret.matches(candidate.trim()),
+        (None, Some(ticket)) => {
+            secret.verify(Purpose::LoginTicket, ticket, now) && state.redeem_ticket(ticket, now)
+        }
+        (None, None) => false,
+    };
+    if !valid {
+        log::warn!("Rejected a dashboard sign-in from {}", peer.address.ip());
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "ok": false, "field": "secret", "error": "Invalid secret" })),
+        )
+            .into_response();
+    }
+
+    let session = secret.issue(Purpose::Session, now);
+    (
+        [(
+            header::SET_COOKIE,
+            session_cookie(&session, DASHBOARD_SESSION_LIFETIME.as_secs(), &peer),
+        )],
+        Json(serde_json::json!({ "ok": true, "csrf_token": secret.csrf_token(&session) })),
+    )
+        .into_response()
+}
+
+async fn api_logout(ConnectInfo(peer): ConnectInfo<Peer>) -> impl IntoResponse {
+    (
+        [(header::SET_COOKIE, session_cookie("", 0, &peer))],
+        Json(serde_json::json!({ "ok": true })),
+    )
+}
+
+/// Returns the CSRF token of the current session, so that a reloaded page can
+/// keep using it. Bearer clients need none.
+async fn api_session(
+    State(state): State<Arc<DashboardState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let csrf_token = match credentials(&state.secret, &headers, SystemTime::now()) {
+        Some(Credentials::Session(session)) => Some(state.secret.csrf_token(&session)),
+        _ => None,
+    };
+    Json(serde_json::json!({ "ok": true, "csrf_token": csrf_token }))
+}
+
+#[derive(Deserialize)]
+struct DomainBody {
+    domain: String,
+}
+
+#[derive(Deserialize)]
+struct TokenBody {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct IntervalBody {
+    interval: String,
+}
+
+#[derive(Deserialize)]
+struct Ipv6Body {
+    enabled: bool,
+}
+
+async fn api_add_domain(body: Result<Json<DomainBody>, JsonRejection>) -> ApiResult {
+    let body = match parse_body("domain", body) {
+        Ok(body) => body,
+        Err(e) => return e,
+    };
+    change_setting("domain", Request::AddDomain(body.domain.trim().to_string())).await
+}
+
+async fn api_remove_domain(Path(domain): Path<String>) -> ApiResult {
+    change_setting("domain", Request::RemoveDomain(domain)).await
+}
+
+async fn api_set_token(body: Result<Json<TokenBody>, JsonRejection>) -> ApiResult {
+    let body = match parse_body("token", body) {
+        Ok(body) => body,
+        Err(e) => return e,
+    };
+    let token = body.token.trim();
+    if token.is_empty() {
+        return invalid("token", "The token cannot be empty");
+    }
+    change_setting("token", Request::Token(Token::new(token.to_string()))).await
+}
+
+async fn api_set_interval(body: Result<Json<IntervalBody>, JsonRejection>) -> ApiResult {
+    let body = match parse_body("interval", body) {
+        Ok(body) => body,
+        Err(e) => return e,
+    };
+    match humantime::parse_duration(body.interval.trim()) {
+        Ok(interval) => change_setting("interval", Request::Interval(interval)).await,
+        Err(e) => invalid("interval", format!("Invalid interval: {e}")),
+    }
+}
+
+async fn api_set_ipv6(body: Result<Json<Ipv6Body>, JsonRejection>) -> ApiResult {
+    let body = match parse_body("ipv6", body) {
+        Ok(body) => body,
+        Err(e) => return e,
+    };
+    change_setting("ipv6", Request::Ipv6(body.enabled)).await
+}
+
 async fn api_check_update() -> impl IntoResponse {
     let result = tokio::task::spawn_blocking(version_check::check_for_update).await;
     match result {
@@ -251,3 +755,162 @@ async fn api_reload() -> impl IntoResponse {
     }
     Json(serde_json::json!({ "ok": false, "error": "no active server" }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn requests_without_browser_headers_are_allowed() {
+        assert!(is_same_origin(&headers(&[("host", "127.0.0.1:18733")])));
+    }
+
+    #[test]
+    fn same_origin_requests_are_allowed() {
+        assert!(is_same_origin(&headers(&[
+            ("host", "127.0.0.1:18733"),
+            ("origin", "http://127.0.0.1:18733"),
+            ("sec-fetch-site", "same-origin"),
+        ])));
+    }
+
+    #[test]
+    fn cross_origin_requests_are_rejected() {
+        assert!(!is_same_origin(&headers(&[
+            ("host", "127.0.0.1:18733"),
+            ("origin", "http://evil.example"),
+        ])));
+        assert!(!is_same_origin(&headers(&[
+            ("host", "127.0.0.1:18733"),
+            ("sec-fetch-site", "cross-site"),
+        ])));
+        assert!(!is_same_origin(&headers(&[
+            ("host", "127.0.0.1:18733"),
+            ("origin", "null"),
+        ])));
+    }
+
+    /// Serves the router on an ephemeral port and returns its address.
+    async fn serve(secret: Secret) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = DashboardListener::new(listener, None).unwrap();
+        let app = router(secret, Vec::new()).into_make_service_with_connect_info::<Peer>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    /// Sends a request, returning its status, `Set-Cookie` header and body.
+    async fn send(request: minreq::Request) -> (i32, Option<String>, serde_json::Value) {
+        tokio::task::spawn_blocking(move || {
+            let response = request.with_timeout(5).send().unwrap();
+            let cookie = response.headers.get("set-cookie").cloned();
+            let body = serde_json::from_str(response.as_str().unwrap()).unwrap_or_default();
+            (response.status_code, cookie, body)
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn api_requires_credentials() {
+        let secret = Secret::generate().unwrap();
+        let bearer = format!("Bearer {}", secret.as_str());
+        let addr = serve(secret).await;
+        let url = format!("http://{addr}/api/session");
+
+        assert_eq!(send(minreq::get(&url)).await.0, 401);
+        let wrong = minreq::get(&url).with_header("Authorization", "Bearer wrong");
+        assert_eq!(send(wrong).await.0, 401);
+        let valid = minreq::get(&url).with_header("Authorization", &bearer);
+        assert_eq!(send(valid).await.0, 200);
+        let scrape = minreq::get(format!("http://{addr}/metrics"));
+        assert_eq!(send(scrape).await.0, 401);
+
+        // minreq always sends its own `Host`, so write this request by hand
+        let rebound = tokio::task::spawn_blocking(move || {
+            use std::io::{Read, Write};
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "GET /api/session HTTP/1.1\r\nHost: rebound.example\r\n\
+                 Authorization: {bearer}\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        assert!(rebound.starts_with("HTTP/1.1 403"));
+    }
+
+    #[tokio::test]
+    async fn session_cookie_needs_csrf_token_for_changes() {
+        let secret = Secret::generate().unwrap();
+        let login = serde_json::json!({ "secret": secret.as_str() }).to_string();
+        let addr = serve(secret).await;
+
+        let wrong_login = minreq::post(format!("http://{addr}/api/login"))
+            .with_header("Content-Type", "application/json")
+            .with_body(r#"{"secret": "wrong"}"#);
+        assert_eq!(send(wrong_login).await.0, 401);
+
+        let (status, cookie, body) = send(
+            minreq::post(format!("http://{addr}/api/login"))
+                .with_header("Content-Type", "application/json")
+                .with_body(login),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let cookie = cookie.unwrap();
+        assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Strict"));
+        let session = cookie.split(';').next().unwrap().to_string();
+        let csrf = body["csrf_token"].as_str().unwrap().to_string();
+
+        let logout =
+            || minreq::post(format!("http://{addr}/api/logout")).with_header("Cookie", &session);
+        assert_eq!(send(logout()).await.0, 403);
+        assert_eq!(
+            send(logout().with_header("X-CSRF-Token", "forged")).await.0,
+            403
+        );
+        let cross_site = logout()
+            .with_header("X-CSRF-Token", &csrf)
+            .with_header("Origin", "http://evil.example");
+        assert_eq!(send(cross_site).await.0, 403);
+        assert_eq!(
+            send(logout().with_header("X-CSRF-Token", &csrf)).await.0,
+            200
+        );
+    }
+
+    #[tokio::test]
+    async fn login_ticket_is_single_use() {
+        let secret = Secret::generate().unwrap();
+        let ticket = secret.issue(Purpose::LoginTicket, SystemTime::now());
+        let addr = serve(secret).await;
+
+        let login = || {
+            minreq::post(format!("http://{addr}/api/login"))
+                .with_header("Content-Type", "application/json")
+                .with_body(serde_json::json!({ "ticket": ticket }).to_string())
+        };
+        assert_eq!(send(login()).await.0, 200);
+        assert_eq!(send(login()).await.0, 401);
+    }
+}
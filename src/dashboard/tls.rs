@@ -0,0 +1,431 @@
+//! Serves the dashboard over HTTPS when it listens on a network address.
+//!
+//! A single port accepts both TLS and plain HTTP, told apart by the first byte
+//! of the connection. Plain HTTP is only accepted from this machine, so the
+//! tray icon and the CLI keep working without trusting the certificate.
+
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use rustls_pemfile::Item;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::server::TlsStream;
+
+use crate::common::config::{Config, DashboardConfig};
+use crate::common::consts::DASHBOARD_HANDSHAKE_TIMEOUT;
+use crate::common::files;
+use crate::common::strings::{DASHBOARD_CERT_FILE_NAME, DASHBOARD_KEY_FILE_NAME};
+
+/// The first byte of every TLS connection: a handshake record.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+const PENDING_CONNECTIONS: usize = 64;
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+const PLAIN_HTTP_REFUSED: &str = "This dashboard is served over HTTPS.\n";
+
+/// The remote end of a dashboard connection.
+#[derive(Clone, Copy, Debug)]
+pub struct Peer {
+    pub address: SocketAddr,
+    /// Whether the connection is encrypted, which decides if the session
+    /// cookie is marked `Secure`.
+    pub tls: bool,
+}
+
+impl Connected<IncomingStream<'_, DashboardListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, DashboardListener>) -> Self {
+        *stream.remote_addr()
+    }
+}
+
+pub enum Connection {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Accepts dashboard connections, completing TLS handshakes in the background
+/// so that a slow client cannot hold up the others.
+pub struct DashboardListener {
+    connections: mpsc::Receiver<(Connection, Peer)>,
+    local_address: SocketAddr,
+    acceptor: JoinHandle<()>,
+}
+
+impl DashboardListener {
+    /// Accepts connections on `listener`. With `tls`, connections from other
+    /// machines have to use it.
+    pub fn new(listener: TcpListener, tls: Option<TlsAcceptor>) -> io::Result<Self> {
+        let local_address = listener.local_addr()?;
+        let (sender, connections) = mpsc::channel(PENDING_CONNECTIONS);
+        let acceptor = tokio::spawn(accept_loop(listener, tls, sender));
+        Ok(Self {
+            connections,
+            local_address,
+            acceptor,
+        })
+    }
+}
+
+impl Drop for DashboardListener {
+    fn drop(&mut self) {
+        // releases the port, so that a reloaded dashboard can bind it again
+        self.acceptor.abort();
+    }
+}
+
+impl Listener for DashboardListener {
+    type Io = Connection;
+    type Addr = Peer;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(Peer {
+            address: self.local_address,
+            tls: false,
+        })
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    sender: mpsc::Sender<(Connection, Peer)>,
+) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                log::debug!("Failed to accept a dashboard connection: {e}");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        let tls = tls.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Some(connection) = handshake(stream, address, tls).await {
+                let _ = sender.send(connection).await;
+            }
+        });
+    }
+}
+
+/// Completes the TLS handshake, or lets plain HTTP through when TLS is off or
+/// the connection comes from this machine.
+async fn handshake(
+    stream: TcpStream,
+    address: SocketAddr,
+    tls: Option<TlsAcceptor>,
+) -> Option<(Connection, Peer)> {
+    let plain = |stream| {
+        let peer = Peer {
+            address,
+            tls: false,
+        };
+        Some((Connection::Plain(stream), peer))
+    };
+    let Some(acceptor) = tls else {
+        return plain(stream);
+    };
+
+    let mut first = [0u8; 1];
+    let peeked = timeout(DASHBOARD_HANDSHAKE_TIMEOUT, stream.peek(&mut first)).await;
+    if !matches!(peeked, Ok(Ok(1))) {
+        return None;
+    }
+
+    if first[0] == TLS_HANDSHAKE_RECORD {
+        return match timeout(DASHBOARD_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => {
+                let peer = Peer { address, tls: true };
+                Some((Connection::Tls(Box::new(stream)), peer))
+            }
+            Ok(Err(e)) => {
+                log::debug!("TLS handshake with {address} failed: {e}");
+                None
+            }
+            Err(_) => None,
+        };
+    }
+
+    if is_same_machine(&stream, address) {
+        return plain(stream);
+    }
+
+    let mut stream = stream;
+    let response = format!(
+        "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{PLAIN_HTTP_REFUSED}",
+        PLAIN_HTTP_REFUSED.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    None
+}
+
+/// Whether a connection comes from this machine: over loopback, or to one of
+/// its own addresses.
+fn is_same_machine(stream: &TcpStream, peer: SocketAddr) -> bool {
+    let peer = peer.ip().to_canonical();
+    peer.is_loopback()
+        || stream
+            .local_addr()
+            .is_ok_and(|local| local.ip().to_canonical() == peer)
+}
+
+/// The TLS settings of an exposed dashboard: the configured certificate, or a
+/// self-signed one kept in the configuration directory.
+pub fn acceptor(config: &DashboardConfig) -> Result<TlsAcceptor> {
+    let (cert, key) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => (read(cert)?, read(key)?),
+        (None, None) => self_signed(config)?,
+        _ => return Err(anyhow!("tls_cert and tls_key have to be set together")),
+    };
+    Ok(TlsAcceptor::from(Arc::new(server_config(&cert, &key)?)))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow!("Failed to read {path:?}: {e}"))
+}
+
+/// Reads the self-signed certificate, generating it on first use. Delete the
+/// files to issue a new one, e.g. after adding names to `hosts`.
+fn self_signed(config: &DashboardConfig) -> Result<(Vec<u8>, Vec<u8>)> {
+    let directory = Config::get_config_directory_path()?;
+    let cert_path = directory.join(DASHBOARD_CERT_FILE_NAME);
+    let key_path = directory.join(DASHBOARD_KEY_FILE_NAME);
+    if cert_path.is_file() && key_path.is_file() {
+        return Ok((read(&cert_path)?, read(&key_path)?));
+    }
+
+    let (cert, key) = generate_certificate(subject_names(config))?;
+    fs::write(&cert_path, &cert).map_err(|e| anyhow!("Failed to write {cert_path:?}: {e}"))?;
+    files::write_private_file(&key_path, &key)?;
+    log::info!("Generated a self-signed dashboard certificate in {cert_path:?}");
+    Ok((cert, key))
+}
+
+/// The names a self-signed certificate is issued for.
+fn subject_names(config: &DashboardConfig) -> Vec<String> {
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    let bind = config.bind_address();
+    if !bind.is_unspecified() && !bind.is_loopback() {
+        names.push(bind.to_string());
+    }
+    names.extend(config.hosts.iter().cloned());
+    names
+}
+
+/// Generates a self-signed certificate, returned with its key as PEM.
+fn generate_certificate(names: Vec<String>) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| anyhow!("Failed to generate a certificate: {e}"))?;
+    let pem = cert
+        .serialize_pem()
+        .map_err(|e| anyhow!("Failed to encode the certificate: {e}"))?;
+    Ok((
+        pem.into_bytes(),
+        cert.serialize_private_key_pem().into_bytes(),
+    ))
+}
+
+/// Builds the server settings from a PEM certificate chain and private key.
+fn server_config(cert: &[u8], key: &[u8]) -> Result<ServerConfig> {
+    let certs =
+        rustls_pemfile::certs(&mut &cert[..]).map_err(|e| anyhow!("Invalid certificate: {e}"))?;
+    if certs.is_empty() {
+        return Err(anyhow!("The certificate file contains no certificate"));
+    }
+
+    let key = rustls_pemfile::read_all(&mut &key[..])
+        .map_err(|e| anyhow!("Invalid private key: {e}"))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("The key file contains no private key"))?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            certs.into_iter().map(rustls::Certificate).collect(),
+            rustls::PrivateKey(key),
+        )
+        .map_err(|e| anyhow!("Invalid certificate or key: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio_rustls::TlsConnector;
+
+    fn localhost_certificate() -> (Vec<u8>, Vec<u8>) {
+        generate_certificate(vec!["localhost".to_string()]).unwrap()
+    }
+
+    /// Serves a fixed response on an ephemeral loopback port.
+    async fn serve(tls: Option<TlsAcceptor>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = DashboardListener::new(listener, tls).unwrap();
+        let address = listener.local_address;
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::get(
+                |axum::extract::ConnectInfo(peer): axum::extract::ConnectInfo<Peer>| async move {
+                    if peer.tls { "tls" } else { "plain" }
+                },
+            ),
+        );
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<Peer>()).await
+        });
+        address
+    }
+
+    async fn get(mut stream: impl AsyncRead + AsyncWrite + Unpin) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn generated_certificate_is_accepted() {
+        let (cert, key) = localhost_certificate();
+        assert!(server_config(&cert, &key).is_ok());
+    }
+
+    #[test]
+    fn invalid_pem_is_rejected() {
+        let (cert, key) = localhost_certificate();
+        assert!(server_config(b"", &key).is_err());
+        assert!(server_config(&cert, b"").is_err());
+        assert!(server_config(&key, &cert).is_err());
+    }
+
+    #[test]
+    fn certificate_names_include_bind_address_and_hosts() {
+        let config = DashboardConfig {
+            bind: Some("192.168.1.10".parse().unwrap()),
+            hosts: vec!["nas.lan".to_string()],
+            ..Default::default()
+        };
+        let names = subject_names(&config);
+        assert!(names.contains(&"192.168.1.10".to_string()));
+        assert!(names.contains(&"nas.lan".to_string()));
+
+        let config = DashboardConfig {
+            bind: Some("0.0.0.0".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(!subject_names(&config).contains(&"0.0.0.0".to_string()));
+    }
+
+    #[test]
+    fn certificate_and_key_go_together() {
+        let config = DashboardConfig {
+            tls_cert: Some("cert.pem".into()),
+            ..Default::default()
+        };
+        assert!(acceptor(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn serves_tls_and_local_plain_http_on_one_port() {
+        let (cert, key) = localhost_certificate();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&cert, &key).unwrap()));
+        let address = serve(Some(acceptor)).await;
+
+        let plain = get(TcpStream::connect(address).await.unwrap()).await;
+        assert!(plain.starts_with("HTTP/1.1 200"));
+        assert!(plain.ends_with("plain"));
+
+        let mut roots = rustls::RootCertStore::empty();
+        for der in rustls_pemfile::certs(&mut &cert[..]).unwrap() {
+            roots.add(&rustls::Certificate(der)).unwrap();
+        }
+        let client = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = TlsConnector::from(Arc::new(client))
+            .connect(
+                "localhost".try_into().unwrap(),
+                TcpStream::connect(address).await.unwrap(),
+            )
+            .await
+            .unwrap();
+        let encrypted = get(stream).await;
+        assert!(encrypted.starts_with("HTTP/1.1 200"));
+        assert!(encrypted.ends_with("tls"));
+    }
+}
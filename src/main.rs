@@ -4,6 +4,7 @@ mod common;
 mod dashboard;
 mod service;
 mod service_manager;
+#[cfg(windows)]
 mod tray;
 
 use crate::arg_parser::*;
@@ -11,14 +12,27 @@ use anyhow::Result;
 use clap::Parser;
 use std::process::exit;
 
+#[cfg(windows)]
 fn is_elevated() -> bool {
     unsafe { windows_sys::Win32::UI::Shell::IsUserAnAdmin() != 0 }
 }
 
+#[cfg(unix)]
+fn is_elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(windows)]
 fn requires_elevation(command: &Command) -> bool {
     !matches!(command, Command::CheckUpdate | Command::Tray(_))
 }
 
+#[cfg(unix)]
+fn requires_elevation(command: &Command) -> bool {
+    !matches!(command, Command::CheckUpdate)
+}
+
+#[cfg(windows)]
 fn elevate_self() -> ! {
     let exe = std::env::current_exe().expect("Failed to determine executable path");
     let args: Vec<String> = std::env::args().skip(1).collect();
@@ -53,10 +67,19 @@ fn elevate_self() -> ! {
     exit(0);
 }
 
+/// There is no equivalent of the UAC prompt, so ask the user to use sudo.
+#[cfg(unix)]
+fn elevate_self() -> ! {
+    eprintln!("This command must be run as root, e.g. with sudo.");
+    exit(1);
+}
+
+#[cfg(windows)]
 fn wide(s: &str) -> Vec<u16> {
     s.encode_utf16().chain(std::iter::once(0)).collect()
 }
 
+#[cfg(windows)]
 fn wait_for_keypress() {
     use std::io::Write;
     print!("\nPress any key to continue...");
@@ -81,11 +104,14 @@ async fn handle_service_command(svc: ServiceCommands) -> Result<()> {
     match svc.command {
         ServiceSubcommands::Install(args) => service_manager::install_service(args)?,
         ServiceSubcommands::Uninstall => service_manager::uninstall_service()?,
+        #[cfg(windows)]
         ServiceSubcommands::RunAsService => {
             if let Err(e) = service::service_dispatcher() {
                 eprintln!("Service error: {e}");
             }
         }
+        #[cfg(unix)]
+        ServiceSubcommands::Run(args) => service::run_daemon(!args.no_web)?,
         ServiceSubcommands::Start(args) => {
             service_manager::start_service(!args.no_tray, !args.no_web)?
         }
@@ -98,6 +124,7 @@ async fn handle_service_command(svc: ServiceCommands) -> Result<()> {
 fn main() {
     let args = Cli::parse();
 
+    #[cfg(windows)]
     if matches!(args.command, Command::Tray(_)) {
         let no_web = match &args.command {
             Command::Tray(tray_args) => tray_args.no_web,
@@ -114,6 +141,7 @@ fn main() {
         elevate_self();
     }
 
+    #[cfg(windows)]
     let elevated_flag = args.elevated;
     let result = tokio_main(args);
 
@@ -121,6 +149,7 @@ fn main() {
         eprintln!("Failed to execute: {e}.");
     }
 
+    #[cfg(windows)]
     if elevated_flag {
         wait_for_keypress();
     }
@@ -168,6 +197,7 @@ async fn tokio_main(args: Cli) -> Result<()> {
         }
         Command::Config => client::print_configuration().await?,
         Command::Status => client::get_last_status().await?,
+        Command::History { limit } => client::print_history(limit).await?,
         Command::CheckUpdate => client::check_update().await,
         Command::ClearLogs => {
             let deleted = client::clear_logs()?;
@@ -180,6 +210,8 @@ async fn tokio_main(args: Cli) -> Result<()> {
         Command::DashboardPort { port } => {
             client::change_dashboard_port(port)?;
         }
+        Command::DashboardSecret { rotate } => client::dashboard_secret(rotate)?,
+        #[cfg(windows)]
         Command::Tray(_) => unreachable!(),
     }
 
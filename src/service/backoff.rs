@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+/// Exponential backoff with jitter for retrying failed updates.
+///
+/// Each failure doubles the delay, starting at `base` and capped at `max`. The
+/// returned delay is drawn from the upper half of that value, so that several
+/// hosts that failed together do not retry in lockstep.
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+fn random_fraction() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    let value = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    value as f64 / u64::MAX as f64
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// Starts over from `base`, e.g. after a successful update.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Returns the delay before the next retry and advances the backoff.
+    pub fn next_delay(&mut self) -> Duration {
+        self.next_delay_with(random_fraction())
+    }
+
+    /// Same as [`Self::next_delay`], with `jitter` in `[0, 1]` picking where in
+    /// the upper half of the exponential delay the result falls.
+    fn next_delay_with(&mut self, jitter: f64) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempt);
+        let delay = self.base.saturating_mul(factor).min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        delay / 2 + (delay / 2).mul_f64(jitter.clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60));
+        let delays: Vec<u64> = (0..5)
+            .map(|_| backoff.next_delay_with(1.0).as_secs())
+            .collect();
+        assert_eq!(delays, vec![10, 20, 40, 60, 60]);
+    }
+
+    #[test]
+    fn jitter_stays_in_upper_half() {
+        let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60));
+        assert_eq!(backoff.next_delay_with(0.0), Duration::from_secs(5));
+
+        let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60));
+        for expected in [10, 20, 40, 60, 60] {
+            let delay = backoff.next_delay();
+            assert!(delay >= Duration::from_secs(expected) / 2);
+            assert!(delay <= Duration::from_secs(expected));
+        }
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60));
+        backoff.next_delay_with(1.0);
+        backoff.next_delay_with(1.0);
+        backoff.reset();
+        assert_eq!(backoff.next_delay_with(1.0), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn many_failures_do_not_overflow() {
+        let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(3600));
+        for _ in 0..100 {
+            backoff.next_delay_with(1.0);
+        }
+        assert_eq!(backoff.next_delay_with(1.0), Duration::from_secs(3600));
+    }
+}
@@ -0,0 +1,432 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use super::provider::{DnsProvider, is_valid_hostname};
+use super::public_address::PublicAddresses;
+use crate::common::config::{CloudflareConfig, ServiceConfig};
+use crate::common::consts::{CLOUDFLARE_API_URL, HTTP_TIMEOUT_SECS};
+
+/// The Cloudflare backend, which upserts an A (and optionally AAAA) record for
+/// every configured record name in a single zone through the Cloudflare API.
+pub struct Cloudflare {
+    api_url: String,
+    zone_id: String,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiError>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct DnsRecord {
+    id: String,
+    content: String,
+}
+
+fn error_summary(errors: &[ApiError]) -> String {
+    if errors.is_empty() {
+        return "unknown error".to_string();
+    }
+    errors
+        .iter()
+        .map(|e| format!("{} ({})", e.message, e.code))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn call<T: DeserializeOwned>(request: minreq::Request, token: &str) -> Result<T> {
+    let response = request
+        .with_header("Authorization", format!("Bearer {token}"))
+        .with_header("Content-Type", "application/json")
+        .with_timeout(HTTP_TIMEOUT_SECS)
+        .send()
+        .map_err(|e| anyhow!("Failed to reach Cloudflare: {e}"))?;
+
+    let body: ApiResponse<T> = serde_json::from_str(response.as_str()?).map_err(|e| {
+        anyhow!(
+            "Unexpected Cloudflare response (HTTP {}): {e}",
+            response.status_code
+        )
+    })?;
+
+    if !body.success {
+        return Err(anyhow!(
+            "Cloudflare rejected the request: {}",
+            error_summary(&body.errors)
+        ));
+    }
+
+    body.result
+        .ok_or(anyhow!("Cloudflare response is missing a result"))
+}
+
+impl Cloudflare {
+    pub fn new(config: &CloudflareConfig) -> Result<Self> {
+        if config.zone_id.is_empty() || !config.zone_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow!("Invalid Cloudflare zone ID: '{}'", config.zone_id));
+        }
+
+        Ok(Self {
+            api_url: config
+                .api_url
+                .as_deref()
+                .unwrap_or(CLOUDFLARE_API_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            zone_id: config.zone_id.clone(),
+        })
+    }
+
+    fn records_url(&self) -> String {
+        format!("{}/zones/{}/dns_records", self.api_url, self.zone_id)
+    }
+
+    fn find_records(&self, token: &str, name: &str, kind: &str) -> Result<Vec<DnsRecord>> {
+        let url = format!("{}?type={kind}&name={name}", self.records_url());
+        call(minreq::get(url), token)
+    }
+
+    /// Makes sure a single `kind` record for `name` holds `content`, returning
+    /// what had to be done.
+    fn upsert_record(
+        &self,
+        token: &str,
+        name: &str,
+        kind: &str,
+        content: &str,
+    ) -> Result<&'static str> {
+        let existing = self.find_records(token, name, kind)?;
+
+        match existing.first() {
+            Some(record) if record.content == content => {
+                log::debug!("{kind} record for {name} is already {content}");
+                Ok("unchanged")
+            }
+            Some(record) => {
+                log::debug!("Updating {kind} record for {name} to {content}");
+                let url = format!("{}/{}", self.records_url(), record.id);
+                let body = serde_json::json!({ "content": content });
+                call::<serde_json::Value>(minreq::patch(url).with_body(body.to_string()), token)?;
+                Ok("updated")
+            }
+            None => {
+                log::debug!("Creating {kind} record for {name} with {content}");
+                let body = serde_json::json!({
+                    "type": kind,
+                    "name": name,
+                    "content": content,
+                    "ttl": 1,
+                    "proxied": false,
+                });
+                call::<serde_json::Value>(
+                    minreq::post(self.records_url()).with_body(body.to_string()),
+                    token,
+                )?;
+                Ok("created")
+            }
+        }
+    }
+
+    /// Deletes all `kind` records for `name`, returning how many there were.
+    fn delete_records(&self, token: &str, name: &str, kind: &str) -> Result<usize> {
+        let records = self.find_records(token, name, kind)?;
+        for record in &records {
+            log::debug!("Deleting {kind} record for {name}");
+            let url = format!("{}/{}", self.records_url(), record.id);
+            call::<serde_json::Value>(minreq::delete(url), token)?;
+        }
+        Ok(records.len())
+    }
+}
+
+fn token(config: &ServiceConfig) -> Result<&str> {
+    config
+        .token
+        .as_ref()
+        .map(|t| t.as_str())
+        .ok_or(anyhow!("No token configured"))
+}
+
+impl DnsProvider for Cloudflare {
+    fn validate_name(&self, name: &str) -> Result<()> {
+        if is_valid_hostname(name) {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid record name: {name}"))
+        }
+    }
+
+    /// Stale records are removed by `update` itself when an address family is
+    /// disabled.
+    fn requires_clear(&self) -> bool {
+        false
+    }
+
+    async fn update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<String> {
+        let token = token(config)?;
+
+        let mut actions = Vec::new();
+        for name in &config.domain {
+            log::debug!("Sending Cloudflare update for {name}");
+            let records = [
+                ("A", addresses.ipv4.map(|v4| v4.to_string())),
+                ("AAAA", addresses.ipv6.map(|v6| v6.to_string())),
+            ];
+
+            for (kind, address) in records {
+                match address {
+                    Some(address) => self
+                        .upsert_record(token, name, kind, &address)
+                        .map(|action| actions.push(format!("{name} {kind} {action}"))),
+                    None => self.delete_records(token, name, kind).map(|deleted| {
+                        if deleted > 0 {
+                            actions.push(format!("{name} {kind} deleted"));
+                        }
+                    }),
+                }
+                .map_err(|e| anyhow!("{name}: {e}"))?;
+            }
+        }
+
+        Ok(actions.join(", "))
+    }
+
+    async fn clear(&self, config: &ServiceConfig) -> Result<()> {
+        let token = token(config)?;
+
+        for name in &config.domain {
+            for kind in ["A", "AAAA"] {
+                self.delete_records(token, name, kind)
+                    .map_err(|e| anyhow!("{name}: {e}"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::ProviderKind;
+    use crate::service::fixtures::{ADDRESSES, make_config};
+    use crate::service::mock_http::MockServer;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const ZONE: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+
+    fn make_provider(server: &MockServer) -> Cloudflare {
+        Cloudflare::new(&CloudflareConfig {
+            zone_id: ZONE.to_string(),
+            api_url: Some(server.url().to_string()),
+        })
+        .unwrap()
+    }
+
+    fn ok(result: &str) -> (u16, String) {
+        (
+            200,
+            format!(r#"{{"success":true,"errors":[],"result":{result}}}"#),
+        )
+    }
+
+    #[test]
+    fn new_rejects_bad_zone_id() {
+        let config = CloudflareConfig {
+            zone_id: "../zones".to_string(),
+            api_url: None,
+        };
+        assert!(Cloudflare::new(&config).is_err());
+    }
+
+    #[test]
+    fn new_defaults_api_url() {
+        let config = CloudflareConfig {
+            zone_id: ZONE.to_string(),
+            api_url: None,
+        };
+        let provider = Cloudflare::new(&config).unwrap();
+        assert_eq!(provider.api_url, CLOUDFLARE_API_URL);
+    }
+
+    #[tokio::test]
+    async fn update_creates_missing_record() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "GET" => ok("[]"),
+            _ => ok(r#"{"id":"new"}"#),
+        });
+        let provider = make_provider(&server);
+
+        provider
+            .update(
+                &make_config(ProviderKind::Cloudflare, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        let post = requests.iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(post.path, format!("/zones/{ZONE}/dns_records"));
+        assert_eq!(post.header("Authorization"), Some("Bearer token"));
+        let body: serde_json::Value = serde_json::from_str(&post.body).unwrap();
+        assert_eq!(body["type"], "A");
+        assert_eq!(body["name"], "home.example.com");
+        assert_eq!(body["content"], "1.2.3.4");
+    }
+
+    #[tokio::test]
+    async fn update_patches_changed_record() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "GET" if req.path.contains("type=A&") => ok(r#"[{"id":"rec1","content":"9.9.9.9"}]"#),
+            "GET" => ok("[]"),
+            _ => ok(r#"{"id":"rec1"}"#),
+        });
+        let provider = make_provider(&server);
+
+        provider
+            .update(
+                &make_config(ProviderKind::Cloudflare, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        let patch = requests.iter().find(|r| r.method == "PATCH").unwrap();
+        assert_eq!(patch.path, format!("/zones/{ZONE}/dns_records/rec1"));
+        assert!(patch.body.contains("1.2.3.4"));
+        assert!(!requests.iter().any(|r| r.method == "POST"));
+    }
+
+    #[tokio::test]
+    async fn update_skips_unchanged_record() {
+        let server = MockServer::start(|req| {
+            if req.path.contains("type=A&") {
+                ok(r#"[{"id":"rec1","content":"1.2.3.4"}]"#)
+            } else {
+                ok("[]")
+            }
+        });
+        let provider = make_provider(&server);
+
+        provider
+            .update(
+                &make_config(ProviderKind::Cloudflare, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap();
+
+        assert!(server.requests().iter().all(|r| r.method == "GET"));
+    }
+
+    #[tokio::test]
+    async fn update_upserts_aaaa_when_ipv6_present() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "GET" => ok("[]"),
+            _ => ok(r#"{"id":"new"}"#),
+        });
+        let provider = make_provider(&server);
+        let addresses = PublicAddresses {
+            ipv4: Some(Ipv4Addr::new(1, 2, 3, 4)),
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+
+        provider
+            .update(
+                &make_config(ProviderKind::Cloudflare, &["home.example.com"]),
+                &addresses,
+            )
+            .await
+            .unwrap();
+
+        let posts: Vec<_> = server
+            .requests()
+            .into_iter()
+            .filter(|r| r.method == "POST")
+            .collect();
+        assert_eq!(posts.len(), 2);
+        assert!(posts.iter().any(|r| r.body.contains(r#""type":"AAAA""#)));
+    }
+
+    #[tokio::test]
+    async fn update_deletes_aaaa_when_ipv6_disabled() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "GET" if req.path.contains("type=AAAA") => ok(r#"[{"id":"v6rec","content":"::1"}]"#),
+            "GET" => ok(r#"[{"id":"rec1","content":"1.2.3.4"}]"#),
+            _ => ok(r#"{"id":"v6rec"}"#),
+        });
+        let provider = make_provider(&server);
+
+        provider
+            .update(
+                &make_config(ProviderKind::Cloudflare, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        let delete = requests.iter().find(|r| r.method == "DELETE").unwrap();
+        assert_eq!(delete.path, format!("/zones/{ZONE}/dns_records/v6rec"));
+    }
+
+    #[tokio::test]
+    async fn update_reports_api_errors() {
+        let server = MockServer::start(|_| {
+            (
+                403,
+                r#"{"success":false,"errors":[{"code":9109,"message":"Invalid access token"}],"result":null}"#
+                    .to_string(),
+            )
+        });
+        let provider = make_provider(&server);
+
+        let err = provider
+            .update(
+                &make_config(ProviderKind::Cloudflare, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("home.example.com"));
+        assert!(err.contains("Invalid access token (9109)"));
+    }
+
+    #[tokio::test]
+    async fn clear_deletes_all_records() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "GET" => ok(r#"[{"id":"rec","content":"1.2.3.4"}]"#),
+            _ => ok(r#"{"id":"rec"}"#),
+        });
+        let provider = make_provider(&server);
+
+        provider
+            .clear(&make_config(
+                ProviderKind::Cloudflare,
+                &["home.example.com"],
+            ))
+            .await
+            .unwrap();
+
+        let deletes = server
+            .requests()
+            .into_iter()
+            .filter(|r| r.method == "DELETE")
+            .count();
+        assert_eq!(deletes, 2);
+    }
+}
@@ -0,0 +1,316 @@
+//! Just enough of the DNS wire format (RFC 1035) to build small messages and
+//! read back the status of a response.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use crate::common::config::DnsTransport;
+
+pub const HEADER_LEN: usize = 12;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_TSIG: u16 = 250;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_ANY: u16 = 255;
+
+pub const OPCODE_UPDATE: u16 = 5;
+
+/// The RD (recursion desired) flag of a standard query.
+pub const FLAG_RD: u16 = 0x0100;
+
+const MAX_UDP_RESPONSE: usize = 4096;
+
+/// Appends `name` in uncompressed wire format. A trailing dot is optional.
+pub fn write_name(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.len() > 253 {
+        return Err(anyhow!("Name is too long: {name}"));
+    }
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(anyhow!("Invalid label in name: {name}"));
+            }
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+    }
+    buf.push(0);
+    Ok(())
+}
+
+/// Appends a message header. `counts` are the four section counts in order.
+pub fn write_header(buf: &mut Vec<u8>, id: u16, flags: u16, counts: [u16; 4]) {
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&flags.to_be_bytes());
+    for count in counts {
+        buf.extend_from_slice(&count.to_be_bytes());
+    }
+}
+
+/// Appends a resource record with the given `rdata`.
+pub fn write_record(
+    buf: &mut Vec<u8>,
+    name: &str,
+    rtype: u16,
+    class: u16,
+    ttl: u32,
+    rdata: &[u8],
+) -> Result<()> {
+    write_name(buf, name)?;
+    buf.extend_from_slice(&rtype.to_be_bytes());
+    buf.extend_from_slice(&class.to_be_bytes());
+    buf.extend_from_slice(&ttl.to_be_bytes());
+    buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    buf.extend_from_slice(rdata);
+    Ok(())
+}
+
+/// Appends a question entry.
+pub fn write_question(buf: &mut Vec<u8>, name: &str, qtype: u16, class: u16) -> Result<()> {
+    write_name(buf, name)?;
+    buf.extend_from_slice(&qtype.to_be_bytes());
+    buf.extend_from_slice(&class.to_be_bytes());
+    Ok(())
+}
+
+/// Returns the offset just past the (possibly compressed) name at `offset`.
+pub fn skip_name(buf: &[u8], mut offset: usize) -> Result<usize> {
+    loop {
+        let len = *buf.get(offset).ok_or(anyhow!("Truncated DNS message"))? as usize;
+        match len {
+            0 => return Ok(offset + 1),
+            // A compression pointer ends the name.
+            l if l & 0xC0 == 0xC0 => return Ok(offset + 2),
+            l => offset += l + 1,
+        }
+    }
+}
+
+/// Returns the RDATA of every answer record of type `rtype` in `response`.
+pub fn answers(response: &[u8], rtype: u16) -> Result<Vec<&[u8]>> {
+    let questions = read_u16(response, 4)?;
+    let answer_count = read_u16(response, 6)?;
+
+    let mut offset = HEADER_LEN;
+    for _ in 0..questions {
+        offset = skip_name(response, offset)? + 4;
+    }
+
+    let mut rdatas = Vec::new();
+    for _ in 0..answer_count {
+        offset = skip_name(response, offset)?;
+        let record_type = read_u16(response, offset)?;
+        let len = read_u16(response, offset + 8)? as usize;
+        offset += 10;
+        let rdata = response
+            .get(offset..offset + len)
+            .ok_or(anyhow!("Truncated DNS message"))?;
+        if record_type == rtype {
+            rdatas.push(rdata);
+        }
+        offset += len;
+    }
+
+    Ok(rdatas)
+}
+
+pub fn read_u16(buf: &[u8], offset: usize) -> Result<u16> {
+    buf.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(anyhow!("Truncated DNS message"))
+}
+
+/// Returns a random message ID.
+pub fn random_id() -> u16 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish() as u16
+}
+
+/// Checks that `response` answers the message with `id` and returns its RCODE.
+pub fn response_code(response: &[u8], id: u16) -> Result<u16> {
+    if response.len() < HEADER_LEN {
+        return Err(anyhow!("Truncated DNS response"));
+    }
+    if read_u16(response, 0)? != id {
+        return Err(anyhow!("DNS response ID does not match the request"));
+    }
+    let flags = read_u16(response, 2)?;
+    if flags & 0x8000 == 0 {
+        return Err(anyhow!("DNS message is not a response"));
+    }
+    Ok(flags & 0x000F)
+}
+
+pub fn rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Sends `message` to `server` and waits for a single response.
+///
+/// Over TCP, messages are prefixed with their two byte length (RFC 1035 §4.2.2).
+pub async fn exchange(
+    server: SocketAddr,
+    transport: DnsTransport,
+    message: &[u8],
+    duration: Duration,
+) -> Result<Vec<u8>> {
+    let exchange = async {
+        match transport {
+            DnsTransport::Udp => {
+                let local: SocketAddr = if server.is_ipv4() {
+                    "0.0.0.0:0".parse()?
+                } else {
+                    "[::]:0".parse()?
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(server).await?;
+                socket.send(message).await?;
+                let mut buf = vec![0; MAX_UDP_RESPONSE];
+                let len = socket.recv(&mut buf).await?;
+                buf.truncate(len);
+                Ok(buf)
+            }
+            DnsTransport::Tcp => {
+                let mut stream = TcpStream::connect(server).await?;
+                stream
+                    .write_all(&(message.len() as u16).to_be_bytes())
+                    .await?;
+                stream.write_all(message).await?;
+                let len = stream.read_u16().await? as usize;
+                let mut buf = vec![0; len];
+                stream.read_exact(&mut buf).await?;
+                Ok(buf)
+            }
+        }
+    };
+
+    timeout(duration, exchange)
+        .await
+        .map_err(|_| anyhow!("No response from DNS server {server}"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_name_encodes_labels() {
+        let mut buf = Vec::new();
+        write_name(&mut buf, "www.example.com").unwrap();
+        assert_eq!(buf, b"\x03www\x07example\x03com\x00");
+    }
+
+    #[test]
+    fn write_name_accepts_trailing_dot() {
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        write_name(&mut a, "example.com.").unwrap();
+        write_name(&mut b, "example.com").unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn write_name_root() {
+        let mut buf = Vec::new();
+        write_name(&mut buf, ".").unwrap();
+        assert_eq!(buf, vec![0]);
+    }
+
+    #[test]
+    fn write_name_rejects_empty_label() {
+        let mut buf = Vec::new();
+        assert!(write_name(&mut buf, "a..b").is_err());
+        assert!(write_name(&mut buf, &"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn write_record_layout() {
+        let mut buf = Vec::new();
+        write_record(&mut buf, "a", TYPE_A, CLASS_IN, 300, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(
+            buf,
+            vec![1, b'a', 0, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn skip_name_handles_labels_and_pointers() {
+        let buf = b"\x03www\x07example\x03com\x00\xC0\x0C";
+        assert_eq!(skip_name(buf, 0).unwrap(), 17);
+        assert_eq!(skip_name(buf, 17).unwrap(), 19);
+        assert!(skip_name(b"\x03ww", 0).is_err());
+    }
+
+    #[test]
+    fn answers_extracts_matching_records() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, 1, 0x8000, [1, 2, 0, 0]);
+        write_question(&mut buf, "myip.example", TYPE_A, CLASS_IN).unwrap();
+        // A compressed owner name pointing at the question.
+        buf.extend_from_slice(&[0xC0, 0x0C]);
+        buf.extend_from_slice(&[0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 1, b'x']);
+        buf.extend_from_slice(&[0xC0, 0x0C]);
+        buf.extend_from_slice(&[0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 1, 2, 3, 4]);
+
+        assert_eq!(answers(&buf, TYPE_A).unwrap(), vec![&[1, 2, 3, 4][..]]);
+        assert!(answers(&buf, TYPE_AAAA).unwrap().is_empty());
+    }
+
+    #[test]
+    fn answers_rejects_truncated() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, 1, 0x8000, [0, 1, 0, 0]);
+        buf.extend_from_slice(&[0, 0, 1, 0, 1]);
+        assert!(answers(&buf, TYPE_A).is_err());
+    }
+
+    #[test]
+    fn response_code_reads_rcode() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, 0x1234, 0x8000 | (OPCODE_UPDATE << 11) | 5, [0; 4]);
+        assert_eq!(response_code(&buf, 0x1234).unwrap(), 5);
+    }
+
+    #[test]
+    fn response_code_rejects_mismatched_id() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, 1, 0x8000, [0; 4]);
+        assert!(response_code(&buf, 2).is_err());
+    }
+
+    #[test]
+    fn response_code_rejects_query() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, 1, 0, [0; 4]);
+        assert!(response_code(&buf, 1).is_err());
+    }
+
+    #[test]
+    fn response_code_rejects_truncated() {
+        assert!(response_code(&[0, 1, 0x80], 1).is_err());
+    }
+}
@@ -2,16 +2,45 @@ use std::net::{Ipv4Addr, Ipv6Addr};
 
 use anyhow::{Result, anyhow};
 
-use crate::common::config::Config;
+use super::provider::DnsProvider;
+use super::public_address::PublicAddresses;
+use crate::common::config::ServiceConfig;
+use crate::common::consts::{DUCKDNS_UPDATE_URL, HTTP_TIMEOUT_SECS};
+
+/// The DuckDNS backend, which updates every configured subdomain in a single
+/// request to the DuckDNS update endpoint.
+pub struct DuckDns {
+    update_url: String,
+}
+
+impl Default for DuckDns {
+    fn default() -> Self {
+        Self {
+            update_url: DUCKDNS_UPDATE_URL.to_string(),
+        }
+    }
+}
+
+// Validates that a domain name only contains characters safe for use in a DuckDNS subdomain.
+// Prevents URL parameter injection via crafted domain strings.
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.len() <= 63
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !domain.starts_with('-')
+        && !domain.ends_with('-')
+}
 
 fn build_update_url(
+    base_url: &str,
     domains_csv: &str,
     token: &str,
     ipv4: Ipv4Addr,
     ipv6: Option<Ipv6Addr>,
 ) -> String {
-    let mut url =
-        format!("https://www.duckdns.org/update?domains={domains_csv}&token={token}&ip={ipv4}");
+    let mut url = format!("{base_url}?domains={domains_csv}&token={token}&ip={ipv4}");
 
     if let Some(v6) = ipv6 {
         url.push_str(&format!("&ipv6={v6}"));
@@ -20,105 +49,95 @@ fn build_update_url(
     url
 }
 
-fn build_clear_url(domains_csv: &str, token: &str) -> String {
-    format!("https://www.duckdns.org/update?domains={domains_csv}&token={token}&clear=true")
+fn build_clear_url(base_url: &str, domains_csv: &str, token: &str) -> String {
+    format!("{base_url}?domains={domains_csv}&token={token}&clear=true")
 }
 
-async fn generate_request(config: &Config) -> Result<String> {
-    let ip = public_ip::addr_v4()
-        .await
-        .ok_or(anyhow!("Failed to get the public IP address"))?;
-
-    let token = config
-        .service
-        .token
-        .as_ref()
-        .ok_or(anyhow!("No token configured"))?;
-
-    let ipv6 = if config.service.ipv6 == Some(true) {
-        Some(
-            public_ip::addr_v6()
-                .await
-                .ok_or(anyhow!("Failed to get the public IPv6 address"))?,
-        )
-    } else {
-        None
-    };
-
-    Ok(build_update_url(
-        &config.service.domains_csv(),
-        token.as_str(),
-        ip,
-        ipv6,
-    ))
+impl DuckDns {
+    fn generate_request(
+        &self,
+        config: &ServiceConfig,
+        addresses: &PublicAddresses,
+    ) -> Result<String> {
+        let token = config
+            .token
+            .as_ref()
+            .ok_or(anyhow!("No token configured"))?;
+        // DuckDNS fills in the caller's address when none is given, so IPv4
+        // cannot be left out.
+        let ipv4 = addresses
+            .ipv4
+            .ok_or(anyhow!("DuckDNS does not support disabling IPv4 updates"))?;
+
+        Ok(build_update_url(
+            &self.update_url,
+            &config.domains_csv(),
+            token.as_str(),
+            ipv4,
+            addresses.ipv6,
+        ))
+    }
 }
 
-fn clear_ip_addresses(config: &Config) -> Result<minreq::Response> {
-    let token = config
-        .service
-        .token
-        .as_ref()
-        .ok_or(anyhow!("No token configured"))?;
+impl DnsProvider for DuckDns {
+    fn validate_name(&self, name: &str) -> Result<()> {
+        if is_valid_domain(name) {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid domain name: {name}"))
+        }
+    }
 
-    let url = build_clear_url(&config.service.domains_csv(), token.as_str());
+    fn check_update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<()> {
+        self.generate_request(config, addresses).map(|_| ())
+    }
 
-    Ok(minreq::get(url).send()?)
-}
+    /// Updates DuckDNS with the provided configuration.
+    ///
+    /// Generates a DuckDNS update request for every configured domain with the
+    /// discovered `addresses`, sends it, and checks that DuckDNS acknowledged it.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(response)` with DuckDNS' response if the update was successful.
+    /// * `Err(e)` if an error occurred while generating or sending the request.
+    async fn update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<String> {
+        let url = self.generate_request(config, addresses)?;
 
-/// Updates DuckDNS with the provided configuration.
-///
-/// This function generates a DuckDNS update request based on the provided `config`,
-/// sends the request, and handles the response. If the configuration specifies, it
-/// also clears existing IP addresses on DuckDNS before sending the update.
-///
-/// # Arguments
-///
-/// * `config`: The configuration used to generate the DuckDNS update request.
-///
-/// # Returns
-///
-/// * `Ok(())` if the update was successful.
-/// * `Err(e)` if an error occurred during the update process, including request
-///   generation, clearing IP addresses, or sending the update.
-pub async fn update(config: &Config) -> Result<()> {
-    let url = generate_request(config).await?;
-
-    if config.service.clear_ip_addresses {
-        // the ipv6 configuration might have been changed to false,
-        // in which case we need to clear the ipv6 address
-        match clear_ip_addresses(config) {
+        log::debug!(
+            "Sending update request for domains: {}",
+            config.domains_csv()
+        );
+        match minreq::get(url).with_timeout(HTTP_TIMEOUT_SECS).send() {
             Ok(res) => {
                 let body = res.as_str()?;
+                log::debug!("Update sent. Response: {body}");
                 match body {
-                    "OK" => log::debug!("Cleared"),
-                    _ => {
-                        return Err(anyhow!(
-                            "Failed to clear IP addresses: DuckDNS responded with '{body}'"
-                        ));
-                    }
+                    "OK" => Ok(body.to_string()),
+                    _ => Err(anyhow!("DuckDNS responded with '{body}'")),
                 }
-                log::debug!("Clear sent. Response: {res:?}");
-            }
-            Err(e) => {
-                return Err(anyhow!("Failed to clear IP addresses: {e}"));
             }
+            Err(e) => Err(anyhow!("Failed to update DuckDNS: {e}")),
         }
     }
 
-    log::debug!(
-        "Sending update request for domains: {}",
-        config.service.domains_csv()
-    );
-    match minreq::get(url).send() {
-        Ok(res) => {
-            let body = res.as_str()?;
-            log::debug!("Update sent. Response: {body}");
-            match body {
-                "OK" => Ok(()),
-                _ => Err(anyhow!("DuckDNS responded with '{body}'")),
+    async fn clear(&self, config: &ServiceConfig) -> Result<()> {
+        let token = config
+            .token
+            .as_ref()
+            .ok_or(anyhow!("No token configured"))?;
+
+        let url = build_clear_url(&self.update_url, &config.domains_csv(), token.as_str());
+
+        let res = minreq::get(url).with_timeout(HTTP_TIMEOUT_SECS).send()?;
+        let body = res.as_str()?;
+        match body {
+            "OK" => {
+                log::debug!("Cleared");
+                Ok(())
             }
+            _ => Err(anyhow!("DuckDNS responded with '{body}'")),
         }
-        Err(e) => Err(anyhow!("Failed to update DuckDNS: {e}")),
     }
 }
 
@@ -127,9 +146,74 @@ mod tests {
     use super::*;
     use std::net::{Ipv4Addr, Ipv6Addr};
 
+    #[test]
+    fn valid_domains() {
+        assert!(is_valid_domain("mydomain"));
+        assert!(is_valid_domain("test123"));
+        assert!(is_valid_domain("my-domain"));
+        assert!(is_valid_domain("a"));
+        assert!(is_valid_domain("a-b-c"));
+    }
+
+    #[test]
+    fn valid_domain_max_length() {
+        let domain = "a".repeat(63);
+        assert!(is_valid_domain(&domain));
+    }
+
+    #[test]
+    fn empty_domain_is_invalid() {
+        assert!(!is_valid_domain(""));
+    }
+
+    #[test]
+    fn domain_exceeding_max_length_is_invalid() {
+        let domain = "a".repeat(64);
+        assert!(!is_valid_domain(&domain));
+    }
+
+    #[test]
+    fn domain_starting_with_hyphen_is_invalid() {
+        assert!(!is_valid_domain("-domain"));
+    }
+
+    #[test]
+    fn domain_ending_with_hyphen_is_invalid() {
+        assert!(!is_valid_domain("domain-"));
+    }
+
+    #[test]
+    fn domain_with_special_chars_is_invalid() {
+        assert!(!is_valid_domain("my.domain"));
+        assert!(!is_valid_domain("my domain"));
+        assert!(!is_valid_domain("my@domain"));
+        assert!(!is_valid_domain("my$domain"));
+        assert!(!is_valid_domain("dom&ain"));
+    }
+
+    #[test]
+    fn domain_with_url_injection_is_invalid() {
+        assert!(!is_valid_domain("test&token=stolen"));
+        assert!(!is_valid_domain("test?token=stolen"));
+    }
+
+    #[test]
+    fn validate_name_reports_invalid_domain() {
+        let provider = DuckDns::default();
+        assert!(provider.validate_name("home").is_ok());
+        let err = provider.validate_name("-bad").unwrap_err();
+        assert!(err.to_string().contains("Invalid domain name"));
+    }
+
     #[test]
     fn build_update_url_ipv4_only() {
-        let url = build_update_url("home", "tok123", Ipv4Addr::new(1, 2, 3, 4), None);
+        let url = build_update_url(
+            DUCKDNS_UPDATE_URL,
+            "home",
+            "tok123",
+            Ipv4Addr::new(1, 2, 3, 4),
+            None,
+        );
         assert_eq!(
             url,
             "https://www.duckdns.org/update?domains=home&token=tok123&ip=1.2.3.4"
@@ -139,6 +223,7 @@ mod tests {
     #[test]
     fn build_update_url_with_ipv6() {
         let url = build_update_url(
+            DUCKDNS_UPDATE_URL,
             "home",
             "tok123",
             Ipv4Addr::new(1, 2, 3, 4),
@@ -149,13 +234,37 @@ mod tests {
 
     #[test]
     fn build_update_url_multiple_domains() {
-        let url = build_update_url("a,b,c", "tok", Ipv4Addr::new(10, 0, 0, 1), None);
+        let url = build_update_url(
+            DUCKDNS_UPDATE_URL,
+            "a,b,c",
+            "tok",
+            Ipv4Addr::new(10, 0, 0, 1),
+            None,
+        );
         assert!(url.contains("domains=a,b,c"));
     }
 
+    #[test]
+    fn disabled_ipv4_is_rejected() {
+        let config = ServiceConfig {
+            token: Some(crate::common::config::Token::new("tok123".to_string())),
+            domain: ["home".to_string()].into(),
+            ..Default::default()
+        };
+        let addresses = PublicAddresses {
+            ipv4: None,
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+        assert!(
+            DuckDns::default()
+                .generate_request(&config, &addresses)
+                .is_err()
+        );
+    }
+
     #[test]
     fn build_clear_url_format() {
-        let url = build_clear_url("home", "tok123");
+        let url = build_clear_url(DUCKDNS_UPDATE_URL, "home", "tok123");
         assert_eq!(
             url,
             "https://www.duckdns.org/update?domains=home&token=tok123&clear=true"
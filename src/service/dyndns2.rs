@@ -0,0 +1,370 @@
+use std::fmt;
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+use super::provider::{DnsProvider, is_valid_hostname};
+use super::public_address::PublicAddresses;
+use crate::common::config::{Dyndns2Config, ServiceConfig};
+use crate::common::consts::HTTP_TIMEOUT_SECS;
+use crate::common::strings::VERSION;
+
+/// The dyndns2 backend, spoken by No-IP, Dynu and other ddclient-compatible
+/// servers. All configured hostnames are updated in a single request.
+pub struct Dyndns2 {
+    server: String,
+    username: String,
+}
+
+/// A failure reported by a dyndns2 server, one variant per return code.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Dyndns2Error {
+    /// `badauth`: the username or password is wrong.
+    BadAuth,
+    /// `nohost`: the hostname does not exist in this account.
+    NoHost(String),
+    /// `notfqdn`: the hostname is not a fully qualified domain name.
+    NotFqdn(String),
+    /// `numhost`: too many hostnames in a single request.
+    NumHost,
+    /// `abuse`: the hostname is blocked for update abuse.
+    Abuse(String),
+    /// `badagent`: the user agent was rejected.
+    BadAgent,
+    /// `!donator`: the request uses a feature reserved for paying accounts.
+    NotDonator,
+    /// `dnserr`: a DNS error on the server side.
+    DnsError,
+    /// `911`: the server is down for maintenance.
+    ServerError,
+    /// Anything the protocol does not define.
+    Unexpected(String),
+}
+
+impl fmt::Display for Dyndns2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadAuth => write!(f, "Invalid username or password"),
+            Self::NoHost(host) => write!(f, "{host} does not exist in this account"),
+            Self::NotFqdn(host) => write!(f, "{host} is not a fully qualified domain name"),
+            Self::NumHost => write!(f, "Too many hostnames in a single update"),
+            Self::Abuse(host) => write!(f, "{host} is blocked for update abuse"),
+            Self::BadAgent => write!(f, "The server rejected the user agent"),
+            Self::NotDonator => write!(f, "The update requires a paid account"),
+            Self::DnsError => write!(f, "The server reported a DNS error"),
+            Self::ServerError => write!(f, "The server is temporarily unavailable"),
+            Self::Unexpected(body) => write!(f, "Unexpected server response '{body}'"),
+        }
+    }
+}
+
+impl std::error::Error for Dyndns2Error {}
+
+/// Maps one line of a dyndns2 response for `host` to its outcome.
+fn parse_return_code(line: &str, host: &str) -> std::result::Result<(), Dyndns2Error> {
+    let code = line.split_whitespace().next().unwrap_or_default();
+    match code {
+        "good" | "nochg" => Ok(()),
+        "badauth" => Err(Dyndns2Error::BadAuth),
+        "nohost" => Err(Dyndns2Error::NoHost(host.to_string())),
+        "notfqdn" => Err(Dyndns2Error::NotFqdn(host.to_string())),
+        "numhost" => Err(Dyndns2Error::NumHost),
+        "abuse" => Err(Dyndns2Error::Abuse(host.to_string())),
+        "badagent" => Err(Dyndns2Error::BadAgent),
+        "!donator" => Err(Dyndns2Error::NotDonator),
+        "dnserr" => Err(Dyndns2Error::DnsError),
+        "911" => Err(Dyndns2Error::ServerError),
+        _ => Err(Dyndns2Error::Unexpected(line.to_string())),
+    }
+}
+
+/// Checks the response body, which holds one return code per hostname in request order.
+fn parse_response(body: &str, hosts: &[&String]) -> std::result::Result<(), Dyndns2Error> {
+    let lines: Vec<&str> = body
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return Err(Dyndns2Error::Unexpected(body.to_string()));
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        // Some servers answer once for the whole request.
+        let host = hosts.get(i).map_or("", |h| h.as_str());
+        parse_return_code(line, host)?;
+    }
+
+    Ok(())
+}
+
+/// Builds the update URL. Without an IPv4 address the IPv6 address goes in
+/// `myip`, since servers fill in the caller's address when it is missing.
+fn build_update_url(server: &str, hosts: &str, addresses: &PublicAddresses) -> String {
+    let mut url = format!("{server}/nic/update?hostname={hosts}");
+
+    match (addresses.ipv4, addresses.ipv6) {
+        (Some(v4), Some(v6)) => url.push_str(&format!("&myip={v4}&myipv6={v6}")),
+        (Some(v4), None) => url.push_str(&format!("&myip={v4}")),
+        (None, Some(v6)) => url.push_str(&format!("&myip={v6}")),
+        (None, None) => {}
+    }
+
+    url
+}
+
+impl Dyndns2 {
+    pub fn new(config: &Dyndns2Config) -> Result<Self> {
+        if config.username.is_empty() {
+            return Err(anyhow!("The dyndns2 provider requires a username"));
+        }
+
+        Ok(Self {
+            server: config.server.trim_end_matches('/').to_string(),
+            username: config.username.clone(),
+        })
+    }
+}
+
+impl DnsProvider for Dyndns2 {
+    fn validate_name(&self, name: &str) -> Result<()> {
+        if is_valid_hostname(name) {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid hostname: {name}"))
+        }
+    }
+
+    /// dyndns2 has no way to remove an address, so stale records are left as is.
+    fn requires_clear(&self) -> bool {
+        false
+    }
+
+    async fn update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<String> {
+        let password = config
+            .token
+            .as_ref()
+            .ok_or(anyhow!("No token configured"))?;
+        let hosts: Vec<&String> = config.domain.iter().collect();
+        let url = build_update_url(&self.server, &config.domains_csv(), addresses);
+        let credentials = BASE64.encode(format!("{}:{}", self.username, password.as_str()));
+
+        log::debug!("Sending dyndns2 update for hosts: {}", config.domains_csv());
+        let res = minreq::get(url)
+            .with_header("Authorization", format!("Basic {credentials}"))
+            .with_header("User-Agent", format!("BarvazDNS/{VERSION}"))
+            .with_timeout(HTTP_TIMEOUT_SECS)
+            .send()
+            .map_err(|e| anyhow!("Failed to reach {}: {e}", self.server))?;
+
+        let body = res.as_str()?;
+        log::debug!("Update sent. Response: {body}");
+        if res.status_code == 401 {
+            return Err(Dyndns2Error::BadAuth.into());
+        }
+
+        parse_response(body, &hosts)?;
+        Ok(body.trim().to_string())
+    }
+
+    async fn clear(&self, _config: &ServiceConfig) -> Result<()> {
+        Err(anyhow!(
+            "The dyndns2 protocol does not support clearing addresses"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::ProviderKind;
+    use crate::service::fixtures::{ADDRESSES, make_config};
+    use crate::service::mock_http::MockServer;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn make_provider(server: &MockServer) -> Dyndns2 {
+        Dyndns2::new(&Dyndns2Config {
+            server: server.url().to_string(),
+            username: "user".to_string(),
+        })
+        .unwrap()
+    }
+
+    async fn update_error(body: &'static str) -> Dyndns2Error {
+        let server = MockServer::start(move |_| (200, body.to_string()));
+        make_provider(&server)
+            .update(
+                &make_config(ProviderKind::Dyndns2, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap_err()
+            .downcast::<Dyndns2Error>()
+            .unwrap()
+    }
+
+    #[test]
+    fn return_codes_map_to_errors() {
+        assert_eq!(parse_return_code("good 1.2.3.4", "h"), Ok(()));
+        assert_eq!(parse_return_code("nochg 1.2.3.4", "h"), Ok(()));
+        assert_eq!(
+            parse_return_code("badauth", "h"),
+            Err(Dyndns2Error::BadAuth)
+        );
+        assert_eq!(
+            parse_return_code("nohost", "h"),
+            Err(Dyndns2Error::NoHost("h".to_string()))
+        );
+        assert_eq!(
+            parse_return_code("notfqdn", "h"),
+            Err(Dyndns2Error::NotFqdn("h".to_string()))
+        );
+        assert_eq!(
+            parse_return_code("numhost", "h"),
+            Err(Dyndns2Error::NumHost)
+        );
+        assert_eq!(
+            parse_return_code("abuse", "h"),
+            Err(Dyndns2Error::Abuse("h".to_string()))
+        );
+        assert_eq!(
+            parse_return_code("badagent", "h"),
+            Err(Dyndns2Error::BadAgent)
+        );
+        assert_eq!(
+            parse_return_code("!donator", "h"),
+            Err(Dyndns2Error::NotDonator)
+        );
+        assert_eq!(
+            parse_return_code("dnserr", "h"),
+            Err(Dyndns2Error::DnsError)
+        );
+        assert_eq!(
+            parse_return_code("911", "h"),
+            Err(Dyndns2Error::ServerError)
+        );
+        assert_eq!(
+            parse_return_code("<html>", "h"),
+            Err(Dyndns2Error::Unexpected("<html>".to_string()))
+        );
+    }
+
+    #[test]
+    fn response_with_one_line_per_host() {
+        let a = "a.example.com".to_string();
+        let b = "b.example.com".to_string();
+        assert_eq!(
+            parse_response("good 1.2.3.4\nnochg 1.2.3.4\n", &[&a, &b]),
+            Ok(())
+        );
+        assert_eq!(
+            parse_response("good 1.2.3.4\nnohost\n", &[&a, &b]),
+            Err(Dyndns2Error::NoHost(b.clone()))
+        );
+    }
+
+    #[test]
+    fn empty_response_is_unexpected() {
+        assert!(matches!(
+            parse_response("  \n", &[]),
+            Err(Dyndns2Error::Unexpected(_))
+        ));
+    }
+
+    #[test]
+    fn build_update_url_ipv4_only() {
+        let url = build_update_url("https://dyn.example", "a.example.com", &ADDRESSES);
+        assert_eq!(
+            url,
+            "https://dyn.example/nic/update?hostname=a.example.com&myip=1.2.3.4"
+        );
+    }
+
+    #[test]
+    fn build_update_url_with_ipv6() {
+        let addresses = PublicAddresses {
+            ipv4: Some(Ipv4Addr::new(1, 2, 3, 4)),
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+        let url = build_update_url("https://dyn.example", "a.example.com", &addresses);
+        assert!(url.ends_with("&myip=1.2.3.4&myipv6=::1"));
+    }
+
+    #[test]
+    fn build_update_url_ipv6_only() {
+        let addresses = PublicAddresses {
+            ipv4: None,
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+        let url = build_update_url("https://dyn.example", "a.example.com", &addresses);
+        assert_eq!(
+            url,
+            "https://dyn.example/nic/update?hostname=a.example.com&myip=::1"
+        );
+    }
+
+    #[test]
+    fn new_requires_username() {
+        let config = Dyndns2Config {
+            server: "https://dyn.example".to_string(),
+            username: String::new(),
+        };
+        assert!(Dyndns2::new(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn update_sends_credentials_and_agent() {
+        let server = MockServer::start(|_| (200, "good 1.2.3.4".to_string()));
+        make_provider(&server)
+            .update(
+                &make_config(ProviderKind::Dyndns2, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(
+            request.path,
+            "/nic/update?hostname=home.example.com&myip=1.2.3.4"
+        );
+        // base64 of "user:token"
+        assert_eq!(
+            request.header("Authorization"),
+            Some("Basic dXNlcjp0b2tlbg==")
+        );
+        assert!(
+            request
+                .header("User-Agent")
+                .unwrap()
+                .starts_with("BarvazDNS/")
+        );
+    }
+
+    #[tokio::test]
+    async fn update_maps_error_codes() {
+        assert_eq!(update_error("badauth").await, Dyndns2Error::BadAuth);
+        assert_eq!(
+            update_error("nohost").await,
+            Dyndns2Error::NoHost("home.example.com".to_string())
+        );
+        assert_eq!(update_error("911").await, Dyndns2Error::ServerError);
+    }
+
+    #[tokio::test]
+    async fn update_treats_401_as_bad_auth() {
+        let server = MockServer::start(|_| (401, "Unauthorized".to_string()));
+        let err = make_provider(&server)
+            .update(
+                &make_config(ProviderKind::Dyndns2, &["home.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast::<Dyndns2Error>().unwrap(),
+            Dyndns2Error::BadAuth
+        );
+    }
+}
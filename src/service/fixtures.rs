@@ -0,0 +1,22 @@
+//! Configurations and addresses shared by the provider tests.
+
+use std::net::Ipv4Addr;
+
+use super::public_address::PublicAddresses;
+use crate::common::config::{ProviderKind, ServiceConfig, Token};
+
+pub const ADDRESSES: PublicAddresses = PublicAddresses {
+    ipv4: Some(Ipv4Addr::new(1, 2, 3, 4)),
+    ipv6: None,
+};
+
+/// A configuration publishing `domains` through `provider`, with `"token"`
+/// as the token.
+pub fn make_config(provider: ProviderKind, domains: &[&str]) -> ServiceConfig {
+    ServiceConfig {
+        provider,
+        token: Some(Token::new("token".to_string())),
+        domain: domains.iter().map(|d| d.to_string()).collect(),
+        ..Default::default()
+    }
+}
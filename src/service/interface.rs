@@ -0,0 +1,369 @@
+//! Reads addresses assigned to the local network interfaces, for hosts that
+//! have a public address configured directly on an interface.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A unicast address assigned to a local interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    /// The interface's friendly name, e.g. `Ethernet`.
+    pub interface: String,
+    pub addr: IpAddr,
+    /// A temporary (privacy extensions, RFC 8981) IPv6 address.
+    pub temporary: bool,
+}
+
+fn is_global_ipv4(addr: &Ipv4Addr) -> bool {
+    let [a, b, ..] = addr.octets();
+    // 100.64.0.0/10 is the carrier-grade NAT range (RFC 6598).
+    let shared = a == 100 && (b & 0xC0) == 64;
+
+    !(addr.is_private()
+        || addr.is_loopback()
+        || addr.is_link_local()
+        || addr.is_unspecified()
+        || addr.is_broadcast()
+        || addr.is_documentation()
+        || shared)
+}
+
+fn is_global_ipv6(addr: &Ipv6Addr) -> bool {
+    !(addr.is_loopback()
+        || addr.is_unspecified()
+        || addr.is_unicast_link_local()
+        || addr.is_unique_local())
+}
+
+/// Whether `address` can be published: a globally routable address that is not
+/// a temporary privacy address, which would change within a day.
+pub fn is_publishable(address: &InterfaceAddress) -> bool {
+    match address.addr {
+        IpAddr::V4(v4) => is_global_ipv4(&v4),
+        IpAddr::V6(v6) => is_global_ipv6(&v6) && !address.temporary,
+    }
+}
+
+/// Picks the first publishable address of `interface` (matched case-insensitively)
+/// for which `family` holds.
+pub fn select(
+    addresses: &[InterfaceAddress],
+    interface: &str,
+    family: impl Fn(&IpAddr) -> bool,
+) -> Option<IpAddr> {
+    addresses
+        .iter()
+        .filter(|a| a.interface.eq_ignore_ascii_case(interface))
+        .filter(|a| family(&a.addr))
+        .find(|a| is_publishable(a))
+        .map(|a| a.addr)
+}
+
+#[cfg(windows)]
+pub use windows::list;
+
+#[cfg(unix)]
+pub use unix::list;
+
+#[cfg(windows)]
+mod windows {
+    use std::io;
+    use std::net::IpAddr;
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, ERROR_SUCCESS};
+    use windows_sys::Win32::NetworkManagement::IpHelper::{
+        GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER, GAA_FLAG_SKIP_MULTICAST,
+        GetAdaptersAddresses, IP_ADAPTER_ADDRESSES_LH,
+    };
+    use windows_sys::Win32::Networking::WinSock::{
+        AF_INET, AF_INET6, AF_UNSPEC, IpDadStatePreferred, IpSuffixOriginRandom, SOCKADDR_IN,
+        SOCKADDR_IN6,
+    };
+
+    use super::InterfaceAddress;
+
+    /// Lists the preferred unicast addresses of all local interfaces.
+    pub fn list() -> io::Result<Vec<InterfaceAddress>> {
+        let flags = GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER;
+        let mut size: u32 = 16 * 1024;
+
+        // The required size can grow between calls if adapters come and go.
+        let buffer = loop {
+            // u64 elements keep the buffer aligned for IP_ADAPTER_ADDRESSES_LH.
+            let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+            let result = unsafe {
+                GetAdaptersAddresses(
+                    AF_UNSPEC as u32,
+                    flags,
+                    ptr::null(),
+                    buffer.as_mut_ptr() as *mut IP_ADAPTER_ADDRESSES_LH,
+                    &mut size,
+                )
+            };
+
+            match result {
+                ERROR_SUCCESS => break buffer,
+                ERROR_BUFFER_OVERFLOW => continue,
+                e => return Err(io::Error::from_raw_os_error(e as i32)),
+            }
+        };
+
+        let mut addresses = Vec::new();
+        let mut adapter = buffer.as_ptr() as *const IP_ADAPTER_ADDRESSES_LH;
+
+        while !adapter.is_null() {
+            let current = unsafe { &*adapter };
+            let interface = unsafe { wide_to_string(current.FriendlyName) };
+
+            let mut unicast = current.FirstUnicastAddress;
+            while !unicast.is_null() {
+                let entry = unsafe { &*unicast };
+                let addr = unsafe { socket_address(entry.Address.lpSockaddr as *const u16) };
+
+                if let Some(addr) = addr
+                    && entry.DadState == IpDadStatePreferred
+                {
+                    addresses.push(InterfaceAddress {
+                        interface: interface.clone(),
+                        addr,
+                        // Windows reports privacy addresses with a random suffix,
+                        // while stable addresses derive theirs from the link layer.
+                        temporary: addr.is_ipv6() && entry.SuffixOrigin == IpSuffixOriginRandom,
+                    });
+                }
+
+                unicast = entry.Next;
+            }
+
+            adapter = current.Next;
+        }
+
+        Ok(addresses)
+    }
+
+    /// Reads an IPv4 or IPv6 address from a `SOCKADDR`, whose first field is the family.
+    unsafe fn socket_address(sockaddr: *const u16) -> Option<IpAddr> {
+        if sockaddr.is_null() {
+            return None;
+        }
+
+        unsafe {
+            match *sockaddr {
+                AF_INET => {
+                    let sa = &*(sockaddr as *const SOCKADDR_IN);
+                    Some(IpAddr::from(sa.sin_addr.S_un.S_addr.to_ne_bytes()))
+                }
+                AF_INET6 => {
+                    let sa = &*(sockaddr as *const SOCKADDR_IN6);
+                    Some(IpAddr::from(sa.sin6_addr.u.Byte))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    unsafe fn wide_to_string(s: *const u16) -> String {
+        if s.is_null() {
+            return String::new();
+        }
+
+        unsafe {
+            let len = (0..).take_while(|&i| *s.add(i) != 0).count();
+            String::from_utf16_lossy(std::slice::from_raw_parts(s, len))
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::CStr;
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::InterfaceAddress;
+
+    /// Lists the unicast addresses of all local interfaces.
+    ///
+    /// On Linux, IPv6 addresses come from `/proc/net/if_inet6`, which unlike
+    /// `getifaddrs` reports whether an address is temporary or not yet usable.
+    pub fn list() -> io::Result<Vec<InterfaceAddress>> {
+        let mut addresses = getifaddrs()?;
+
+        #[cfg(target_os = "linux")]
+        if let Ok(content) = std::fs::read_to_string("/proc/net/if_inet6") {
+            addresses.retain(|a| a.addr.is_ipv4());
+            addresses.extend(super::parse_if_inet6(&content));
+        }
+
+        Ok(addresses)
+    }
+
+    fn getifaddrs() -> io::Result<Vec<InterfaceAddress>> {
+        let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut head) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut addresses = Vec::new();
+        let mut current = head;
+        while !current.is_null() {
+            let entry = unsafe { &*current };
+            if let Some(addr) = unsafe { socket_address(entry.ifa_addr) } {
+                addresses.push(InterfaceAddress {
+                    interface: unsafe { CStr::from_ptr(entry.ifa_name) }
+                        .to_string_lossy()
+                        .into_owned(),
+                    addr,
+                    temporary: false,
+                });
+            }
+            current = entry.ifa_next;
+        }
+
+        unsafe { libc::freeifaddrs(head) };
+        Ok(addresses)
+    }
+
+    /// Reads an IPv4 or IPv6 address from a `sockaddr`.
+    unsafe fn socket_address(sockaddr: *const libc::sockaddr) -> Option<IpAddr> {
+        if sockaddr.is_null() {
+            return None;
+        }
+
+        unsafe {
+            match (*sockaddr).sa_family as libc::c_int {
+                libc::AF_INET => {
+                    let sa = &*(sockaddr as *const libc::sockaddr_in);
+                    Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr))))
+                }
+                libc::AF_INET6 => {
+                    let sa = &*(sockaddr as *const libc::sockaddr_in6);
+                    Some(IpAddr::V6(Ipv6Addr::from(sa.sin6_addr.s6_addr)))
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Flags of addresses that must not be published: duplicate address detection
+/// failed or is still running, or the address is deprecated.
+#[cfg(target_os = "linux")]
+const IFA_F_UNUSABLE: u32 = libc::IFA_F_DADFAILED | libc::IFA_F_DEPRECATED | libc::IFA_F_TENTATIVE;
+
+/// Parses `/proc/net/if_inet6`, skipping addresses that are deprecated or
+/// did not pass duplicate address detection. Each line holds the address
+/// in hex, the interface index, prefix length, scope, flags (all hex) and
+/// the interface name.
+#[cfg(target_os = "linux")]
+fn parse_if_inet6(content: &str) -> Vec<InterfaceAddress> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [hex, _index, _prefix, _scope, flags, name] = fields[..] else {
+                return None;
+            };
+            let addr = u128::from_str_radix(hex, 16).ok()?;
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            (flags & IFA_F_UNUSABLE == 0).then(|| InterfaceAddress {
+                interface: name.to_string(),
+                addr: IpAddr::V6(Ipv6Addr::from(addr)),
+                temporary: flags & libc::IFA_F_TEMPORARY != 0,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(interface: &str, addr: &str, temporary: bool) -> InterfaceAddress {
+        InterfaceAddress {
+            interface: interface.to_string(),
+            addr: addr.parse().unwrap(),
+            temporary,
+        }
+    }
+
+    #[test]
+    fn non_global_addresses_are_not_publishable() {
+        for addr in [
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "100.64.0.1",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "fc00::1",
+        ] {
+            assert!(!is_publishable(&address("eth0", addr, false)), "{addr}");
+        }
+    }
+
+    #[test]
+    fn global_addresses_are_publishable() {
+        assert!(is_publishable(&address("eth0", "8.8.8.8", false)));
+        assert!(is_publishable(&address("eth0", "2001:4860::1", false)));
+    }
+
+    #[test]
+    fn temporary_addresses_are_not_publishable() {
+        assert!(!is_publishable(&address("eth0", "2001:4860::1", true)));
+    }
+
+    #[test]
+    fn select_filters_interface_and_family() {
+        let addresses = [
+            address("Wi-Fi", "8.8.4.4", false),
+            address("Ethernet", "fe80::1", false),
+            address("Ethernet", "2001:db8:1::abcd", true),
+            address("Ethernet", "192.168.1.10", false),
+            address("Ethernet", "2001:4860::10", false),
+            address("Ethernet", "8.8.8.8", false),
+        ];
+
+        assert_eq!(
+            select(&addresses, "ethernet", IpAddr::is_ipv6),
+            Some("2001:4860::10".parse().unwrap())
+        );
+        assert_eq!(
+            select(&addresses, "Ethernet", IpAddr::is_ipv4),
+            Some("8.8.8.8".parse().unwrap())
+        );
+        assert_eq!(select(&addresses, "Loopback", IpAddr::is_ipv4), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parses_proc_if_inet6() {
+        let content = "\
+00000000000000000000000000000001 01 80 10 80       lo
+fe800000000000000211223344556677 02 40 20 80     eth0
+20014860000000000000000000000010 02 40 00 80     eth0
+20014860000000000000000000abcdef 02 40 00 01     eth0
+20014860000000000000000000000020 02 40 00 c0     eth0
+20014860000000000000000000000030 02 40 00 02     eth0
+20014860000000000000000000000040 02 40 00 08     eth0
+";
+        let addresses = parse_if_inet6(content);
+        assert_eq!(
+            addresses,
+            vec![
+                address("lo", "::1", false),
+                address("eth0", "fe80::211:2233:4455:6677", false),
+                address("eth0", "2001:4860::10", false),
+                address("eth0", "2001:4860::ab:cdef", true),
+                address("eth0", "2001:4860::30", false),
+            ]
+        );
+        assert_eq!(
+            select(&addresses, "eth0", IpAddr::is_ipv6),
+            Some("2001:4860::10".parse().unwrap())
+        );
+    }
+}
@@ -1,56 +1,113 @@
 use anyhow::{Result, anyhow};
-use flexi_logger::{Cleanup, FileSpec, LogSpecification, Logger, LoggerHandle, WriteMode};
-use std::ffi::OsString;
+use flexi_logger::{
+    Cleanup, Duplicate, FileSpec, LogSpecification, Logger, LoggerHandle, WriteMode,
+    writers::LogWriter,
+};
 use std::io::Write;
-use std::sync::{Arc, mpsc};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use tokio::io::AsyncWriteExt;
-use tokio::net::windows::named_pipe::NamedPipeServer;
 use tokio::runtime::Runtime;
-use tokio::sync::Mutex;
-use windows_service::service::{
-    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
-};
-use windows_service::{
-    define_windows_service,
-    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
-    service_dispatcher,
-};
-use windows_sys::Win32::System::SystemInformation::GetTickCount64;
+use tokio::sync::{Mutex, broadcast, oneshot};
 
 use flexi_logger::{DeferredNow, Record};
 
 use crate::common::strings::VERSION;
 use crate::common::{
     self,
-    config::Config,
-    message::{self, Request, Response, ServiceRequest, UpdateStatus},
+    config::{Config, ServiceConfig},
+    history::History,
+    message::{Event, Hello, Request, Response, UpdateStatus, Welcome},
+    metrics::Metrics,
+    transport::Transport,
 };
 
+mod backoff;
+mod cloudflare;
+mod dns_wire;
 mod duckdns;
+mod dyndns2;
+#[cfg(test)]
+mod fixtures;
+mod interface;
+#[cfg(test)]
+mod mock_http;
+#[cfg(windows)]
 mod named_pipe;
-use named_pipe::{NamedPipeServerWithTimeout, create_admin_pipe};
+mod provider;
+mod public_address;
+mod published;
+mod rfc2136;
+#[cfg(unix)]
+mod systemd;
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+mod unix_socket;
+mod webhook;
+#[cfg(windows)]
+mod windows;
+use backoff::Backoff;
+#[cfg(windows)]
+use named_pipe::IpcListener;
+use provider::{DnsProvider, Provider, Report};
+#[cfg(unix)]
+pub use unix::run_daemon;
+#[cfg(unix)]
+use unix_socket::IpcListener;
+#[cfg(windows)]
+pub use windows::service_dispatcher;
+
+fn log_formatter(
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> Result<(), std::io::Error> {
+    write!(
+        w,
+        "[{}] {} [{}]: {}",
+        now.now().format("%Y-%m-%d %H:%M:%S"),
+        record.level(),
+        record.module_path().unwrap_or("<unknown>"),
+        record.args()
+    )
+}
+
+/// Publishes every log line as an [`Event::Log`], formatted like the lines in
+/// the log file.
+struct EventLogWriter {
+    events: broadcast::Sender<Event>,
+}
 
-define_windows_service!(duckdns_service_main, service_main);
+impl LogWriter for EventLogWriter {
+    fn write(&self, now: &mut DeferredNow, record: &Record) -> std::io::Result<()> {
+        if self.events.receiver_count() == 0 {
+            return Ok(());
+        }
+        let mut line = Vec::new();
+        log_formatter(&mut line, now, record)?;
+        let _ = self
+            .events
+            .send(Event::Log(String::from_utf8_lossy(&line).into_owned()));
+        Ok(())
+    }
 
-pub fn service_dispatcher() -> Result<()> {
-    service_dispatcher::start(common::strings::SERVICE_NAME, duckdns_service_main)
-        .map_err(|e| anyhow!("Dispatching error: {e:#?}"))
+    fn flush(&self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
-fn logger_init(log_level: &str) -> Result<LoggerHandle> {
-    let log_formatter =
-        |w: &mut dyn Write, now: &mut DeferredNow, record: &Record| -> Result<(), std::io::Error> {
-            write!(
-                w,
-                "[{}] {} [{}]: {}",
-                now.now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                record.module_path().unwrap_or("<unknown>"),
-                record.args()
-            )
-        };
+/// The channel that events are published on for subscribed clients.
+fn event_channel() -> broadcast::Sender<Event> {
+    broadcast::Sender::new(common::consts::EVENT_BACKLOG)
+}
 
+/// Starts logging to the config directory, and to stderr as well when running
+/// in the foreground. Log lines are published on `events` too.
+fn logger_init(
+    log_level: &str,
+    to_stderr: bool,
+    events: broadcast::Sender<Event>,
+) -> Result<LoggerHandle> {
     let path = Config::get_config_directory_path()?;
 
     if !path.is_dir() {
@@ -62,11 +119,12 @@ fn logger_init(log_level: &str) -> Result<LoggerHandle> {
 
     Logger::try_with_str(log_level)
         .map_err(|e| anyhow!("Invalid log level '{log_level}': {e}"))?
-        .log_to_file(
+        .log_to_file_and_writer(
             FileSpec::default()
                 .directory(path)
                 .basename(common::strings::LOG_FILE_BASENAME)
                 .suppress_timestamp(),
+            Box::new(EventLogWriter { events }),
         )
         .rotate(
             flexi_logger::Criterion::Size(common::consts::LOG_ROTATION_SIZE),
@@ -74,111 +132,87 @@ fn logger_init(log_level: &str) -> Result<LoggerHandle> {
             Cleanup::KeepLogFiles(common::consts::LOG_KEEP_FILES),
         )
         .write_mode(WriteMode::Direct)
+        .duplicate_to_stderr(if to_stderr {
+            Duplicate::All
+        } else {
+            Duplicate::None
+        })
         .format_for_files(log_formatter)
         .append()
         .start()
         .map_err(|e| anyhow!("{e}"))
 }
 
-fn set_service_status(
-    status_handle: &ServiceStatusHandle,
-    current_state: ServiceState,
-    exit_code: u32,
-) -> Result<(), windows_service::Error> {
-    let next_status = ServiceStatus {
-        service_type: ServiceType::OWN_PROCESS,
-        current_state,
-        controls_accepted: if current_state == ServiceState::Running {
-            ServiceControlAccept::STOP
-        } else {
-            ServiceControlAccept::empty()
-        },
-        exit_code: ServiceExitCode::Win32(exit_code),
-        checkpoint: 0,
-        wait_hint: if current_state == ServiceState::StartPending {
-            Duration::from_secs(10)
-        } else {
-            Duration::default()
-        },
-        process_id: None,
-    };
-
-    status_handle.set_service_status(next_status)
+/// Commands handled by the update loop.
+enum UpdateCommand {
+    /// Switches to a changed configuration and updates with it.
+    Reconfigure(Box<Config>),
+    /// Publishes now, even if the addresses are unchanged, and reports the
+    /// result. Requests that arrive while a forced update is in flight share
+    /// its result instead of starting another one.
+    Force(oneshot::Sender<Result<(), String>>),
 }
 
+/// State shared by every client connection and the update loop.
+#[derive(Clone)]
 struct ServiceContext {
     logger_handle: LoggerHandle,
-    status_handle: ServiceStatusHandle,
-    config: Config,
+    config: Arc<Mutex<Config>>,
     update_status: Arc<Mutex<UpdateStatus>>,
+    metrics: Arc<Mutex<Metrics>>,
+    /// Changes pushed to subscribed clients.
+    events: broadcast::Sender<Event>,
 }
 
 fn log_config_warnings(config: &Config) {
-    if config.service.token.is_none() {
-        log::warn!("No token is configured");
-    }
-    if config.service.domain.is_empty() {
+    let domains = config.resolved_domains();
+    if domains.is_empty() {
         log::warn!("No domain is configured");
+    } else if domains.len() > config.service.max_domains {
+        log::warn!(
+            "{} domains are configured, more than max_domains ({})",
+            domains.len(),
+            config.service.max_domains
+        );
     }
-}
-
-fn ensure_config_directory() -> Result<()> {
-    let path = Config::get_config_directory_path()?;
-    if !path.is_dir() {
-        std::fs::create_dir_all(&path)
-            .map_err(|e| anyhow!("Failed to create config directory: {e}"))?;
+    for domain in domains
+        .iter()
+        .filter(|d| d.token.is_none() && d.provider.requires_token())
+    {
+        log::warn!("No token is configured for {}", domain.name);
     }
-    Ok(())
-}
 
-fn service_main(_args: Vec<OsString>) {
-    let (shutdown_tx, shutdown_rx) = mpsc::channel();
-
-    let event_handler = move |control_event| -> ServiceControlHandlerResult {
-        match control_event {
-            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
-            ServiceControl::Stop => {
-                let _ = shutdown_tx.send(());
-                ServiceControlHandlerResult::NoError
+    let groups = config.group_domains(&domains);
+    if groups.is_empty()
+        && let Err(e) = Provider::for_config(&config.service)
+    {
+        log::warn!("{e}");
+    }
+    for group in &groups {
+        match Provider::for_config(group) {
+            Ok(provider) => {
+                for name in &group.domain {
+                    if let Err(e) = provider.validate_name(name) {
+                        log::warn!("{e}");
+                    }
+                }
             }
-            _ => ServiceControlHandlerResult::NotImplemented,
+            Err(e) => log::warn!("{e}"),
         }
-    };
-
-    // Register system service event handler
-    let status_handle =
-        service_control_handler::register(common::strings::SERVICE_NAME, event_handler).unwrap();
-
-    set_service_status(&status_handle, ServiceState::StartPending, 0).unwrap();
-
-    // Ensure config directory exists
-    if let Err(e) = ensure_config_directory() {
-        eprintln!("{e}");
-        set_service_status(&status_handle, ServiceState::Stopped, 3).unwrap();
-        return;
     }
+}
 
-    // Initialize logger with default level first so early log messages are captured
-    let logger_handle = match logger_init("info") {
-        Err(e) => {
-            eprintln!("Failed to initialize logger: {e}");
-            set_service_status(&status_handle, ServiceState::Stopped, 2).unwrap();
-            return;
-        }
-        Ok(handle) => handle,
-    };
-
-    // Read config (may emit log messages)
-    let config = match Config::read() {
-        Ok(c) => c,
-        Err(e) => {
-            log::error!("{e}");
-            set_service_status(&status_handle, ServiceState::Stopped, 1).unwrap();
-            return;
-        }
-    };
+fn ensure_config_directory() -> Result<()> {
+    common::files::create_private_directory(&Config::get_config_directory_path()?)
+}
 
-    // Apply the configured log level (env var overrides config)
+/// Applies the configured log level (the environment variable overrides the
+/// config) and builds the context shared by the listening and update loops.
+fn new_context(
+    logger_handle: LoggerHandle,
+    config: Config,
+    events: broadcast::Sender<Event>,
+) -> ServiceContext {
     let level = std::env::var(common::strings::ENV_VAR_LOG_LEVEL)
         .unwrap_or_else(|_| config.service.log_level.clone());
     if let Ok(spec) = LogSpecification::parse(&level) {
@@ -188,28 +222,23 @@ fn service_main(_args: Vec<OsString>) {
     log::debug!("Service is running with the following configuration:\n{config}");
     log_config_warnings(&config);
 
-    let context = ServiceContext {
-        status_handle,
+    ServiceContext {
         logger_handle,
-        config,
+        config: Arc::new(Mutex::new(config)),
         update_status: Arc::new(Mutex::new(UpdateStatus::default())),
-    };
-
-    if let Err(e) = run_service(context, shutdown_rx) {
-        log::error!("Service failed: {:?}", e);
+        metrics: Arc::new(Mutex::new(Metrics::new(SystemTime::now()))),
+        events,
     }
 }
 
-// Validates that a domain name only contains characters safe for use in a DuckDNS subdomain.
-// Prevents URL parameter injection via crafted domain strings.
-fn is_valid_domain(domain: &str) -> bool {
-    !domain.is_empty()
-        && domain.len() <= 63
-        && domain
-            .chars()
-            .all(|c| c.is_ascii_alphanumeric() || c == '-')
-        && !domain.starts_with('-')
-        && !domain.ends_with('-')
+/// The configuration as clients see it: `[[domains]]` entries are listed
+/// along with the names in `service.domain`, so that clients show every
+/// configured domain.
+fn listed_config(config: &Config) -> ServiceConfig {
+    ServiceConfig {
+        domain: config.domain_names(),
+        ..config.service.clone()
+    }
 }
 
 fn validate_interval(interval: &Duration) -> Result<()> {
@@ -224,13 +253,17 @@ fn validate_interval(interval: &Duration) -> Result<()> {
     }
 }
 
-fn validate_add_domain(domain: &str, existing: &std::collections::BTreeSet<String>) -> Result<()> {
-    if !is_valid_domain(domain) {
-        Err(anyhow!("Invalid domain name: {domain}"))
-    } else if existing.len() >= common::consts::MAX_DOMAIN_COUNT {
+fn validate_add_domain(
+    provider: &impl DnsProvider,
+    domain: &str,
+    existing: &std::collections::BTreeSet<String>,
+    limit: usize,
+) -> Result<()> {
+    provider.validate_name(domain)?;
+    if existing.len() >= limit {
         Err(anyhow!(
-            "The number of domains to update is limited to {}",
-            common::consts::MAX_DOMAIN_COUNT
+            "The number of domains to update is limited to {limit}. \
+             Raise max_domains in the configuration to add more"
         ))
     } else if existing.contains(domain) {
         Err(anyhow!("Domain {domain} already exists"))
@@ -252,48 +285,65 @@ fn validate_remove_domain(
 
 async fn handle_message(
     msg: &Request,
-    context: &mut ServiceContext,
-    update_tx: &tokio::sync::mpsc::Sender<Config>,
+    context: &ServiceContext,
+    update_tx: &tokio::sync::mpsc::Sender<UpdateCommand>,
 ) -> Result<Response> {
     log::debug!("Received: {:?}", msg);
 
-    let res = match msg {
+    // Changes lock the configuration until they are stored and handed to the
+    // update loop, so concurrent changes from several clients apply in order.
+    // Everything else returns early without waiting for them.
+    let mut config = match msg {
         Request::Interval(interval) => {
             validate_interval(interval)?;
-            context.config.service.interval = *interval;
-            Ok(Response::Ok)
+            let mut config = context.config.lock().await;
+            config.service.interval = *interval;
+            config
         }
         Request::AddDomain(domain) => {
-            validate_add_domain(domain, &context.config.service.domain)?;
-            context.config.service.domain.insert(domain.clone());
-            Ok(Response::Ok)
+            let mut config = context.config.lock().await;
+            let provider = Provider::for_config(&config.service)?;
+            validate_add_domain(
+                &provider,
+                domain,
+                &config.domain_names(),
+                config.service.max_domains,
+            )?;
+            config.service.domain.insert(domain.clone());
+            config
         }
         Request::RemoveDomain(domain) => {
-            validate_remove_domain(domain, &context.config.service.domain)?;
-            context.config.service.domain.remove(domain);
-            Ok(Response::Ok)
+            let mut config = context.config.lock().await;
+            validate_remove_domain(domain, &config.domain_names())?;
+            config.service.domain.remove(domain);
+            config.domains.retain(|d| &d.name != domain);
+            config
         }
         Request::Token(token) => {
-            context.config.service.token.replace(token.clone());
-            Ok(Response::Ok)
+            let mut config = context.config.lock().await;
+            config.service.token.replace(token.clone());
+            config
         }
         Request::Ipv6(enable) => {
-            context.config.service.ipv6.replace(*enable);
-            context.config.service.clear_ip_addresses = true;
-            Ok(Response::Ok)
+            let mut config = context.config.lock().await;
+            config.service.ipv6.replace(*enable);
+            config.service.clear_ip_addresses = true;
+            config
         }
         Request::ForceUpdate => {
-            context.config = Config::read()?;
-            let domains: Vec<String> = context.config.service.domain.iter().cloned().collect();
-            match duckdns::update(&context.config).await {
-                Ok(()) => {
-                    let mut status = context.update_status.lock().await;
-                    status.last_success = Some((SystemTime::now(), domains));
+            let (reply_tx, reply_rx) = oneshot::channel();
+            update_tx
+                .send(UpdateCommand::Force(reply_tx))
+                .await
+                .map_err(|e| anyhow!("Failed to notify update loop: {e}"))?;
+            return match reply_rx.await {
+                Ok(Ok(())) => {
                     log::info!("Force update succeeded");
-                    return Ok(Response::Ok);
+                    Ok(Response::Ok)
                 }
-                Err(e) => Err(anyhow!("Update failed: {e}")),
-            }
+                Ok(Err(e)) => Err(anyhow!("Update failed: {e}")),
+                Err(_) => Err(anyhow!("The update loop stopped before updating")),
+            };
         }
         Request::DebugLevel(level) => {
             let new_spec = LogSpecification::parse(level)?;
@@ -302,44 +352,64 @@ async fn handle_message(
             return Ok(Response::Ok);
         }
         Request::GetConfig => {
-            return Ok(Response::Config(context.config.service.clone()));
+            let config = context.config.lock().await;
+            return Ok(Response::Config(Box::new(listed_config(&config))));
         }
         Request::GetStatus => {
             let status = context.update_status.lock().await;
             return Ok(Response::Status(status.clone()));
         }
+        Request::GetHistory(limit) => {
+            return Ok(Response::History(History::read().latest(*limit)));
+        }
         Request::Version => {
             return Ok(Response::Version(VERSION.to_string()));
         }
-    }?;
+        Request::GetMetrics => {
+            let metrics = context.metrics.lock().await;
+            return Ok(Response::Metrics(Box::new(metrics.clone())));
+        }
+        // The connection is handed over to `stream_events` before it gets here.
+        Request::Subscribe => {
+            return Err(anyhow!("Subscriptions cannot be combined with requests"));
+        }
+    };
 
-    context.config.store()?;
-    log_config_warnings(&context.config);
+    config.store()?;
+    log_config_warnings(&config);
     update_tx
-        .send(context.config.clone())
+        .send(UpdateCommand::Reconfigure(Box::new(config.clone())))
         .await
         .map_err(|e| anyhow!("Failed to notify update loop: {e}"))?;
-
-    log::debug!("New config:\n{}", context.config);
-    Ok(res)
+    // The update loop clears the published addresses once with its copy, so
+    // later changes must not ask for that again.
+    config.service.clear_ip_addresses = false;
+    let _ = context
+        .events
+        .send(Event::Config(Box::new(listed_config(&config))));
+
+    log::debug!("New config:\n{}", *config);
+    Ok(Response::Ok)
 }
 
-async fn send_response(pipe: &mut NamedPipeServer, response: Response) -> Result<()> {
+async fn send_response(stream: &mut impl Transport, response: Response) -> Result<()> {
     log::debug!("response is {response:?}");
-    let encoded = message::encode(&response)?;
-    pipe.write_all(&encoded).await?;
-    Ok(())
+    stream.write_message(&response).await
 }
 
 async fn force_update_on_service_start(
-    update_tx: &tokio::sync::mpsc::Sender<Config>,
-    config: &Config,
+    update_tx: &tokio::sync::mpsc::Sender<UpdateCommand>,
+    config: &Mutex<Config>,
     max_delay: Duration,
 ) {
-    let ms_since_boot = unsafe { GetTickCount64() };
-    let uptime = Duration::from_millis(ms_since_boot);
-
-    if uptime < max_delay {
+    #[cfg(windows)]
+    let uptime = windows::uptime();
+    #[cfg(unix)]
+    let uptime = unix::uptime();
+
+    if let Some(uptime) = uptime
+        && uptime < max_delay
+    {
         let to_sleep = max_delay - uptime;
         log::info!(
             "System just booted (uptime {}, delaying update by {})",
@@ -349,14 +419,172 @@ async fn force_update_on_service_start(
         tokio::time::sleep(to_sleep).await;
     }
 
-    if let Err(e) = update_tx.send(config.clone()).await {
+    // Clients may have changed the configuration while waiting.
+    let config = config.lock().await.clone();
+    if let Err(e) = update_tx
+        .send(UpdateCommand::Reconfigure(Box::new(config)))
+        .await
+    {
         log::error!("Failed to request an update: {e}");
     }
 }
 
+/// Answers the client's [`Hello`]. Returns false if the connection should be
+/// closed.
+async fn greet_client(stream: &mut impl Transport) -> bool {
+    let read =
+        tokio::time::timeout(common::consts::PIPE_TIMEOUT, stream.read_message::<Hello>()).await;
+
+    let hello = match read {
+        Err(_) => {
+            log::error!("Read error: timed out");
+            return false;
+        }
+        Ok(Ok(None)) => {
+            log::debug!("Client disconnected");
+            return false;
+        }
+        Ok(Ok(Some(hello))) => hello,
+        Ok(Err(e)) => {
+            log::error!("Failed to read the client hello: {e}");
+            return false;
+        }
+    };
+    log::debug!(
+        "Client version {} speaks protocol {}",
+        hello.version,
+        hello.protocol
+    );
+
+    // The client learns from the welcome whether it is compatible, so send it
+    // either way.
+    if let Err(e) = stream.write_message(&Welcome::current()).await {
+        log::error!("Failed to send welcome: {e}");
+        return false;
+    }
+
+    if !hello.is_compatible() {
+        log::error!(
+            "Client protocol {} is no longer supported. Client version: {}, Service version: {}",
+            hello.protocol,
+            hello.version,
+            common::strings::VERSION
+        );
+        return false;
+    }
+    true
+}
+
+/// Sends events to a client that subscribed, starting with the current status
+/// and configuration, until it hangs up. Nothing is logged per event, since
+/// every log line is an event itself.
+async fn stream_events(stream: &mut impl Transport, context: &ServiceContext) {
+    // Subscribe before taking the snapshot, so that no change falls between.
+    let mut events = context.events.subscribe();
+    let snapshot = [
+        Event::Status(context.update_status.lock().await.clone()),
+        Event::Config(Box::new(listed_config(&*context.config.lock().await))),
+    ];
+    for event in snapshot {
+        if stream.write_message(&Response::Event(event)).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            // Subscribed clients send nothing more, so the read only
+            // completes when they hang up.
+            _ = stream.read_message::<Request>() => {
+                log::debug!("Subscriber disconnected");
+                return;
+            }
+        };
+        match event {
+            Ok(event) => {
+                if stream.write_message(&Response::Event(event)).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                log::debug!("A subscriber fell behind and missed {missed} events");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Serves requests from a connected client until it disconnects or stays idle
+/// for longer than `PIPE_TIMEOUT`. A client that subscribes gets events
+/// instead, for as long as it stays connected.
+async fn serve_client(
+    stream: &mut impl Transport,
+    context: &ServiceContext,
+    update_tx: &tokio::sync::mpsc::Sender<UpdateCommand>,
+) {
+    if !greet_client(stream).await {
+        return;
+    }
+
+    loop {
+        let read = tokio::time::timeout(
+            common::consts::PIPE_TIMEOUT,
+            stream.read_message::<Request>(),
+        )
+        .await;
+
+        let msg = match read {
+            Err(_) => {
+                log::debug!("Client is idle, closing the connection");
+                return;
+            }
+            Ok(Ok(None)) => {
+                log::debug!("Client disconnected");
+                return;
+            }
+            Ok(Ok(Some(Request::Subscribe))) => {
+                log::debug!("Client subscribed to events");
+                stream_events(stream, context).await;
+                return;
+            }
+            Ok(Ok(Some(msg))) => msg,
+            Ok(Err(e)) => {
+                log::error!("Failed to read a request: {e}");
+                if let Err(e) = send_response(stream, Response::Err(e.to_string())).await {
+                    log::debug!("Failed to send error response: {e}");
+                    return;
+                }
+                // A request this service does not understand still arrived in
+                // a complete frame, but after a bad frame the rest of the
+                // stream can't be framed.
+                if e.downcast_ref::<serde_json::Error>().is_some() {
+                    continue;
+                }
+                return;
+            }
+        };
+
+        let res = match handle_message(&msg, context, update_tx).await {
+            Err(e) => {
+                log::error!("Failed to handle request, error: {e}");
+                Response::Err(e.to_string())
+            }
+            Ok(res) => res,
+        };
+        if let Err(e) = send_response(stream, res).await {
+            log::error!("Failed to send response: {e}");
+            return;
+        }
+    }
+}
+
+/// Accepts clients and serves each of them on its own task, so a slow request
+/// such as a forced update doesn't hold up the others.
 async fn service_listening_loop(
-    mut context: ServiceContext,
-    update_tx: tokio::sync::mpsc::Sender<Config>,
+    context: ServiceContext,
+    mut listener: IpcListener,
+    update_tx: tokio::sync::mpsc::Sender<UpdateCommand>,
 ) {
     force_update_on_service_start(
         &update_tx,
@@ -366,150 +594,279 @@ async fn service_listening_loop(
     .await;
 
     loop {
-        match create_admin_pipe(common::strings::PIPE_NAME) {
-            Ok(mut pipe) => {
-                log::debug!("Waiting for a client...");
-                if let Err(e) = pipe
-                    .connect_with_timeout(common::consts::PIPE_TIMEOUT)
-                    .await
-                {
-                    log::debug!("Pipe connection error: {:?}", e);
-                    continue;
-                }
+        log::debug!("Waiting for a client...");
+        match listener.accept().await {
+            Ok(mut stream) => {
                 log::debug!("Client connected");
-
-                let mut buffer = vec![0; common::consts::PIPE_BUFFER_SIZE];
-                match pipe
-                    .read_with_timeout(&mut buffer, common::consts::PIPE_TIMEOUT)
-                    .await
-                {
-                    Ok(0) => {
-                        log::debug!("Client disconnected");
-                    }
-                    Ok(bytes_read) => {
-                        let msg: ServiceRequest = match message::decode(&buffer) {
-                            Ok(m) => m,
-                            Err(e) => {
-                                log::error!("Failed to deserialize message, error: {e}");
-                                log::debug!(
-                                    "read {bytes_read} bytes, request size: {} bytes",
-                                    std::mem::size_of::<ServiceRequest>()
-                                );
-                                continue;
-                            }
-                        };
-                        if !msg.is_compatible() {
-                            log::error!(
-                                "Client version incompatible. Client version: {}, Service version: {}",
-                                msg.version(),
-                                common::strings::VERSION
-                            );
-                            let res = Response::Err("Client version incompatible".to_string());
-                            if let Err(e) = send_response(&mut pipe, res).await {
-                                log::error!("Failed to send response: {e}");
-                            }
-                            continue;
-                        }
-                        match handle_message(msg.request(), &mut context, &update_tx).await {
-                            Err(e) => {
-                                log::error!("Failed to handle request, error: {e}");
-                                if let Err(e) =
-                                    send_response(&mut pipe, Response::Err(e.to_string())).await
-                                {
-                                    log::error!("Failed to send error response: {e}");
-                                }
-                            }
-                            Ok(res) => {
-                                if let Err(e) = send_response(&mut pipe, res).await {
-                                    log::error!("Failed to send response: {e}");
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Read error: {:?}", e);
-                    }
-                }
+                let context = context.clone();
+                let update_tx = update_tx.clone();
+                tokio::spawn(async move {
+                    serve_client(&mut stream, &context, &update_tx).await;
+                });
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                log::debug!("Connection error: {:?}", e);
             }
             Err(e) => {
-                log::error!("Failed to create pipe: {e:?}")
+                log::error!("Failed to accept a client: {e:?}");
             }
         }
     }
 }
 
+/// The schedule for `config`, restarted so that the next regular update is a
+/// full interval away.
+fn restart_schedule(config: &Config) -> (tokio::time::Interval, Backoff) {
+    let mut interval = tokio::time::interval(config.update_interval());
+    interval.reset();
+    let backoff = Backoff::new(
+        common::consts::RETRY_BASE_DELAY,
+        config.service.max_retry_delay,
+    );
+    (interval, backoff)
+}
+
 async fn update_ip_loop(
-    mut receiver: tokio::sync::mpsc::Receiver<Config>,
+    receiver: tokio::sync::mpsc::Receiver<UpdateCommand>,
+    initial_config: Config,
+    update_status: Arc<Mutex<UpdateStatus>>,
+    metrics: Arc<Mutex<Metrics>>,
+    events: broadcast::Sender<Event>,
+) {
+    update_loop(
+        receiver,
+        initial_config,
+        update_status,
+        metrics,
+        events,
+        provider::publish,
+    )
+    .await
+}
+
+/// Completes on the next tick of the watchdog interval, if the watchdog is
+/// enabled.
+async fn watchdog_tick(watchdog: &mut Option<tokio::time::Interval>) {
+    match watchdog {
+        Some(watchdog) => {
+            watchdog.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The body of [`update_ip_loop`], publishing through `publish` so that tests
+/// can stand in for the provider.
+async fn update_loop(
+    mut receiver: tokio::sync::mpsc::Receiver<UpdateCommand>,
     initial_config: Config,
     update_status: Arc<Mutex<UpdateStatus>>,
+    metrics: Arc<Mutex<Metrics>>,
+    events: broadcast::Sender<Event>,
+    publish: impl AsyncFn(&Config, bool) -> Report,
 ) {
     let mut config = initial_config;
-    let mut interval = tokio::time::interval(config.service.interval);
+    let mut interval = tokio::time::interval(config.update_interval());
+    let mut backoff = Backoff::new(
+        common::consts::RETRY_BASE_DELAY,
+        config.service.max_retry_delay,
+    );
+    let mut retry_at: Option<tokio::time::Instant> = None;
     // The first tick completes immediately, which triggers the initial update.
-    // Subsequent ticks follow the configured interval.
+    // Subsequent ticks follow the configured interval, and failed updates are
+    // retried in between with exponential backoff.
+
+    // Replies owed to force requests. While there are any, a forced update is
+    // due right away.
+    let mut forced: Vec<oneshot::Sender<Result<(), String>>> = Vec::new();
+    // Set when the configuration changed during an update, so that another
+    // one follows right away with the new configuration.
+    let mut due = false;
+
+    // Pinging the watchdog from this loop lets systemd restart the service if
+    // an update hangs.
+    #[cfg(unix)]
+    let mut watchdog = systemd::watchdog_interval().map(tokio::time::interval);
+    #[cfg(windows)]
+    let mut watchdog: Option<tokio::time::Interval> = None;
 
     loop {
-        tokio::select! {
-            Some(c) = receiver.recv() => {
-                config = c;
-                interval = tokio::time::interval(config.service.interval);
-                interval.reset();
+        if !due && forced.is_empty() {
+            let retry = async {
+                match retry_at {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                Some(command) = receiver.recv() => match command {
+                    UpdateCommand::Reconfigure(c) => {
+                        config = *c;
+                        // A new configuration cancels any pending retry.
+                        (interval, backoff) = restart_schedule(&config);
+                        retry_at = None;
+                    }
+                    UpdateCommand::Force(reply) => forced.push(reply),
+                },
+                _ = interval.tick() => {},
+                _ = retry => {
+                    log::info!("Retrying the failed update");
+                },
+                _ = watchdog_tick(&mut watchdog) => {
+                    #[cfg(unix)]
+                    systemd::notify("WATCHDOG=1");
+                    continue;
+                },
+            };
+        }
+        due = false;
+
+        let force = !forced.is_empty();
+        let mut replies = std::mem::take(&mut forced);
+
+        if !config.is_ready() {
+            for reply in replies {
+                let _ = reply.send(Err("No token or domain is configured".to_string()));
+            }
+            continue;
+        }
+
+        let mut changed_config = None;
+        let report = {
+            // Addresses are cleared per batch, so every domain is published
+            // while they are, not only the ones whose interval has passed.
+            let update = publish(&config, force || config.service.clear_ip_addresses);
+            tokio::pin!(update);
+            loop {
+                tokio::select! {
+                    result = &mut update => break result,
+                    Some(command) = receiver.recv() => match command {
+                        UpdateCommand::Force(reply) if force => replies.push(reply),
+                        // A regular update may skip unchanged addresses, so a
+                        // forced one follows it instead.
+                        UpdateCommand::Force(reply) => forced.push(reply),
+                        UpdateCommand::Reconfigure(c) => changed_config = Some(c),
+                    },
+                    // A slow update is still making progress, as every request
+                    // it sends is bounded by a timeout.
+                    _ = watchdog_tick(&mut watchdog) => {
+                        #[cfg(unix)]
+                        systemd::notify("WATCHDOG=1");
+                    },
+                }
             }
-            _ = interval.tick() => {},
         };
 
-        let ready = config.service.token.is_some() && !config.service.domain.is_empty();
+        let error = report.error();
+        let reply = error.clone().map_or(Ok(()), Err);
+        {
+            let mut metrics = metrics.lock().await;
+            for attempt in &report.attempts {
+                metrics.record(attempt);
+            }
+            metrics.retain(&config.domain_names());
+        }
+        let mut status = update_status.lock().await;
+        if !report.batches.is_empty() {
+            let configured = config.domain_names();
+            status.record_attempt(report.domain_outcomes(), SystemTime::now());
+            status
+                .domains
+                .retain(|domain, _| configured.contains(domain));
+            let _ = events.send(Event::Status(status.clone()));
+        }
+        match error {
+            None => {
+                backoff.reset();
+                retry_at = None;
+                config.service.clear_ip_addresses = false;
 
-        if ready {
-            let domains: Vec<String> = config.service.domain.iter().cloned().collect();
-            match duckdns::update(&config).await {
-                Ok(()) => {
-                    let mut status = update_status.lock().await;
-                    status.last_success = Some((SystemTime::now(), domains));
+                if report.published() {
                     log::info!("Update succeeded");
+                } else if report.batches.is_empty() {
+                    log::debug!("No domain is due for an update");
+                } else {
+                    log::info!("Public addresses are unchanged, skipping update");
                 }
-                Err(e) => log::error!("Update failed: {e}"),
             }
+            // Batches that succeeded are not due again until their interval
+            // has passed, so the retry only repeats the failed ones.
+            Some(e) => {
+                let delay = backoff.next_delay();
+                retry_at = Some(tokio::time::Instant::now() + delay);
+                log::error!(
+                    "Update failed: {e}. Retrying in {}",
+                    humantime::format_duration(Duration::from_secs(delay.as_secs()))
+                );
+            }
+        }
+        drop(status);
+
+        // Replies go out once the status reflects the update.
+        for r in replies {
+            let _ = r.send(reply.clone());
+        }
 
-            config.service.clear_ip_addresses = false;
+        if let Some(c) = changed_config {
+            config = *c;
+            (interval, backoff) = restart_schedule(&config);
+            retry_at = None;
+            due = true;
         }
     }
 }
 
-fn run_service(context: ServiceContext, shutdown_rx: mpsc::Receiver<()>) -> Result<()> {
+/// Runs the listening and update loops until `shutdown` completes or either
+/// loop ends. The dashboard is served from here only when `with_dashboard` is
+/// set; on Windows it runs in the tray process instead.
+fn run_service(
+    context: ServiceContext,
+    shutdown: impl Future<Output = ()>,
+    with_dashboard: bool,
+) -> Result<()> {
     let (update_tx, update_rx) = tokio::sync::mpsc::channel(8);
-    let status_handle = context.status_handle;
-
-    // Tell the system that the service is running now
-    set_service_status(&context.status_handle, ServiceState::Running, 0)?;
-    log::info!("Service has started");
 
     let rt = Runtime::new().map_err(|e| anyhow!("Failed to create tokio runtime: {e}"))?;
     rt.block_on(async {
+        let listener =
+            IpcListener::bind().map_err(|e| anyhow!("Failed to listen for clients: {e}"))?;
+        log::info!("Service has started");
+
         let update_ip_handle = tokio::spawn(update_ip_loop(
             update_rx,
-            context.config.clone(),
+            context.config.lock().await.clone(),
             context.update_status.clone(),
+            context.metrics.clone(),
+            context.events.clone(),
         ));
-        let listening_loop_handle = tokio::spawn(service_listening_loop(context, update_tx));
-        let shutdown_handle = tokio::spawn(async move {
-            let _ = shutdown_rx.recv();
-        });
+        let listening_loop_handle =
+            tokio::spawn(service_listening_loop(context, listener, update_tx));
+        if with_dashboard {
+            tokio::spawn(crate::dashboard::start());
+        }
+        #[cfg(unix)]
+        systemd::notify("READY=1");
 
         tokio::select! {
             _ = listening_loop_handle => {
                 log::error!("listening loop has ended unexpectedly");
             }
-            _ = shutdown_handle => {
+            _ = shutdown => {
                 log::debug!("shutdown has been initiated");
             }
             _ = update_ip_handle => {
-                log::error!("Cannot update DuckDNS");
+                log::error!("Cannot update DNS records");
             }
         }
-    });
 
-    set_service_status(&status_handle, ServiceState::Stopped, 0)?;
+        #[cfg(unix)]
+        systemd::notify("STOPPING=1");
+        anyhow::Ok(())
+    })?;
+
+    // Don't wait for the loops, which never return on their own.
+    rt.shutdown_background();
     log::info!("Service has stopped");
 
     Ok(())
@@ -518,57 +875,478 @@ fn run_service(context: ServiceContext, shutdown_rx: mpsc::Receiver<()>) -> Resu
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::BTreeSet;
+    use crate::common::message::{self, DomainOutcome, handshake};
+    use provider::Outcome;
+    use std::collections::{BTreeMap, BTreeSet};
+    use tokio::io::AsyncWriteExt;
 
-    #[test]
-    fn valid_domains() {
-        assert!(is_valid_domain("mydomain"));
-        assert!(is_valid_domain("test123"));
-        assert!(is_valid_domain("my-domain"));
-        assert!(is_valid_domain("a"));
-        assert!(is_valid_domain("a-b-c"));
+    fn duckdns_provider() -> Provider {
+        Provider::DuckDns(duckdns::DuckDns::default())
     }
 
-    #[test]
-    fn valid_domain_max_length() {
-        let domain = "a".repeat(63);
-        assert!(is_valid_domain(&domain));
+    /// The logger can only be started once per process, so the tests share it.
+    fn test_logger() -> LoggerHandle {
+        static HANDLE: std::sync::OnceLock<LoggerHandle> = std::sync::OnceLock::new();
+        HANDLE
+            .get_or_init(|| {
+                Logger::try_with_str("off")
+                    .unwrap()
+                    .do_not_log()
+                    .start()
+                    .unwrap()
+            })
+            .clone()
     }
 
-    #[test]
-    fn empty_domain_is_invalid() {
-        assert!(!is_valid_domain(""));
+    fn test_context() -> ServiceContext {
+        let config: Config = toml::from_str(common::strings::DEFAULT_CONFIG_CONTENT).unwrap();
+        ServiceContext {
+            logger_handle: test_logger(),
+            config: Arc::new(Mutex::new(config)),
+            update_status: Arc::new(Mutex::new(UpdateStatus::default())),
+            metrics: Arc::new(Mutex::new(Metrics::new(SystemTime::now()))),
+            events: event_channel(),
+        }
     }
 
-    #[test]
-    fn domain_exceeding_max_length_is_invalid() {
-        let domain = "a".repeat(64);
-        assert!(!is_valid_domain(&domain));
+    /// Serves `request` from `context` over an in-memory stream, exercising the
+    /// same path as a client connected over the named pipe or Unix socket.
+    async fn exchange(request: Request, context: &ServiceContext) -> Response {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(8);
+
+        // The client hangs up after the response, like the CLI does.
+        let client = async move {
+            let welcome = handshake(&mut client).await?;
+            request.send_over(&mut client, &welcome).await
+        };
+        let (response, ()) = tokio::join!(client, serve_client(&mut server, context, &update_tx));
+        response.unwrap()
     }
 
-    #[test]
-    fn domain_starting_with_hyphen_is_invalid() {
-        assert!(!is_valid_domain("-domain"));
+    #[tokio::test]
+    async fn exchange_version() {
+        let response = exchange(Request::Version, &test_context()).await;
+        assert!(matches!(response, Response::Version(v) if v == VERSION));
     }
 
-    #[test]
-    fn domain_ending_with_hyphen_is_invalid() {
-        assert!(!is_valid_domain("domain-"));
+    #[tokio::test]
+    async fn exchange_status() {
+        let context = test_context();
+        context.update_status.lock().await.record_attempt(
+            BTreeMap::from([(
+                "home".to_string(),
+                DomainOutcome::Failed("timeout".to_string()),
+            )]),
+            SystemTime::now(),
+        );
+
+        let response = exchange(Request::GetStatus, &context).await;
+        assert!(matches!(
+            response,
+            Response::Status(status) if status.consecutive_failures == 1
+        ));
     }
 
-    #[test]
-    fn domain_with_special_chars_is_invalid() {
-        assert!(!is_valid_domain("my.domain"));
-        assert!(!is_valid_domain("my domain"));
-        assert!(!is_valid_domain("my@domain"));
-        assert!(!is_valid_domain("my$domain"));
-        assert!(!is_valid_domain("dom&ain"));
+    #[tokio::test]
+    async fn exchange_config() {
+        let context = test_context();
+        context
+            .config
+            .lock()
+            .await
+            .service
+            .domain
+            .insert("home".to_string());
+
+        let response = exchange(Request::GetConfig, &context).await;
+        assert!(matches!(
+            response,
+            Response::Config(config) if config.domain.contains("home")
+        ));
     }
 
-    #[test]
-    fn domain_with_url_injection_is_invalid() {
-        assert!(!is_valid_domain("test&token=stolen"));
-        assert!(!is_valid_domain("test?token=stolen"));
+    #[tokio::test]
+    async fn exchange_metrics() {
+        let context = test_context();
+        context
+            .metrics
+            .lock()
+            .await
+            .record(&common::history::HistoryEntry {
+                time: SystemTime::now(),
+                provider: "duckdns".to_string(),
+                domains: vec!["home".to_string()],
+                ipv4: None,
+                ipv6: None,
+                result: common::history::AttemptResult::Unchanged,
+                duration: Duration::from_millis(100),
+            });
+
+        let response = exchange(Request::GetMetrics, &context).await;
+        assert!(matches!(
+            response,
+            Response::Metrics(metrics) if metrics.domains[0].successes == 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn exchange_rejected_request_returns_error() {
+        let context = test_context();
+        let response = exchange(Request::AddDomain("-bad".to_string()), &context).await;
+        assert!(matches!(response, Response::Err(_)));
+        assert!(context.config.lock().await.service.domain.is_empty());
+    }
+
+    #[tokio::test]
+    async fn exchanges_several_requests_over_one_connection() {
+        let context = test_context();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(8);
+
+        let client = async move {
+            let welcome = handshake(&mut client).await.unwrap();
+            let first = Request::Version
+                .send_over(&mut client, &welcome)
+                .await
+                .unwrap();
+            let second = Request::GetStatus
+                .send_over(&mut client, &welcome)
+                .await
+                .unwrap();
+            (first, second)
+        };
+        let ((first, second), ()) =
+            tokio::join!(client, serve_client(&mut server, &context, &update_tx));
+
+        assert!(matches!(first, Response::Version(_)));
+        assert!(matches!(second, Response::Status(_)));
+    }
+
+    #[tokio::test]
+    async fn oversized_request_gets_error_response() {
+        let context = test_context();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(8);
+
+        let client = async move {
+            let len = (common::consts::MAX_FRAME_SIZE + 1) as u32;
+            handshake(&mut client).await.unwrap();
+            client.write_all(&len.to_be_bytes()).await.unwrap();
+            client.read_message::<Response>().await.unwrap()
+        };
+        let (response, ()) = tokio::join!(client, serve_client(&mut server, &context, &update_tx));
+
+        assert!(matches!(response, Some(Response::Err(_))));
+    }
+
+    #[tokio::test]
+    async fn status_is_answered_while_config_is_locked() {
+        let context = test_context();
+        let _change_in_flight = context.config.lock().await;
+
+        let response = tokio::time::timeout(
+            Duration::from_secs(1),
+            exchange(Request::GetStatus, &context),
+        )
+        .await
+        .expect("status waited for the config lock");
+        assert!(matches!(response, Response::Status(_)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn listening_loop_serves_clients_concurrently() {
+        let path =
+            std::env::temp_dir().join(format!("barvazdns-{}-concurrent.sock", std::process::id()));
+        let listener = IpcListener::bind_at(&path).unwrap();
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(8);
+        let service = tokio::spawn(service_listening_loop(test_context(), listener, update_tx));
+
+        // A client that connects and then says nothing holds its connection
+        // until the read times out.
+        let _idle = tokio::net::UnixStream::connect(&path).await.unwrap();
+
+        let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let response = tokio::time::timeout(common::consts::PIPE_TIMEOUT / 2, async {
+            let welcome = handshake(&mut client).await.unwrap();
+            Request::Version.send_over(&mut client, &welcome).await
+        })
+        .await
+        .expect("second client waited for the first one");
+        assert!(matches!(response, Ok(Response::Version(_))));
+
+        service.abort();
+    }
+
+    /// Runs [`update_loop`] with a stand-in provider that reports whether each
+    /// update was forced, then waits for a permit from the returned semaphore.
+    fn spawn_update_loop(
+        result: fn() -> Result<Outcome>,
+        events: broadcast::Sender<Event>,
+    ) -> (
+        tokio::sync::mpsc::Sender<UpdateCommand>,
+        tokio::sync::mpsc::UnboundedReceiver<bool>,
+        Arc<tokio::sync::Semaphore>,
+    ) {
+        let mut config = test_context().config.try_lock().unwrap().clone();
+        config.service.token = Some(common::config::Token::new("token".to_string()));
+        config.service.domain.insert("home".to_string());
+
+        let (update_tx, update_rx) = tokio::sync::mpsc::channel(8);
+        let (started_tx, started_rx) = tokio::sync::mpsc::unbounded_channel();
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let publish = {
+            let gate = gate.clone();
+            async move |_: &Config, force: bool| {
+                started_tx.send(force).unwrap();
+                gate.acquire().await.unwrap().forget();
+                Report {
+                    batches: vec![provider::Batch {
+                        domains: vec!["home".to_string()],
+                        result: result().map_err(|e| e.to_string()),
+                    }],
+                    ..Default::default()
+                }
+            }
+        };
+        let status = Arc::new(Mutex::new(UpdateStatus::default()));
+        let metrics = Arc::new(Mutex::new(Metrics::new(SystemTime::now())));
+        tokio::spawn(update_loop(
+            update_rx, config, status, metrics, events, publish,
+        ));
+        (update_tx, started_rx, gate)
+    }
+
+    async fn force(
+        update_tx: &tokio::sync::mpsc::Sender<UpdateCommand>,
+    ) -> oneshot::Receiver<Result<(), String>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        update_tx
+            .send(UpdateCommand::Force(reply_tx))
+            .await
+            .unwrap();
+        reply_rx
+    }
+
+    #[tokio::test]
+    async fn forced_updates_in_flight_coalesce() {
+        let (update_tx, mut started, gate) =
+            spawn_update_loop(|| Ok(Outcome::Published("ok".to_string())), event_channel());
+        // The initial update.
+        assert_eq!(started.recv().await, Some(false));
+        gate.add_permits(1);
+
+        let first = force(&update_tx).await;
+        assert_eq!(started.recv().await, Some(true));
+        let second = force(&update_tx).await;
+        tokio::task::yield_now().await;
+        gate.add_permits(1);
+
+        assert_eq!(first.await.unwrap(), Ok(()));
+        assert_eq!(second.await.unwrap(), Ok(()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(started.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn force_during_regular_update_forces_another() {
+        let (update_tx, mut started, gate) =
+            spawn_update_loop(|| Ok(Outcome::Unchanged), event_channel());
+        assert_eq!(started.recv().await, Some(false));
+
+        let reply = force(&update_tx).await;
+        tokio::task::yield_now().await;
+        gate.add_permits(1);
+
+        assert_eq!(started.recv().await, Some(true));
+        gate.add_permits(1);
+        assert_eq!(reply.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn forced_update_reports_failure() {
+        let (update_tx, mut started, gate) =
+            spawn_update_loop(|| Err(anyhow!("timeout")), event_channel());
+        assert_eq!(started.recv().await, Some(false));
+        gate.add_permits(1);
+
+        let reply = force(&update_tx).await;
+        assert_eq!(started.recv().await, Some(true));
+        gate.add_permits(1);
+        assert_eq!(reply.await.unwrap(), Err("timeout".to_string()));
+    }
+
+    #[tokio::test]
+    async fn publishes_without_a_token_when_the_provider_needs_none() {
+        let mut config = test_context().config.try_lock().unwrap().clone();
+        config.service.provider = common::config::ProviderKind::Rfc2136;
+        config.service.token = None;
+        config.service.domain.insert("home.example.com".to_string());
+
+        let (_update_tx, update_rx) = tokio::sync::mpsc::channel(8);
+        let (started_tx, mut started) = tokio::sync::mpsc::unbounded_channel();
+        let publish = async move |_: &Config, force: bool| {
+            started_tx.send(force).unwrap();
+            Report::default()
+        };
+        let status = Arc::new(Mutex::new(UpdateStatus::default()));
+        let metrics = Arc::new(Mutex::new(Metrics::new(SystemTime::now())));
+        tokio::spawn(update_loop(
+            update_rx,
+            config,
+            status,
+            metrics,
+            event_channel(),
+            publish,
+        ));
+        assert_eq!(started.recv().await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn clearing_addresses_publishes_domains_that_are_not_due() {
+        let mut config = test_context().config.try_lock().unwrap().clone();
+        config.service.token = Some(common::config::Token::new("token".to_string()));
+        config.service.ipv6 = Some(true);
+        config.domains.push(common::config::DomainConfig {
+            name: "rarely.example.com".to_string(),
+            provider: None,
+            token: None,
+            ipv4: None,
+            ipv6: None,
+            interval: Some(Duration::from_secs(24 * 60 * 60)),
+        });
+
+        let (update_tx, update_rx) = tokio::sync::mpsc::channel(8);
+        let (started_tx, mut started) = tokio::sync::mpsc::unbounded_channel();
+        let publish = async move |config: &Config, force: bool| {
+            started_tx
+                .send((force, config.service.clear_ip_addresses))
+                .unwrap();
+            Report::default()
+        };
+        let status = Arc::new(Mutex::new(UpdateStatus::default()));
+        let metrics = Arc::new(Mutex::new(Metrics::new(SystemTime::now())));
+        tokio::spawn(update_loop(
+            update_rx,
+            config.clone(),
+            status,
+            metrics,
+            event_channel(),
+            publish,
+        ));
+        assert_eq!(started.recv().await, Some((false, false)));
+
+        config.service.ipv6 = Some(false);
+        config.service.clear_ip_addresses = true;
+        update_tx
+            .send(UpdateCommand::Reconfigure(Box::new(config)))
+            .await
+            .unwrap();
+        assert_eq!(started.recv().await, Some((true, true)));
+    }
+
+    #[tokio::test]
+    async fn update_attempts_are_published_as_events() {
+        let events = event_channel();
+        let mut subscriber = events.subscribe();
+        let (_update_tx, mut started, gate) = spawn_update_loop(|| Err(anyhow!("timeout")), events);
+        assert_eq!(started.recv().await, Some(false));
+        gate.add_permits(1);
+
+        let event = subscriber.recv().await.unwrap();
+        assert!(matches!(event, Event::Status(status) if status.consecutive_failures == 1));
+    }
+
+    #[tokio::test]
+    async fn subscriber_gets_snapshot_then_changes() {
+        let context = test_context();
+        context
+            .config
+            .lock()
+            .await
+            .service
+            .domain
+            .insert("home".to_string());
+        let (client, mut server) = tokio::io::duplex(1024);
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(8);
+        let events = context.events.clone();
+
+        let client = async move {
+            let mut client = client;
+            let welcome = handshake(&mut client).await.unwrap();
+            let mut subscription = message::Subscription::over(client, &welcome).await.unwrap();
+            let status = subscription.next().await.unwrap();
+            let config = subscription.next().await.unwrap();
+            events.send(Event::Log("a line".to_string())).unwrap();
+            let log = subscription.next().await.unwrap();
+            // Hanging up ends the subscription on the service's side.
+            (status, config, log)
+        };
+        let ((status, config, log), ()) =
+            tokio::join!(client, serve_client(&mut server, &context, &update_tx));
+
+        assert!(matches!(status, Some(Event::Status(_))));
+        assert!(matches!(config, Some(Event::Config(c)) if c.domain.contains("home")));
+        assert!(matches!(log, Some(Event::Log(line)) if line == "a line"));
+    }
+
+    #[tokio::test]
+    async fn client_sees_closed_connection_as_error() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        drop(server);
+        assert!(handshake(&mut client).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_request_keeps_connection_open() {
+        let context = test_context();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(8);
+
+        let client = async move {
+            let welcome = handshake(&mut client).await.unwrap();
+            // A request from a newer client that this service doesn't know.
+            let payload = br#"{"Frobnicate":1}"#;
+            client
+                .write_all(&(payload.len() as u32).to_be_bytes())
+                .await
+                .unwrap();
+            client.write_all(payload).await.unwrap();
+            let unknown = client.read_message::<Response>().await.unwrap();
+            let version = Request::Version
+                .send_over(&mut client, &welcome)
+                .await
+                .unwrap();
+            (unknown, version)
+        };
+        let ((unknown, version), ()) =
+            tokio::join!(client, serve_client(&mut server, &context, &update_tx));
+
+        assert!(matches!(unknown, Some(Response::Err(_))));
+        assert!(matches!(version, Response::Version(_)));
+    }
+
+    #[tokio::test]
+    async fn outdated_client_is_welcomed_then_disconnected() {
+        let context = test_context();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let (update_tx, _update_rx) = tokio::sync::mpsc::channel(8);
+
+        let client = async move {
+            let mut hello = Hello::current();
+            hello.protocol = message::MIN_PROTOCOL_VERSION - 1;
+            client.write_message(&hello).await.unwrap();
+            let welcome = client.read_message::<Welcome>().await.unwrap();
+            let next = client.read_message::<Response>().await.unwrap();
+            (welcome, next)
+        };
+        // The service drops the connection once it is done with the client.
+        let service = async move { serve_client(&mut server, &context, &update_tx).await };
+        let ((welcome, next), ()) = tokio::join!(client, service);
+
+        assert!(welcome.is_some());
+        assert!(next.is_none());
     }
 
     #[test]
@@ -589,27 +1367,26 @@ mod tests {
     #[test]
     fn validate_add_domain_success() {
         let existing = BTreeSet::new();
-        assert!(validate_add_domain("myhost", &existing).is_ok());
+        assert!(validate_add_domain(&duckdns_provider(), "myhost", &existing, 5).is_ok());
     }
 
     #[test]
     fn validate_add_domain_invalid_name() {
         let existing = BTreeSet::new();
-        assert!(validate_add_domain("-bad", &existing).is_err());
+        assert!(validate_add_domain(&duckdns_provider(), "-bad", &existing, 5).is_err());
     }
 
     #[test]
     fn validate_add_domain_duplicate() {
         let existing: BTreeSet<String> = ["myhost".to_string()].into();
-        assert!(validate_add_domain("myhost", &existing).is_err());
+        assert!(validate_add_domain(&duckdns_provider(), "myhost", &existing, 5).is_err());
     }
 
     #[test]
     fn validate_add_domain_at_limit() {
-        let existing: BTreeSet<String> = (0..common::consts::MAX_DOMAIN_COUNT)
-            .map(|i| format!("host{i}"))
-            .collect();
-        assert!(validate_add_domain("onemore", &existing).is_err());
+        let existing: BTreeSet<String> = (0..5).map(|i| format!("host{i}")).collect();
+        assert!(validate_add_domain(&duckdns_provider(), "onemore", &existing, 5).is_err());
+        assert!(validate_add_domain(&duckdns_provider(), "onemore", &existing, 6).is_ok());
     }
 
     #[test]
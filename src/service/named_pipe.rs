@@ -1,14 +1,15 @@
 use std::io;
 use std::ptr;
-use std::time::Duration;
 
-use tokio::io::AsyncReadExt;
 use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
 use tokio::time::timeout;
 use windows_sys::Win32::Foundation::LocalFree;
 use windows_sys::Win32::Security::Authorization::ConvertStringSecurityDescriptorToSecurityDescriptorW;
 use windows_sys::Win32::Security::SECURITY_ATTRIBUTES;
 
+use crate::common::consts::PIPE_TIMEOUT;
+use crate::common::strings;
+
 /// SDDL security descriptor that restricts pipe access to privileged accounts.
 ///
 /// The descriptor defines a Discretionary Access Control List (DACL) with two
@@ -44,7 +45,7 @@ const SDDL_REVISION_1: u32 = 1;
 ///
 /// This prevents unprivileged users from reading or writing to the pipe,
 /// protecting sensitive data (such as the DuckDNS token) that flows through it.
-pub fn create_admin_pipe(name: &str) -> io::Result<NamedPipeServer> {
+fn create_admin_pipe(name: &str) -> io::Result<NamedPipeServer> {
     let mut sd = ptr::null_mut();
 
     let ok = unsafe {
@@ -75,21 +76,32 @@ pub fn create_admin_pipe(name: &str) -> io::Result<NamedPipeServer> {
     result
 }
 
-pub trait NamedPipeServerWithTimeout {
-    async fn read_with_timeout(&mut self, buf: &mut [u8], duration: Duration) -> io::Result<usize>;
-    async fn connect_with_timeout(&self, duration: Duration) -> io::Result<()>;
+/// Accepts clients on the service's named pipe, one pipe instance per client.
+pub struct IpcListener {
+    /// The instance the next client connects to.
+    next: NamedPipeServer,
 }
 
-impl NamedPipeServerWithTimeout for NamedPipeServer {
-    async fn read_with_timeout(&mut self, buf: &mut [u8], duration: Duration) -> io::Result<usize> {
-        timeout(duration, self.read(buf))
-            .await
-            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Read operation timed out"))?
+impl IpcListener {
+    pub fn bind() -> io::Result<Self> {
+        Ok(Self {
+            next: create_admin_pipe(strings::PIPE_NAME)?,
+        })
     }
 
-    async fn connect_with_timeout(&self, duration: Duration) -> io::Result<()> {
-        timeout(duration, self.connect())
+    /// Waits for a client to connect to the pending pipe instance. Times out
+    /// after [`PIPE_TIMEOUT`] so that the caller gets to run periodically.
+    pub async fn accept(&mut self) -> io::Result<NamedPipeServer> {
+        timeout(PIPE_TIMEOUT, self.next.connect())
             .await
-            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Connect operation timed out"))?
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::TimedOut, "Connect operation timed out")
+            })??;
+
+        // Create the instance for the next client before handing this one
+        // off, so that there is always one to connect to while clients are
+        // being served.
+        let next = create_admin_pipe(strings::PIPE_NAME)?;
+        Ok(std::mem::replace(&mut self.next, next))
     }
 }
@@ -0,0 +1,501 @@
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime};
+
+use anyhow::{Result, anyhow};
+
+use super::cloudflare::Cloudflare;
+use super::duckdns::DuckDns;
+use super::dyndns2::Dyndns2;
+use super::public_address::{self, PublicAddresses};
+use super::published::PublishedState;
+use super::rfc2136::Rfc2136;
+use super::webhook::Webhook;
+use crate::common::config::{Config, ProviderKind, ServiceConfig};
+use crate::common::history::{AttemptResult, History, HistoryEntry};
+use crate::common::message::DomainOutcome;
+
+/// A dynamic DNS backend that the update loop publishes addresses to.
+///
+/// Implementations are selected per configuration entry through
+/// [`Provider::for_config`], so the update loop never needs to know which
+/// backend it is talking to.
+pub trait DnsProvider {
+    /// Checks that `name` is acceptable as a domain entry for this provider.
+    fn validate_name(&self, name: &str) -> Result<()>;
+
+    /// Whether `update` can leave stale records behind (e.g. an IPv6 address after
+    /// IPv6 was disabled), so that `clear` must run before it when requested.
+    fn requires_clear(&self) -> bool {
+        true
+    }
+
+    /// Checks that `update` can publish `addresses` with `config`, so that
+    /// records are never cleared ahead of an update that is bound to fail.
+    fn check_update(&self, _config: &ServiceConfig, _addresses: &PublicAddresses) -> Result<()> {
+        Ok(())
+    }
+
+    /// Publishes `addresses` for every configured domain, returning a short
+    /// summary of the provider's response for the update history.
+    async fn update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<String>;
+
+    /// Removes previously published addresses for every configured domain.
+    async fn clear(&self, config: &ServiceConfig) -> Result<()>;
+}
+
+/// Validates a fully qualified host name, e.g. `home.example.com`.
+///
+/// Only letters, digits, hyphens and underscores are allowed in each label, which
+/// also keeps the name safe to embed in a query string.
+pub fn is_valid_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
+/// All supported providers, dispatched statically.
+pub enum Provider {
+    DuckDns(DuckDns),
+    Cloudflare(Cloudflare),
+    Rfc2136(Rfc2136),
+    Dyndns2(Dyndns2),
+    Webhook(Webhook),
+}
+
+impl Provider {
+    pub fn for_config(config: &ServiceConfig) -> Result<Self> {
+        match config.provider {
+            ProviderKind::DuckDns => Ok(Self::DuckDns(DuckDns::default())),
+            ProviderKind::Cloudflare => {
+                let settings = config.cloudflare.as_ref().ok_or(anyhow!(
+                    "The Cloudflare provider requires a [service.cloudflare] section"
+                ))?;
+                Ok(Self::Cloudflare(Cloudflare::new(settings)?))
+            }
+            ProviderKind::Rfc2136 => {
+                let settings = config.rfc2136.as_ref().ok_or(anyhow!(
+                    "The RFC 2136 provider requires a [service.rfc2136] section"
+                ))?;
+                Ok(Self::Rfc2136(Rfc2136::new(settings)?))
+            }
+            ProviderKind::Dyndns2 => {
+                let settings = config.dyndns2.as_ref().ok_or(anyhow!(
+                    "The dyndns2 provider requires a [service.dyndns2] section"
+                ))?;
+                Ok(Self::Dyndns2(Dyndns2::new(settings)?))
+            }
+            ProviderKind::Webhook => {
+                let settings = config.webhook.as_ref().ok_or(anyhow!(
+                    "The webhook provider requires a [service.webhook] section"
+                ))?;
+                Ok(Self::Webhook(Webhook::new(settings)?))
+            }
+        }
+    }
+}
+
+impl DnsProvider for Provider {
+    fn validate_name(&self, name: &str) -> Result<()> {
+        match self {
+            Self::DuckDns(p) => p.validate_name(name),
+            Self::Cloudflare(p) => p.validate_name(name),
+            Self::Rfc2136(p) => p.validate_name(name),
+            Self::Dyndns2(p) => p.validate_name(name),
+            Self::Webhook(p) => p.validate_name(name),
+        }
+    }
+
+    fn requires_clear(&self) -> bool {
+        match self {
+            Self::DuckDns(p) => p.requires_clear(),
+            Self::Cloudflare(p) => p.requires_clear(),
+            Self::Rfc2136(p) => p.requires_clear(),
+            Self::Dyndns2(p) => p.requires_clear(),
+            Self::Webhook(p) => p.requires_clear(),
+        }
+    }
+
+    fn check_update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<()> {
+        match self {
+            Self::DuckDns(p) => p.check_update(config, addresses),
+            Self::Cloudflare(p) => p.check_update(config, addresses),
+            Self::Rfc2136(p) => p.check_update(config, addresses),
+            Self::Dyndns2(p) => p.check_update(config, addresses),
+            Self::Webhook(p) => p.check_update(config, addresses),
+        }
+    }
+
+    async fn update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<String> {
+        match self {
+            Self::DuckDns(p) => p.update(config, addresses).await,
+            Self::Cloudflare(p) => p.update(config, addresses).await,
+            Self::Rfc2136(p) => p.update(config, addresses).await,
+            Self::Dyndns2(p) => p.update(config, addresses).await,
+            Self::Webhook(p) => p.update(config, addresses).await,
+        }
+    }
+
+    async fn clear(&self, config: &ServiceConfig) -> Result<()> {
+        match self {
+            Self::DuckDns(p) => p.clear(config).await,
+            Self::Cloudflare(p) => p.clear(config).await,
+            Self::Rfc2136(p) => p.clear(config).await,
+            Self::Dyndns2(p) => p.clear(config).await,
+            Self::Webhook(p) => p.clear(config).await,
+        }
+    }
+}
+
+/// The result of a batch that [`publish`] handled successfully.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The provider accepted the update and responded with the given summary.
+    Published(String),
+    /// The addresses match what was last published and the refresh interval
+    /// has not elapsed, so the provider was not contacted.
+    Unchanged,
+}
+
+/// What [`publish`] did for one batch of domains sent in a single provider call.
+#[derive(Debug)]
+pub struct Batch {
+    pub domains: Vec<String>,
+    pub result: Result<Outcome, String>,
+}
+
+/// What [`publish`] did for each batch of domains that was due.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub batches: Vec<Batch>,
+    /// The provider calls as recorded in the update history.
+    pub attempts: Vec<HistoryEntry>,
+}
+
+impl Report {
+    /// The errors of the failed batches, or `None` if every batch succeeded.
+    pub fn error(&self) -> Option<String> {
+        let failed: Vec<(&Vec<String>, &String)> = self
+            .batches
+            .iter()
+            .filter_map(|batch| batch.result.as_ref().err().map(|e| (&batch.domains, e)))
+            .collect();
+
+        match failed.as_slice() {
+            [] => None,
+            [(_, e)] if self.batches.len() == 1 => Some(e.to_string()),
+            _ => Some(
+                failed
+                    .iter()
+                    .map(|(domains, e)| format!("{}: {e}", domains.join(",")))
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+        }
+    }
+
+    /// Whether any batch was sent to the provider.
+    pub fn published(&self) -> bool {
+        self.batches
+            .iter()
+            .any(|batch| matches!(batch.result, Ok(Outcome::Published(_))))
+    }
+
+    /// The outcome for every domain in the report.
+    pub fn domain_outcomes(&self) -> BTreeMap<String, DomainOutcome> {
+        self.batches
+            .iter()
+            .flat_map(|batch| {
+                let outcome = match &batch.result {
+                    Ok(Outcome::Published(_)) => DomainOutcome::Updated,
+                    Ok(Outcome::Unchanged) => DomainOutcome::Unchanged,
+                    Err(e) => DomainOutcome::Failed(e.clone()),
+                };
+                batch
+                    .domains
+                    .iter()
+                    .map(move |d| (d.clone(), outcome.clone()))
+            })
+            .collect()
+    }
+}
+
+/// Publishes the configured domains that are due, recording each provider call
+/// in the update history.
+///
+/// Domains are checked on their own interval and grouped by provider, token and
+/// address families into batches of at most `batch_size` domains, each sent in
+/// a single provider call that succeeds or fails on its own. The public
+/// addresses are discovered once and compared with the last published ones,
+/// and nothing is sent for a batch whose addresses are unchanged unless `force`
+/// is set, which also publishes every domain regardless of its interval. If the
+/// configuration asks for it, previously published addresses are cleared
+/// first, since the IPv6 setting might have been switched off in the meantime.
+pub async fn publish(full_config: &Config, force: bool) -> Report {
+    let now = SystemTime::now();
+    let tick = full_config.update_interval();
+    let mut state = PublishedState::read();
+    state.retain(&full_config.domain_names());
+
+    let domains = full_config.resolved_domains();
+    let groups = full_config.group_domains(
+        domains
+            .iter()
+            .filter(|domain| force || state.is_due(domain, tick, now)),
+    );
+
+    if groups.is_empty() {
+        return Report::default();
+    }
+
+    let ipv4 = groups.iter().any(|g| g.ipv4 == Some(true));
+    let ipv6 = groups.iter().any(|g| g.ipv6 == Some(true));
+    let started = Instant::now();
+    let discovered = {
+        let full_config = full_config.clone();
+        off_runtime(async move || public_address::discover(&full_config, ipv4, ipv6).await).await
+    };
+    let discovery_time = started.elapsed();
+
+    let mut report = Report::default();
+    for config in &groups {
+        let started = Instant::now();
+        let (addresses, result) = match &discovered {
+            Ok(addresses) => {
+                let addresses =
+                    addresses.filter(config.ipv4 == Some(true), config.ipv6 == Some(true));
+                let result = publish_group(config, &addresses, force, &mut state).await;
+                (Some(addresses), result)
+            }
+            Err(e) => (None, Err(anyhow!("{e}"))),
+        };
+
+        let entry = HistoryEntry {
+            time: SystemTime::now(),
+            provider: config.provider.to_string(),
+            domains: config.domain.iter().cloned().collect(),
+            ipv4: addresses.and_then(|a| a.ipv4),
+            ipv6: addresses.and_then(|a| a.ipv6),
+            result: match &result {
+                Ok(Outcome::Published(response)) => AttemptResult::Published {
+                    response: response.clone(),
+                },
+                Ok(Outcome::Unchanged) => AttemptResult::Unchanged,
+                Err(e) => AttemptResult::Failed {
+                    error: e.to_string(),
+                },
+            },
+            duration: discovery_time + started.elapsed(),
+        };
+        // Unchanged batches come up on every tick and would soon push every
+        // actual update out of the history, so only the metrics count them.
+        if !matches!(entry.result, AttemptResult::Unchanged)
+            && let Err(e) = History::append(entry.clone())
+        {
+            log::warn!("Failed to save the update history: {e}");
+        }
+        report.attempts.push(entry);
+
+        report.batches.push(Batch {
+            domains: config.domain.iter().cloned().collect(),
+            result: result.map_err(|e| e.to_string()),
+        });
+    }
+
+    if let Err(e) = state.store() {
+        log::warn!("Failed to save the published addresses: {e}");
+    }
+
+    report
+}
+
+/// Runs `task` on a blocking thread, since provider and IP source requests
+/// block. The runtime's workers stay free, so the update loop keeps answering
+/// the watchdog while an update is in flight.
+async fn off_runtime<T: Send + 'static>(
+    task: impl AsyncFnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || runtime.block_on(task()))
+        .await
+        .unwrap_or_else(|e| Err(anyhow!("The update task failed: {e}")))
+}
+
+/// Publishes one group of domains that share a provider call, keeping `state`
+/// up to date with the result.
+async fn publish_group(
+    config: &ServiceConfig,
+    addresses: &PublicAddresses,
+    force: bool,
+    state: &mut PublishedState,
+) -> Result<Outcome> {
+    if config.token.is_none() && config.provider.requires_token() {
+        return Err(anyhow!("No token configured"));
+    }
+    let provider = Provider::for_config(config)?;
+
+    if !force && state.is_current(config, addresses, SystemTime::now()) {
+        state.mark_checked(config, SystemTime::now());
+        return Ok(Outcome::Unchanged);
+    }
+
+    let result = {
+        let config = config.clone();
+        let addresses = *addresses;
+        off_runtime(async move || {
+            provider.check_update(&config, &addresses)?;
+            if config.clear_ip_addresses && provider.requires_clear() {
+                provider
+                    .clear(&config)
+                    .await
+                    .map_err(|e| anyhow!("Failed to clear IP addresses: {e}"))?;
+            }
+
+            provider.update(&config, &addresses).await
+        })
+        .await
+    };
+
+    match &result {
+        Ok(_) => {
+            state.record(config, addresses, SystemTime::now());
+            state.mark_checked(config, SystemTime::now());
+        }
+        Err(_) => state.forget(config),
+    }
+
+    result.map(Outcome::Published)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_hostnames() {
+        assert!(is_valid_hostname("example.com"));
+        assert!(is_valid_hostname("home.example.com"));
+        assert!(is_valid_hostname("_acme.example.com"));
+        assert!(is_valid_hostname("a"));
+    }
+
+    #[test]
+    fn invalid_hostnames() {
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("home..example.com"));
+        assert!(!is_valid_hostname("-home.example.com"));
+        assert!(!is_valid_hostname("home.example.com&type=TXT"));
+        assert!(!is_valid_hostname(&"a".repeat(64)));
+    }
+
+    fn batch(domains: &[&str], result: Result<Outcome, &str>) -> Batch {
+        Batch {
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            result: result.map_err(str::to_string),
+        }
+    }
+
+    #[test]
+    fn report_keeps_outcome_per_batch() {
+        let report = Report {
+            batches: vec![
+                batch(&["a", "b"], Ok(Outcome::Published("OK".to_string()))),
+                batch(&["c"], Err("KO")),
+                batch(&["d"], Ok(Outcome::Unchanged)),
+            ],
+            ..Default::default()
+        };
+        assert!(report.published());
+        assert_eq!(report.error().as_deref(), Some("c: KO"));
+
+        let outcomes = report.domain_outcomes();
+        assert_eq!(outcomes["a"], DomainOutcome::Updated);
+        assert_eq!(outcomes["b"], DomainOutcome::Updated);
+        assert_eq!(outcomes["c"], DomainOutcome::Failed("KO".to_string()));
+        assert_eq!(outcomes["d"], DomainOutcome::Unchanged);
+    }
+
+    #[test]
+    fn single_batch_error_is_reported_as_is() {
+        let report = Report {
+            batches: vec![batch(&["a"], Err("KO"))],
+            ..Default::default()
+        };
+        assert!(!report.published());
+        assert_eq!(report.error().as_deref(), Some("KO"));
+        assert!(Report::default().error().is_none());
+    }
+
+    #[test]
+    fn for_config_requires_provider_section() {
+        let mut config: ServiceConfig = toml::from_str(
+            r#"
+interval = "1 day"
+provider = "cloudflare"
+"#,
+        )
+        .unwrap();
+        assert!(Provider::for_config(&config).is_err());
+
+        config.provider = ProviderKind::Rfc2136;
+        assert!(Provider::for_config(&config).is_err());
+
+        config.provider = ProviderKind::Dyndns2;
+        assert!(Provider::for_config(&config).is_err());
+
+        config.provider = ProviderKind::Webhook;
+        assert!(Provider::for_config(&config).is_err());
+
+        config.provider = ProviderKind::DuckDns;
+        assert!(matches!(
+            Provider::for_config(&config),
+            Ok(Provider::DuckDns(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn nothing_is_cleared_ahead_of_a_failing_update() {
+        let config = ServiceConfig {
+            token: Some(crate::common::config::Token::new("tok".to_string())),
+            domain: ["home".to_string()].into(),
+            clear_ip_addresses: true,
+            ..Default::default()
+        };
+        // DuckDNS cannot publish without IPv4, so the clear request is never sent.
+        let addresses = PublicAddresses {
+            ipv4: None,
+            ipv6: Some(std::net::Ipv6Addr::LOCALHOST),
+        };
+        let err = publish_group(&config, &addresses, true, &mut PublishedState::default())
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("does not support disabling IPv4"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn group_without_a_token_fails_on_its_own() {
+        let config = ServiceConfig {
+            domain: ["home".to_string()].into(),
+            clear_ip_addresses: true,
+            ..Default::default()
+        };
+        let addresses = PublicAddresses {
+            ipv4: Some(std::net::Ipv4Addr::new(1, 2, 3, 4)),
+            ipv6: None,
+        };
+        let err = publish_group(&config, &addresses, true, &mut PublishedState::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "No token configured");
+    }
+}
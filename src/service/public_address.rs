@@ -0,0 +1,355 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use tokio::net::lookup_host;
+
+use super::{dns_wire, interface};
+use crate::common::config::{Config, DnsTransport, IpDiscoveryConfig, IpSource};
+
+/// The public addresses discovered for this host, ready to be published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicAddresses {
+    /// Only discovered when IPv4 updates are enabled.
+    pub ipv4: Option<Ipv4Addr>,
+    /// Only discovered when IPv6 updates are enabled.
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl PublicAddresses {
+    /// Keeps only the address families that are enabled.
+    pub fn filter(self, ipv4: bool, ipv6: bool) -> Self {
+        Self {
+            ipv4: self.ipv4.filter(|_| ipv4),
+            ipv6: self.ipv6.filter(|_| ipv6),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn matches(self, addr: &IpAddr) -> bool {
+        match self {
+            Self::V4 => addr.is_ipv4(),
+            Self::V6 => addr.is_ipv6(),
+        }
+    }
+
+    fn record_type(self) -> u16 {
+        match self {
+            Self::V4 => dns_wire::TYPE_A,
+            Self::V6 => dns_wire::TYPE_AAAA,
+        }
+    }
+}
+
+impl std::fmt::Display for Family {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V4 => write!(f, "IPv4"),
+            Self::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+async fn query_http(url: &str, timeout: Duration) -> Result<IpAddr> {
+    let res = minreq::get(url)
+        .with_timeout(timeout.as_secs().max(1))
+        .send()
+        .map_err(|e| anyhow!("Failed to reach {url}: {e}"))?;
+
+    if res.status_code != 200 {
+        return Err(anyhow!("{url} responded with HTTP {}", res.status_code));
+    }
+
+    let body = res.as_str()?.trim();
+    body.parse()
+        .map_err(|_| anyhow!("{url} returned '{body}' instead of an IP address"))
+}
+
+/// Resolves `server` (a host name or address, optionally with a port) to an
+/// address of `family`, so that the query itself travels over that family.
+async fn resolver_address(server: &str, family: Family) -> Result<SocketAddr> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    lookup_host((server, 53))
+        .await
+        .map_err(|e| anyhow!("Failed to resolve {server}: {e}"))?
+        .find(|addr| family.matches(&addr.ip()))
+        .ok_or(anyhow!("{server} has no {family} address"))
+}
+
+async fn query_dns(server: &str, name: &str, family: Family, timeout: Duration) -> Result<IpAddr> {
+    let server_addr = resolver_address(server, family).await?;
+
+    let id = dns_wire::random_id();
+    let mut query = Vec::new();
+    dns_wire::write_header(&mut query, id, dns_wire::FLAG_RD, [1, 0, 0, 0]);
+    dns_wire::write_question(&mut query, name, family.record_type(), dns_wire::CLASS_IN)?;
+
+    let response = dns_wire::exchange(server_addr, DnsTransport::Udp, &query, timeout).await?;
+    let rcode = dns_wire::response_code(&response, id)?;
+    if rcode != 0 {
+        return Err(anyhow!("{server} answered {}", dns_wire::rcode_name(rcode)));
+    }
+
+    let rdata = dns_wire::answers(&response, family.record_type())?
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("{server} returned no address for {name}"))?;
+
+    match (family, rdata.len()) {
+        (Family::V4, 4) => Ok(IpAddr::from(<[u8; 4]>::try_from(rdata)?)),
+        (Family::V6, 16) => Ok(IpAddr::from(<[u8; 16]>::try_from(rdata)?)),
+        _ => Err(anyhow!("{server} returned a malformed address")),
+    }
+}
+
+async fn query_source(
+    source: &IpSource,
+    family: Family,
+    default_timeout: Duration,
+) -> Result<IpAddr> {
+    let addr = match source {
+        IpSource::Http { url, timeout } => {
+            query_http(url, timeout.unwrap_or(default_timeout)).await?
+        }
+        IpSource::Dns {
+            server,
+            name,
+            timeout,
+        } => query_dns(server, name, family, timeout.unwrap_or(default_timeout)).await?,
+        IpSource::Interface { name } => {
+            let addresses =
+                interface::list().map_err(|e| anyhow!("Failed to list network interfaces: {e}"))?;
+            interface::select(&addresses, name, |addr| family.matches(addr)).ok_or(anyhow!(
+                "Interface {name} has no publishable {family} address"
+            ))?
+        }
+    };
+
+    if family.matches(&addr) {
+        Ok(addr)
+    } else {
+        Err(anyhow!("Expected an {family} address, got {addr}"))
+    }
+}
+
+/// Queries `sources` in order until `settings.require_agreement` of them
+/// returned the same address. Failing sources are skipped.
+async fn resolve(
+    sources: &[IpSource],
+    family: Family,
+    settings: &IpDiscoveryConfig,
+) -> Result<IpAddr> {
+    let required = settings.require_agreement;
+    if required == 0 || required > sources.len() {
+        return Err(anyhow!(
+            "Cannot require {required} agreeing sources out of {} {family} sources",
+            sources.len()
+        ));
+    }
+
+    let mut votes: Vec<(IpAddr, usize)> = Vec::new();
+    for source in sources {
+        let addr = match query_source(source, family, settings.timeout).await {
+            Ok(addr) => addr,
+            Err(e) => {
+                log::warn!("{family} source failed: {e}");
+                continue;
+            }
+        };
+        log::debug!("{family} source {source:?} returned {addr}");
+
+        let count = match votes.iter_mut().find(|(a, _)| *a == addr) {
+            Some((_, count)) => {
+                *count += 1;
+                *count
+            }
+            None => {
+                votes.push((addr, 1));
+                1
+            }
+        };
+
+        if count >= required {
+            return Ok(addr);
+        }
+    }
+
+    Err(anyhow!(
+        "Failed to get the public {family} address: no {required} sources agreed"
+    ))
+}
+
+async fn discover_ipv4(settings: Option<&IpDiscoveryConfig>) -> Result<Ipv4Addr> {
+    match settings.filter(|s| !s.ipv4.is_empty()) {
+        Some(settings) => match resolve(&settings.ipv4, Family::V4, settings).await? {
+            IpAddr::V4(addr) => Ok(addr),
+            IpAddr::V6(addr) => Err(anyhow!("Expected an IPv4 address, got {addr}")),
+        },
+        None => public_ip::addr_v4()
+            .await
+            .ok_or(anyhow!("Failed to get the public IP address")),
+    }
+}
+
+async fn discover_ipv6(settings: Option<&IpDiscoveryConfig>) -> Result<Ipv6Addr> {
+    match settings.filter(|s| !s.ipv6.is_empty()) {
+        Some(settings) => match resolve(&settings.ipv6, Family::V6, settings).await? {
+            IpAddr::V6(addr) => Ok(addr),
+            IpAddr::V4(addr) => Err(anyhow!("Expected an IPv6 address, got {addr}")),
+        },
+        None => public_ip::addr_v6()
+            .await
+            .ok_or(anyhow!("Failed to get the public IPv6 address")),
+    }
+}
+
+/// Looks up the public addresses of the requested families, using the sources
+/// configured in `config`.
+pub async fn discover(config: &Config, ipv4: bool, ipv6: bool) -> Result<PublicAddresses> {
+    let settings = config.ip_discovery.as_ref();
+
+    let ipv4 = if ipv4 {
+        Some(discover_ipv4(settings).await?)
+    } else {
+        None
+    };
+
+    let ipv6 = if ipv6 {
+        Some(discover_ipv6(settings).await?)
+    } else {
+        None
+    };
+
+    Ok(PublicAddresses { ipv4, ipv6 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::mock_http::MockServer;
+    use tokio::net::UdpSocket;
+
+    fn http_source(server: &MockServer) -> IpSource {
+        IpSource::Http {
+            url: server.url().to_string(),
+            timeout: None,
+        }
+    }
+
+    fn settings(require_agreement: usize) -> IpDiscoveryConfig {
+        IpDiscoveryConfig {
+            require_agreement,
+            ..Default::default()
+        }
+    }
+
+    /// Answers a single A query with `answer`.
+    async fn serve_dns_once(answer: [u8; 4]) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 512];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let mut response = buf[..len].to_vec();
+            response[2] |= 0x80;
+            response[7] = 1;
+            response.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4]);
+            response.extend_from_slice(&answer);
+            socket.send_to(&response, peer).await.unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn first_source_wins_by_default() {
+        let a = MockServer::start(|_| (200, "1.2.3.4\n".to_string()));
+        let b = MockServer::start(|_| (200, "5.6.7.8".to_string()));
+        let addr = resolve(
+            &[http_source(&a), http_source(&b)],
+            Family::V4,
+            &settings(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(addr, IpAddr::from([1, 2, 3, 4]));
+        assert!(b.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failing_source_is_skipped() {
+        let a = MockServer::start(|_| (500, String::new()));
+        let b = MockServer::start(|_| (200, "not an address".to_string()));
+        let c = MockServer::start(|_| (200, "5.6.7.8".to_string()));
+        let sources = [http_source(&a), http_source(&b), http_source(&c)];
+        let addr = resolve(&sources, Family::V4, &settings(1)).await.unwrap();
+        assert_eq!(addr, IpAddr::from([5, 6, 7, 8]));
+    }
+
+    #[tokio::test]
+    async fn agreement_outvotes_a_misbehaving_source() {
+        let a = MockServer::start(|_| (200, "9.9.9.9".to_string()));
+        let b = MockServer::start(|_| (200, "1.2.3.4".to_string()));
+        let c = MockServer::start(|_| (200, "1.2.3.4".to_string()));
+        let sources = [http_source(&a), http_source(&b), http_source(&c)];
+        let addr = resolve(&sources, Family::V4, &settings(2)).await.unwrap();
+        assert_eq!(addr, IpAddr::from([1, 2, 3, 4]));
+    }
+
+    #[tokio::test]
+    async fn disagreement_fails() {
+        let a = MockServer::start(|_| (200, "9.9.9.9".to_string()));
+        let b = MockServer::start(|_| (200, "1.2.3.4".to_string()));
+        let sources = [http_source(&a), http_source(&b)];
+        assert!(resolve(&sources, Family::V4, &settings(2)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn impossible_agreement_is_rejected() {
+        let a = MockServer::start(|_| (200, "1.2.3.4".to_string()));
+        assert!(
+            resolve(&[http_source(&a)], Family::V4, &settings(2))
+                .await
+                .is_err()
+        );
+        assert!(
+            resolve(&[http_source(&a)], Family::V4, &settings(0))
+                .await
+                .is_err()
+        );
+        assert!(a.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn wrong_family_is_rejected() {
+        let a = MockServer::start(|_| (200, "::1".to_string()));
+        assert!(
+            resolve(&[http_source(&a)], Family::V4, &settings(1))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn dns_source_reads_answer() {
+        let server = serve_dns_once([1, 2, 3, 4]).await;
+        let source = IpSource::Dns {
+            server: server.to_string(),
+            name: "myip.example".to_string(),
+            timeout: None,
+        };
+        let addr = query_source(&source, Family::V4, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(addr, IpAddr::from([1, 2, 3, 4]));
+    }
+}
@@ -0,0 +1,340 @@
+//! Remembers the addresses last published for each domain, so that unchanged
+//! addresses are not sent to the provider on every tick.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::public_address::PublicAddresses;
+use crate::common;
+use crate::common::config::{Config, ResolvedDomain, ServiceConfig};
+use crate::common::files;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PublishedRecord {
+    /// Identifies the provider settings the record was published with.
+    pub fingerprint: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    #[serde(with = "humantime_serde")]
+    pub published_at: SystemTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PublishedState {
+    /// A random key for the fingerprints, so that they cannot be matched
+    /// against precomputed hashes of likely tokens or compared between
+    /// installs. Anyone who can read the file can still test guessed tokens
+    /// offline, which is why it is only readable by its owner.
+    #[serde(default = "generate_key")]
+    key: String,
+    #[serde(default)]
+    domains: BTreeMap<String, PublishedRecord>,
+    /// When each domain was last checked without an error, so that every
+    /// domain is checked on its own interval.
+    #[serde(default)]
+    checked: BTreeMap<String, humantime_serde::Serde<SystemTime>>,
+}
+
+impl Default for PublishedState {
+    fn default() -> Self {
+        Self {
+            key: generate_key(),
+            domains: BTreeMap::new(),
+            checked: BTreeMap::new(),
+        }
+    }
+}
+
+fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    if let Err(e) = getrandom::getrandom(&mut bytes) {
+        log::warn!("Failed to generate a key for the published addresses: {e}");
+    }
+    BASE64.encode(bytes)
+}
+
+/// Signs everything that decides where and how records are published, so
+/// that switching provider, token or provider settings triggers a new update.
+///
+/// `serde_json` keeps object keys sorted, which makes the encoding canonical.
+fn fingerprint(key: &str, config: &ServiceConfig) -> String {
+    let settings = serde_json::json!({
+        "provider": config.provider,
+        "token": config.token.as_ref().map(|t| t.as_str()),
+        "cloudflare": config.cloudflare,
+        "rfc2136": config.rfc2136,
+        "dyndns2": config.dyndns2,
+        "webhook": config.webhook,
+    });
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(settings.to_string().as_bytes());
+    BASE64.encode(mac.finalize().into_bytes())
+}
+
+impl PublishedState {
+    fn file_path() -> Result<PathBuf> {
+        let mut path = Config::get_config_directory_path()?;
+        path.push(common::strings::PUBLISHED_STATE_FILE_NAME);
+        Ok(path)
+    }
+
+    /// Reads the state from disk. A missing or unreadable file yields an empty
+    /// state, which only means the next update is sent unconditionally.
+    pub fn read() -> Self {
+        let Ok(path) = Self::file_path() else {
+            return Self::default();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("Ignoring malformed {path:?}: {e}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn store(&self) -> Result<()> {
+        let path = Self::file_path()?;
+        files::write_private_file(&path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Whether every configured domain already points at `addresses` and was
+    /// published within the refresh interval.
+    pub fn is_current(
+        &self,
+        config: &ServiceConfig,
+        addresses: &PublicAddresses,
+        now: SystemTime,
+    ) -> bool {
+        let fingerprint = fingerprint(&self.key, config);
+
+        !config.domain.is_empty()
+            && config.domain.iter().all(|domain| {
+                self.domains.get(domain).is_some_and(|record| {
+                    record.fingerprint == fingerprint
+                        && record.ipv4 == addresses.ipv4
+                        && record.ipv6 == addresses.ipv6
+                        && now
+                            .duration_since(record.published_at)
+                            .is_ok_and(|age| age < config.refresh_interval)
+                })
+            })
+    }
+
+    /// Records `addresses` as published for every domain in `config`.
+    pub fn record(&mut self, config: &ServiceConfig, addresses: &PublicAddresses, now: SystemTime) {
+        let fingerprint = fingerprint(&self.key, config);
+
+        for domain in &config.domain {
+            self.domains.insert(
+                domain.clone(),
+                PublishedRecord {
+                    fingerprint: fingerprint.clone(),
+                    ipv4: addresses.ipv4,
+                    ipv6: addresses.ipv6,
+                    published_at: now,
+                },
+            );
+        }
+    }
+
+    /// Forgets the domains in `config`, e.g. after a failed or partial update.
+    pub fn forget(&mut self, config: &ServiceConfig) {
+        self.domains
+            .retain(|domain, _| !config.domain.contains(domain));
+    }
+
+    /// Whether `domain` is due for a check on a schedule that ticks every
+    /// `tick`, i.e. whether its interval would run out before the next tick.
+    pub fn is_due(&self, domain: &ResolvedDomain, tick: Duration, now: SystemTime) -> bool {
+        self.checked
+            .get(&domain.name)
+            .is_none_or(|checked_at| **checked_at + domain.interval < now + tick)
+    }
+
+    /// Remembers that the domains in `config` were checked at `now`.
+    pub fn mark_checked(&mut self, config: &ServiceConfig, now: SystemTime) {
+        for domain in &config.domain {
+            self.checked.insert(domain.clone(), now.into());
+        }
+    }
+
+    /// Drops domains that are no longer configured.
+    pub fn retain(&mut self, names: &BTreeSet<String>) {
+        self.domains.retain(|domain, _| names.contains(domain));
+        self.checked.retain(|domain, _| names.contains(domain));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::{ProviderKind, Token};
+    use crate::service::fixtures::{ADDRESSES, make_config};
+    use std::time::Duration;
+
+    #[test]
+    fn empty_state_is_not_current() {
+        let state = PublishedState::default();
+        assert!(!state.is_current(
+            &make_config(ProviderKind::DuckDns, &["a"]),
+            &ADDRESSES,
+            SystemTime::now()
+        ));
+    }
+
+    #[test]
+    fn recorded_addresses_are_current() {
+        let config = make_config(ProviderKind::DuckDns, &["a", "b"]);
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        state.record(&config, &ADDRESSES, now);
+        assert!(state.is_current(&config, &ADDRESSES, now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn changed_address_is_not_current() {
+        let config = make_config(ProviderKind::DuckDns, &["a"]);
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        state.record(&config, &ADDRESSES, now);
+
+        let changed = PublicAddresses {
+            ipv4: Some(Ipv4Addr::new(5, 6, 7, 8)),
+            ipv6: None,
+        };
+        assert!(!state.is_current(&config, &changed, now));
+
+        let with_ipv6 = PublicAddresses {
+            ipv4: ADDRESSES.ipv4,
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+        assert!(!state.is_current(&config, &with_ipv6, now));
+    }
+
+    #[test]
+    fn refresh_interval_expires_records() {
+        let config = make_config(ProviderKind::DuckDns, &["a"]);
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        state.record(&config, &ADDRESSES, now);
+        assert!(!state.is_current(&config, &ADDRESSES, now + config.refresh_interval));
+    }
+
+    #[test]
+    fn new_domain_is_not_current() {
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        state.record(&make_config(ProviderKind::DuckDns, &["a"]), &ADDRESSES, now);
+        assert!(!state.is_current(
+            &make_config(ProviderKind::DuckDns, &["a", "b"]),
+            &ADDRESSES,
+            now
+        ));
+    }
+
+    #[test]
+    fn provider_settings_change_is_not_current() {
+        let mut config = make_config(ProviderKind::DuckDns, &["a"]);
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        state.record(&config, &ADDRESSES, now);
+
+        config.token = Some(Token::new("other".to_string()));
+        assert!(!state.is_current(&config, &ADDRESSES, now));
+
+        config.token = Some(Token::new("token".to_string()));
+        config.provider = ProviderKind::Cloudflare;
+        assert!(!state.is_current(&config, &ADDRESSES, now));
+    }
+
+    #[test]
+    fn retain_drops_removed_domains() {
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        let config = make_config(ProviderKind::DuckDns, &["a", "b"]);
+        state.record(&config, &ADDRESSES, now);
+        state.mark_checked(&config, now);
+
+        state.retain(&BTreeSet::from(["a".to_string()]));
+        assert_eq!(state.domains.keys().collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(state.checked.keys().collect::<Vec<_>>(), vec!["a"]);
+    }
+
+    #[test]
+    fn domains_are_due_on_their_own_interval() {
+        let tick = Duration::from_secs(300);
+        let hourly = ResolvedDomain {
+            name: "a".to_string(),
+            provider: ProviderKind::DuckDns,
+            token: None,
+            ipv4: true,
+            ipv6: false,
+            interval: Duration::from_secs(3600),
+        };
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        assert!(state.is_due(&hourly, tick, now));
+
+        state.mark_checked(&make_config(ProviderKind::DuckDns, &["a"]), now);
+        assert!(!state.is_due(&hourly, tick, now + tick));
+        // Due on the last tick before the interval runs out.
+        assert!(state.is_due(&hourly, tick, now + Duration::from_secs(3400)));
+
+        // A domain on the loop's own interval is due on every tick.
+        let every_tick = ResolvedDomain {
+            interval: tick,
+            ..hourly
+        };
+        assert!(state.is_due(&every_tick, tick, now + tick));
+    }
+
+    #[test]
+    fn forget_invalidates_domains() {
+        let config = make_config(ProviderKind::DuckDns, &["a"]);
+        let now = SystemTime::now();
+        let mut state = PublishedState::default();
+        state.record(&config, &ADDRESSES, now);
+        state.forget(&config);
+        assert!(!state.is_current(&config, &ADDRESSES, now));
+    }
+
+    #[test]
+    fn state_roundtrips_through_json() {
+        let config = make_config(ProviderKind::DuckDns, &["a"]);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut state = PublishedState::default();
+        state.record(&config, &ADDRESSES, now);
+        state.mark_checked(&config, now);
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(!json.contains("token"));
+        let parsed: PublishedState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.domains, state.domains);
+        assert_eq!(parsed.checked, state.checked);
+        assert!(parsed.is_current(&config, &ADDRESSES, now));
+    }
+
+    #[test]
+    fn fingerprint_is_stable_and_keyed() {
+        let config = make_config(ProviderKind::DuckDns, &["a"]);
+        assert_eq!(
+            fingerprint("key", &config),
+            "H7Oebdk82qeQDV2omI_DmqCVllx_EQOWoBUgyeroF8E"
+        );
+        assert_ne!(fingerprint("other", &config), fingerprint("key", &config));
+    }
+}
@@ -0,0 +1,464 @@
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+
+use super::dns_wire::{
+    self, CLASS_ANY, CLASS_IN, OPCODE_UPDATE, TYPE_A, TYPE_AAAA, TYPE_SOA, TYPE_TSIG,
+};
+use super::provider::{DnsProvider, is_valid_hostname};
+use super::public_address::PublicAddresses;
+use crate::common::config::{Rfc2136Config, ServiceConfig, TsigAlgorithm, TsigKey};
+use crate::common::consts::DNS_TIMEOUT;
+
+/// Allowed clock skew between us and the server when verifying TSIG (RFC 8945 §5.2.3).
+const TSIG_FUDGE: u16 = 300;
+
+/// The RFC 2136 backend, which replaces the A/AAAA RRsets of every configured
+/// name with a single DNS UPDATE message, optionally signed with TSIG.
+///
+/// Only the response code is checked; the TSIG signature on the response is not
+/// verified.
+pub struct Rfc2136 {
+    settings: Rfc2136Config,
+    secret: Option<Vec<u8>>,
+}
+
+/// A single entry in the update section of a DNS UPDATE message.
+enum Change {
+    /// Deletes the whole RRset of the given type (RFC 2136 §2.5.2).
+    DeleteRrset(String, u16),
+    /// Adds a record to an RRset (RFC 2136 §2.5.1).
+    Add(String, u16, Vec<u8>),
+}
+
+fn algorithm_name(algorithm: TsigAlgorithm) -> &'static str {
+    match algorithm {
+        TsigAlgorithm::HmacSha256 => "hmac-sha256",
+        TsigAlgorithm::HmacSha512 => "hmac-sha512",
+    }
+}
+
+fn hmac(algorithm: TsigAlgorithm, secret: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    Ok(match algorithm {
+        TsigAlgorithm::HmacSha256 => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+                .map_err(|e| anyhow!("Invalid TSIG secret: {e}"))?;
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+        TsigAlgorithm::HmacSha512 => {
+            let mut mac = Hmac::<Sha512>::new_from_slice(secret)
+                .map_err(|e| anyhow!("Invalid TSIG secret: {e}"))?;
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+    })
+}
+
+/// Appends a TSIG record to `message` (RFC 8945 §4.2) and bumps its ARCOUNT.
+fn sign(message: &mut Vec<u8>, key: &TsigKey, secret: &[u8], time_signed: u64) -> Result<()> {
+    let algorithm = algorithm_name(key.algorithm);
+    let time_signed = &time_signed.to_be_bytes()[2..];
+
+    // TSIG variables, in canonical (lowercase, uncompressed) form.
+    let mut signed = message.clone();
+    dns_wire::write_name(&mut signed, &key.name.to_ascii_lowercase())?;
+    signed.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    signed.extend_from_slice(&0u32.to_be_bytes());
+    dns_wire::write_name(&mut signed, algorithm)?;
+    signed.extend_from_slice(time_signed);
+    signed.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+    signed.extend_from_slice(&0u16.to_be_bytes()); // error
+    signed.extend_from_slice(&0u16.to_be_bytes()); // other len
+
+    let mac = hmac(key.algorithm, secret, &signed)?;
+    let id = dns_wire::read_u16(message, 0)?;
+
+    let mut rdata = Vec::new();
+    dns_wire::write_name(&mut rdata, algorithm)?;
+    rdata.extend_from_slice(time_signed);
+    rdata.extend_from_slice(&TSIG_FUDGE.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(&mac);
+    rdata.extend_from_slice(&id.to_be_bytes());
+    rdata.extend_from_slice(&0u16.to_be_bytes()); // error
+    rdata.extend_from_slice(&0u16.to_be_bytes()); // other len
+
+    dns_wire::write_record(message, &key.name, TYPE_TSIG, CLASS_ANY, 0, &rdata)?;
+
+    let arcount = dns_wire::read_u16(message, 10)? + 1;
+    message[10..12].copy_from_slice(&arcount.to_be_bytes());
+    Ok(())
+}
+
+impl Rfc2136 {
+    pub fn new(config: &Rfc2136Config) -> Result<Self> {
+        if !is_valid_hostname(config.zone.strip_suffix('.').unwrap_or(&config.zone)) {
+            return Err(anyhow!("Invalid RFC 2136 zone: '{}'", config.zone));
+        }
+
+        let secret = match &config.tsig {
+            Some(key) => Some(
+                BASE64
+                    .decode(key.secret.as_str())
+                    .map_err(|e| anyhow!("Invalid TSIG secret for key '{}': {e}", key.name))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            settings: config.clone(),
+            secret,
+        })
+    }
+
+    fn in_zone(&self, name: &str) -> bool {
+        let zone = self
+            .settings
+            .zone
+            .strip_suffix('.')
+            .unwrap_or(&self.settings.zone)
+            .to_ascii_lowercase();
+        let name = name.to_ascii_lowercase();
+        name == zone || name.ends_with(&format!(".{zone}"))
+    }
+
+    fn build_message(&self, id: u16, changes: &[Change], time_signed: u64) -> Result<Vec<u8>> {
+        let mut message = Vec::new();
+        dns_wire::write_header(
+            &mut message,
+            id,
+            OPCODE_UPDATE << 11,
+            [1, 0, changes.len() as u16, 0],
+        );
+
+        // Zone section
+        dns_wire::write_name(&mut message, &self.settings.zone)?;
+        message.extend_from_slice(&TYPE_SOA.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+        // Update section
+        for change in changes {
+            match change {
+                Change::DeleteRrset(name, rtype) => {
+                    dns_wire::write_record(&mut message, name, *rtype, CLASS_ANY, 0, &[])?
+                }
+                Change::Add(name, rtype, rdata) => dns_wire::write_record(
+                    &mut message,
+                    name,
+                    *rtype,
+                    CLASS_IN,
+                    self.settings.ttl,
+                    rdata,
+                )?,
+            }
+        }
+
+        if let (Some(key), Some(secret)) = (&self.settings.tsig, &self.secret) {
+            sign(&mut message, key, secret, time_signed)?;
+        }
+
+        Ok(message)
+    }
+
+    async fn server_address(&self) -> Result<SocketAddr> {
+        tokio::net::lookup_host((self.settings.server.as_str(), self.settings.port))
+            .await
+            .map_err(|e| anyhow!("Failed to resolve {}: {e}", self.settings.server))?
+            .next()
+            .ok_or(anyhow!("Failed to resolve {}", self.settings.server))
+    }
+
+    async fn send(&self, changes: &[Change]) -> Result<()> {
+        let id = dns_wire::random_id();
+        let time_signed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let message = self.build_message(id, changes, time_signed)?;
+        let server = self.server_address().await?;
+
+        log::debug!(
+            "Sending DNS UPDATE with {} change(s) to {server} over {:?}",
+            changes.len(),
+            self.settings.transport
+        );
+        let response =
+            dns_wire::exchange(server, self.settings.transport, &message, DNS_TIMEOUT).await?;
+
+        match dns_wire::response_code(&response, id)? {
+            0 => Ok(()),
+            rcode => Err(anyhow!(
+                "DNS server {server} responded with {}",
+                dns_wire::rcode_name(rcode)
+            )),
+        }
+    }
+}
+
+impl DnsProvider for Rfc2136 {
+    fn validate_name(&self, name: &str) -> Result<()> {
+        if !is_valid_hostname(name) {
+            Err(anyhow!("Invalid record name: {name}"))
+        } else if !self.in_zone(name) {
+            Err(anyhow!(
+                "{name} is not inside the zone {}",
+                self.settings.zone
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stale RRsets are deleted by `update` itself when an address family is
+    /// disabled.
+    fn requires_clear(&self) -> bool {
+        false
+    }
+
+    async fn update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<String> {
+        let mut changes = Vec::new();
+        for name in &config.domain {
+            changes.push(Change::DeleteRrset(name.clone(), TYPE_A));
+            if let Some(v4) = addresses.ipv4 {
+                changes.push(Change::Add(name.clone(), TYPE_A, v4.octets().to_vec()));
+            }
+            changes.push(Change::DeleteRrset(name.clone(), TYPE_AAAA));
+            if let Some(v6) = addresses.ipv6 {
+                changes.push(Change::Add(name.clone(), TYPE_AAAA, v6.octets().to_vec()));
+            }
+        }
+
+        self.send(&changes).await?;
+        Ok("NOERROR".to_string())
+    }
+
+    async fn clear(&self, config: &ServiceConfig) -> Result<()> {
+        let changes: Vec<Change> = config
+            .domain
+            .iter()
+            .flat_map(|name| {
+                [
+                    Change::DeleteRrset(name.clone(), TYPE_A),
+                    Change::DeleteRrset(name.clone(), TYPE_AAAA),
+                ]
+            })
+            .collect();
+
+        self.send(&changes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::{DnsTransport, ProviderKind, Token};
+    use crate::service::fixtures::make_config;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn make_settings(tsig: Option<TsigKey>) -> Rfc2136Config {
+        Rfc2136Config {
+            server: "127.0.0.1".to_string(),
+            port: 53,
+            transport: DnsTransport::Udp,
+            zone: "example.com".to_string(),
+            ttl: 300,
+            tsig,
+        }
+    }
+
+    fn make_key(algorithm: TsigAlgorithm) -> TsigKey {
+        TsigKey {
+            name: "ddns-key".to_string(),
+            algorithm,
+            // base64 of "0123456789abcdef0123456789abcdef"
+            secret: Token::new("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=".to_string()),
+        }
+    }
+
+    fn update_changes() -> Vec<Change> {
+        vec![
+            Change::DeleteRrset("host.example.com".to_string(), TYPE_A),
+            Change::Add(
+                "host.example.com".to_string(),
+                TYPE_A,
+                Ipv4Addr::new(192, 0, 2, 1).octets().to_vec(),
+            ),
+        ]
+    }
+
+    const UNSIGNED_REFERENCE: &str = concat!(
+        "beef28000001000000020000",
+        "076578616d706c6503636f6d0000060001",
+        "04686f7374076578616d706c6503636f6d00000100ff000000000000",
+        "04686f7374076578616d706c6503636f6d00000100010000012c0004c0000201",
+    );
+
+    const SIGNED_REFERENCE: &str = concat!(
+        "beef28000001000000020001",
+        "076578616d706c6503636f6d0000060001",
+        "04686f7374076578616d706c6503636f6d00000100ff000000000000",
+        "04686f7374076578616d706c6503636f6d00000100010000012c0004c0000201",
+        "0864646e732d6b65790000fa00ff00000000003d",
+        "0b686d61632d7368613235360000006553f100012c0020",
+        "02b5f5b22cf91df07b10fe537278ecf0c00f6162a6a5bb006cda182c845b8fbe",
+        "beef00000000",
+    );
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn new_rejects_invalid_secret() {
+        let mut key = make_key(TsigAlgorithm::HmacSha256);
+        key.secret = Token::new("not base64!".to_string());
+        assert!(Rfc2136::new(&make_settings(Some(key))).is_err());
+    }
+
+    #[test]
+    fn new_rejects_invalid_zone() {
+        let mut settings = make_settings(None);
+        settings.zone = "bad zone".to_string();
+        assert!(Rfc2136::new(&settings).is_err());
+    }
+
+    #[test]
+    fn validate_name_requires_zone_membership() {
+        let provider = Rfc2136::new(&make_settings(None)).unwrap();
+        assert!(provider.validate_name("host.example.com").is_ok());
+        assert!(provider.validate_name("HOST.Example.COM").is_ok());
+        assert!(provider.validate_name("example.com").is_ok());
+        assert!(provider.validate_name("host.example.org").is_err());
+        assert!(provider.validate_name("notexample.com").is_err());
+        assert!(provider.validate_name("bad..example.com").is_err());
+    }
+
+    #[test]
+    fn unsigned_update_message_layout() {
+        let provider = Rfc2136::new(&make_settings(None)).unwrap();
+        let message = provider
+            .build_message(0xbeef, &update_changes(), 0)
+            .unwrap();
+        assert_eq!(hex(&message), UNSIGNED_REFERENCE);
+    }
+
+    #[test]
+    fn signed_update_matches_reference() {
+        // Reference computed independently from RFC 8945 with Python's hmac module.
+        let provider =
+            Rfc2136::new(&make_settings(Some(make_key(TsigAlgorithm::HmacSha256)))).unwrap();
+        let message = provider
+            .build_message(0xbeef, &update_changes(), 1_700_000_000)
+            .unwrap();
+        assert_eq!(dns_wire::read_u16(&message, 10).unwrap(), 1);
+        assert_eq!(hex(&message), SIGNED_REFERENCE);
+    }
+
+    #[test]
+    fn sha512_mac_length() {
+        let provider =
+            Rfc2136::new(&make_settings(Some(make_key(TsigAlgorithm::HmacSha512)))).unwrap();
+        let unsigned = Rfc2136::new(&make_settings(None))
+            .unwrap()
+            .build_message(1, &update_changes(), 0)
+            .unwrap();
+        let signed = provider.build_message(1, &update_changes(), 0).unwrap();
+        let mut algorithm = Vec::new();
+        dns_wire::write_name(&mut algorithm, "hmac-sha512").unwrap();
+        // owner + type/class/ttl/rdlength + algorithm + time/fudge + mac size
+        let mac_size_offset = unsigned.len() + 10 + 10 + algorithm.len() + 8;
+        assert_eq!(dns_wire::read_u16(&signed, mac_size_offset).unwrap(), 64);
+    }
+
+    async fn serve_once(rcode: u16) -> (SocketAddr, tokio::task::JoinHandle<Vec<u8>>) {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let mut buf = vec![0; 4096];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            buf.truncate(len);
+            let mut response = Vec::new();
+            let id = dns_wire::read_u16(&buf, 0).unwrap();
+            dns_wire::write_header(
+                &mut response,
+                id,
+                0x8000 | (OPCODE_UPDATE << 11) | rcode,
+                [0; 4],
+            );
+            socket.send_to(&response, peer).await.unwrap();
+            buf
+        });
+        (addr, handle)
+    }
+
+    fn provider_for(addr: SocketAddr) -> Rfc2136 {
+        let mut settings = make_settings(Some(make_key(TsigAlgorithm::HmacSha256)));
+        settings.server = addr.ip().to_string();
+        settings.port = addr.port();
+        Rfc2136::new(&settings).unwrap()
+    }
+
+    #[tokio::test]
+    async fn update_sends_signed_message() {
+        let (addr, server) = serve_once(0).await;
+        let provider = provider_for(addr);
+        let addresses = PublicAddresses {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+
+        provider
+            .update(
+                &make_config(ProviderKind::Rfc2136, &["host.example.com"]),
+                &addresses,
+            )
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap();
+        // delete A, add A, delete AAAA, add AAAA
+        assert_eq!(dns_wire::read_u16(&request, 8).unwrap(), 4);
+        assert_eq!(dns_wire::read_u16(&request, 10).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn update_reports_rcode() {
+        let (addr, _server) = serve_once(9).await;
+        let provider = provider_for(addr);
+        let addresses = PublicAddresses {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ipv6: None,
+        };
+
+        let err = provider
+            .update(
+                &make_config(ProviderKind::Rfc2136, &["host.example.com"]),
+                &addresses,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("NOTAUTH"));
+    }
+
+    #[tokio::test]
+    async fn clear_deletes_both_rrsets() {
+        let (addr, server) = serve_once(0).await;
+        let provider = provider_for(addr);
+
+        provider
+            .clear(&make_config(
+                ProviderKind::Rfc2136,
+                &["a.example.com", "b.example.com"],
+            ))
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap();
+        assert_eq!(dns_wire::read_u16(&request, 8).unwrap(), 4);
+    }
+}
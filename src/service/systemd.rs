@@ -0,0 +1,143 @@
+//! The parts of the systemd service protocol the daemon speaks: readiness and
+//! watchdog notifications (`sd_notify`) and socket activation.
+//!
+//! All of them are no-ops when the daemon is not started by systemd.
+
+use std::env;
+use std::io;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::time::Duration;
+
+/// The first file descriptor passed by socket activation (`SD_LISTEN_FDS_START`).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sends `state` (e.g. `READY=1`) to the service manager, if it asked for
+/// notifications by setting `NOTIFY_SOCKET`.
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    if let Err(e) = send_notification(path.as_encoded_bytes(), state) {
+        log::warn!("Failed to notify systemd: {e}");
+    }
+}
+
+fn send_notification(path: &[u8], state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    // A leading '@' denotes a socket in the abstract namespace.
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+
+    socket.send_to(state.as_bytes(), std::ffi::OsStr::from_bytes(path))?;
+    Ok(())
+}
+
+/// How often the watchdog must be pinged with `WATCHDOG=1`: half of the
+/// `WatchdogSec=` configured in the unit, as recommended by `sd_watchdog_enabled(3)`.
+pub fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        env::var("WATCHDOG_USEC").ok().as_deref(),
+        env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    // WATCHDOG_PID is optional, but when set it must name this process.
+    if let Some(pid) = pid
+        && pid.parse::<u32>().ok() != Some(own_pid)
+    {
+        return None;
+    }
+
+    let usec: u64 = usec?.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// The number of sockets passed to this process by socket activation.
+fn listen_fds(fds: Option<&str>, pid: Option<&str>, own_pid: u32) -> usize {
+    if pid.and_then(|p| p.parse::<u32>().ok()) != Some(own_pid) {
+        return 0;
+    }
+    fds.and_then(|n| n.parse().ok()).unwrap_or(0)
+}
+
+/// Takes over the listening socket passed by socket activation, if any.
+pub fn activated_listener() -> io::Result<Option<UnixListener>> {
+    let count = listen_fds(
+        env::var("LISTEN_FDS").ok().as_deref(),
+        env::var("LISTEN_PID").ok().as_deref(),
+        std::process::id(),
+    );
+
+    match count {
+        0 => Ok(None),
+        1 => {
+            // Safety: systemd passes ownership of the descriptor to us, and it
+            // is only taken once since the listener is bound once per process.
+            let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+            listener.set_nonblocking(true)?;
+            Ok(Some(listener))
+        }
+        n => Err(io::Error::other(format!(
+            "Expected a single socket from systemd, got {n}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_is_pinged_at_half_the_timeout() {
+        assert_eq!(
+            parse_watchdog(Some("60000000"), None, 42),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog(Some("60000000"), Some("42"), 42),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn watchdog_for_another_process_is_ignored() {
+        assert_eq!(parse_watchdog(Some("60000000"), Some("7"), 42), None);
+        assert_eq!(parse_watchdog(None, None, 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog(Some("soon"), None, 42), None);
+    }
+
+    #[test]
+    fn listen_fds_require_matching_pid() {
+        assert_eq!(listen_fds(Some("1"), Some("42"), 42), 1);
+        assert_eq!(listen_fds(Some("1"), Some("7"), 42), 0);
+        assert_eq!(listen_fds(Some("1"), None, 42), 0);
+        assert_eq!(listen_fds(None, Some("42"), 42), 0);
+    }
+
+    #[test]
+    fn notification_reaches_socket() {
+        let path =
+            std::env::temp_dir().join(format!("barvazdns-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        send_notification(path.as_os_str().as_bytes(), "READY=1").unwrap();
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,69 @@
+//! Runs the service as a foreground daemon, e.g. under an init system.
+
+use anyhow::{Result, anyhow};
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+
+use super::{ensure_config_directory, event_channel, logger_init, new_context, run_service};
+use crate::common::config::Config;
+
+/// Time since the system booted, where the platform exposes it.
+pub fn uptime() -> Option<Duration> {
+    let content = std::fs::read_to_string("/proc/uptime").ok()?;
+    parse_uptime(&content)
+}
+
+fn parse_uptime(content: &str) -> Option<Duration> {
+    let seconds: f64 = content.split_whitespace().next()?.parse().ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Completes on SIGTERM (sent by init systems to stop a service) or SIGINT.
+async fn wait_for_shutdown_signal() {
+    let (Ok(mut term), Ok(mut int)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) else {
+        log::error!("Failed to install signal handlers");
+        return;
+    };
+
+    tokio::select! {
+        _ = term.recv() => log::debug!("Received SIGTERM"),
+        _ = int.recv() => log::debug!("Received SIGINT"),
+    }
+}
+
+/// Runs the service in the foreground until it receives SIGTERM or SIGINT.
+///
+/// Logs go to the config directory and are duplicated to stderr, where the
+/// init system (or the terminal) picks them up. The web dashboard is served
+/// from the daemon itself unless `with_web` is false or it is disabled in the
+/// configuration.
+pub fn run_daemon(with_web: bool) -> Result<()> {
+    ensure_config_directory()?;
+    let events = event_channel();
+    let logger_handle = logger_init("info", true, events.clone())
+        .map_err(|e| anyhow!("Failed to initialize logger: {e}"))?;
+    let config = Config::read()?;
+
+    let with_dashboard = with_web && !config.dashboard.as_ref().is_some_and(|d| d.disabled);
+    let context = new_context(logger_handle, config, events);
+
+    run_service(context, wait_for_shutdown_signal(), with_dashboard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proc_uptime() {
+        assert_eq!(
+            parse_uptime("350735.47 234388.90\n"),
+            Some(Duration::from_secs_f64(350735.47))
+        );
+        assert_eq!(parse_uptime(""), None);
+        assert_eq!(parse_uptime("garbage"), None);
+    }
+}
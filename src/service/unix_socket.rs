@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use tokio::net::{UnixListener, UnixStream};
+
+use super::systemd;
+use crate::common::strings;
+
+/// Only the owner (the user the daemon runs as, normally root) may connect,
+/// which protects the token that flows through the socket the same way the
+/// named pipe's DACL does on Windows.
+const SOCKET_MODE: u32 = 0o600;
+
+/// Accepts clients on the service's Unix domain socket.
+pub struct IpcListener {
+    listener: UnixListener,
+    /// The socket file if it was created here, rather than passed in by
+    /// systemd socket activation, so that it is removed on drop.
+    path: Option<PathBuf>,
+}
+
+impl IpcListener {
+    /// Uses the socket passed by systemd socket activation if there is one,
+    /// and binds [`strings::SOCKET_PATH`] otherwise.
+    pub fn bind() -> io::Result<Self> {
+        if let Some(listener) = systemd::activated_listener()? {
+            log::debug!("Using the socket passed by systemd");
+            return Ok(Self {
+                listener: UnixListener::from_std(listener)?,
+                path: None,
+            });
+        }
+
+        Self::bind_at(Path::new(strings::SOCKET_PATH))
+    }
+
+    pub(super) fn bind_at(path: &Path) -> io::Result<Self> {
+        // A socket left behind by a previous run that did not shut down cleanly.
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+
+        // Bind inside a directory only the owner can enter and move the socket
+        // into place once its mode is set, so that it is never accessible to
+        // anyone else in between.
+        let staging = path.with_extension("staging");
+        let _ = fs::remove_dir_all(&staging);
+        fs::DirBuilder::new().mode(0o700).create(&staging)?;
+        let staged = staging.join("socket");
+        let listener = UnixListener::bind(&staged).and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(SOCKET_MODE))?;
+            fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+        let _ = fs::remove_dir_all(&staging);
+
+        Ok(Self {
+            listener: listener?,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub async fn accept(&mut self) -> io::Result<UnixStream> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(stream)
+    }
+}
+
+impl Drop for IpcListener {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("barvazdns-{}-{name}.sock", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn socket_is_owner_only() {
+        let path = socket_path("mode");
+        let _listener = IpcListener::bind_at(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, SOCKET_MODE);
+        assert!(!path.with_extension("staging").exists());
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_socket_and_cleans_up() {
+        let path = socket_path("stale");
+        fs::write(&path, "").unwrap();
+
+        let mut listener = IpcListener::bind_at(&path).unwrap();
+        let client = tokio::spawn({
+            let path = path.clone();
+            async move {
+                let mut stream = UnixStream::connect(path).await.unwrap();
+                stream.write_all(b"ping").await.unwrap();
+            }
+        });
+        let mut stream = listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        client.await.unwrap();
+        assert_eq!(buf, b"ping");
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,373 @@
+use anyhow::{Result, anyhow};
+use regex::Regex;
+
+use super::provider::{DnsProvider, is_valid_hostname};
+use super::public_address::PublicAddresses;
+use crate::common::config::{HttpMethod, ServiceConfig, WebhookConfig};
+use crate::common::consts::HTTP_TIMEOUT_SECS;
+
+/// A user defined HTTP endpoint. When a template refers to `{domain}`, one
+/// request is sent per configured domain, otherwise a single request covers
+/// all of them through `{domains}`.
+pub struct Webhook {
+    settings: WebhookConfig,
+    success: Option<Regex>,
+}
+
+/// Values substituted into the URL, header and body templates.
+struct Placeholders<'a> {
+    domain: &'a str,
+    domains: &'a str,
+    token: &'a str,
+    addresses: &'a PublicAddresses,
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986, so
+/// that a value substituted into the URL cannot change its structure.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Substitutes the placeholders in `template`, passing each value through
+/// `escape` first.
+fn render_template(template: &str, values: &Placeholders, escape: fn(&str) -> String) -> String {
+    let ipv4 = values
+        .addresses
+        .ipv4
+        .map(|v4| v4.to_string())
+        .unwrap_or_default();
+    let ipv6 = values
+        .addresses
+        .ipv6
+        .map(|v6| v6.to_string())
+        .unwrap_or_default();
+
+    template
+        .replace("{domains}", &escape(values.domains))
+        .replace("{domain}", &escape(values.domain))
+        .replace("{ipv4}", &escape(&ipv4))
+        .replace("{ipv6}", &escape(&ipv6))
+        .replace("{token}", &escape(values.token))
+}
+
+fn to_minreq_method(method: HttpMethod) -> minreq::Method {
+    match method {
+        HttpMethod::Get => minreq::Method::Get,
+        HttpMethod::Post => minreq::Method::Post,
+        HttpMethod::Put => minreq::Method::Put,
+        HttpMethod::Patch => minreq::Method::Patch,
+    }
+}
+
+impl Webhook {
+    pub fn new(config: &WebhookConfig) -> Result<Self> {
+        if !config.url.starts_with("http://") && !config.url.starts_with("https://") {
+            return Err(anyhow!("Invalid webhook URL: '{}'", config.url));
+        }
+
+        let success = config
+            .success
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid webhook success pattern: {e}"))?;
+
+        Ok(Self {
+            settings: config.clone(),
+            success,
+        })
+    }
+
+    fn per_domain(&self) -> bool {
+        self.settings.url.contains("{domain}")
+            || self
+                .settings
+                .body
+                .as_ref()
+                .is_some_and(|b| b.contains("{domain}"))
+            || self
+                .settings
+                .headers
+                .values()
+                .any(|v| v.contains("{domain}"))
+    }
+
+    fn build_request(&self, values: &Placeholders) -> minreq::Request {
+        let url = render_template(&self.settings.url, values, percent_encode);
+        let mut request = minreq::Request::new(to_minreq_method(self.settings.method), url)
+            .with_timeout(HTTP_TIMEOUT_SECS);
+
+        for (name, value) in &self.settings.headers {
+            request = request.with_header(name, render_template(value, values, str::to_string));
+        }
+
+        if let Some(body) = &self.settings.body {
+            request = request.with_body(render_template(body, values, str::to_string));
+        }
+
+        request
+    }
+
+    fn send(&self, values: &Placeholders) -> Result<String> {
+        let res = self
+            .build_request(values)
+            .send()
+            .map_err(|e| anyhow!("Failed to reach webhook: {e}"))?;
+        let body = res.as_str()?;
+        log::debug!("Webhook responded with HTTP {}: {body}", res.status_code);
+
+        if !(200..300).contains(&res.status_code) {
+            return Err(anyhow!("Webhook failed with HTTP {}", res.status_code));
+        }
+
+        if let Some(success) = &self.success
+            && !success.is_match(body)
+        {
+            return Err(anyhow!("Unexpected webhook response '{body}'"));
+        }
+
+        Ok(format!("HTTP {} {}", res.status_code, body.trim()))
+    }
+}
+
+impl DnsProvider for Webhook {
+    fn validate_name(&self, name: &str) -> Result<()> {
+        if is_valid_hostname(name) {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid hostname: {name}"))
+        }
+    }
+
+    /// There is no generic way to remove an address, so clearing is skipped.
+    fn requires_clear(&self) -> bool {
+        false
+    }
+
+    async fn update(&self, config: &ServiceConfig, addresses: &PublicAddresses) -> Result<String> {
+        let token = config.token.as_ref().map_or("", |t| t.as_str());
+        let domains = config.domains_csv();
+        let mut values = Placeholders {
+            domain: "",
+            domains: &domains,
+            token,
+            addresses,
+        };
+
+        if !self.per_domain() {
+            log::debug!("Sending webhook update for domains: {domains}");
+            return self.send(&values);
+        }
+
+        let mut responses = Vec::new();
+        for domain in &config.domain {
+            log::debug!("Sending webhook update for {domain}");
+            values.domain = domain;
+            let response = self
+                .send(&values)
+                .map_err(|e| anyhow!("Failed to update {domain}: {e}"))?;
+            responses.push(format!("{domain}: {response}"));
+        }
+
+        Ok(responses.join("; "))
+    }
+
+    async fn clear(&self, _config: &ServiceConfig) -> Result<()> {
+        Err(anyhow!(
+            "The webhook provider does not support clearing addresses"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::config::{ProviderKind, Token};
+    use crate::service::fixtures::{ADDRESSES, make_config};
+    use crate::service::mock_http::MockServer;
+    use std::collections::BTreeMap;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn make_settings(url: &str) -> WebhookConfig {
+        WebhookConfig {
+            url: url.to_string(),
+            method: HttpMethod::Get,
+            headers: BTreeMap::new(),
+            body: None,
+            success: None,
+        }
+    }
+
+    #[test]
+    fn render_template_substitutes_placeholders() {
+        let addresses = PublicAddresses {
+            ipv4: Some(Ipv4Addr::new(1, 2, 3, 4)),
+            ipv6: Some(Ipv6Addr::LOCALHOST),
+        };
+        let values = Placeholders {
+            domain: "a.example.com",
+            domains: "a.example.com,b.example.com",
+            token: "secret",
+            addresses: &addresses,
+        };
+        assert_eq!(
+            render_template(
+                "h={domain}&all={domains}&ip={ipv4}&ip6={ipv6}&t={token}",
+                &values,
+                str::to_string
+            ),
+            "h=a.example.com&all=a.example.com,b.example.com&ip=1.2.3.4&ip6=::1&t=secret"
+        );
+    }
+
+    #[test]
+    fn render_template_escapes_values() {
+        let values = Placeholders {
+            domain: "a.example.com",
+            domains: "a.example.com,b.example.com",
+            token: "a&b #%c",
+            addresses: &ADDRESSES,
+        };
+        assert_eq!(
+            render_template("?all={domains}&t={token}", &values, percent_encode),
+            "?all=a.example.com%2Cb.example.com&t=a%26b%20%23%25c"
+        );
+    }
+
+    #[test]
+    fn render_template_empty_ipv6() {
+        let values = Placeholders {
+            domain: "",
+            domains: "",
+            token: "",
+            addresses: &ADDRESSES,
+        };
+        assert_eq!(
+            render_template("ip6={ipv6}", &values, str::to_string),
+            "ip6="
+        );
+    }
+
+    #[test]
+    fn new_rejects_invalid_settings() {
+        assert!(Webhook::new(&make_settings("ftp://example.com")).is_err());
+
+        let mut settings = make_settings("https://example.com");
+        settings.success = Some("(".to_string());
+        assert!(Webhook::new(&settings).is_err());
+    }
+
+    #[tokio::test]
+    async fn update_sends_one_request_per_domain() {
+        let server = MockServer::start(|_| (200, "OK".to_string()));
+        let settings = make_settings(&format!(
+            "{}/update?host={{domain}}&ip={{ipv4}}",
+            server.url()
+        ));
+        Webhook::new(&settings)
+            .unwrap()
+            .update(
+                &make_config(ProviderKind::Webhook, &["a.example.com", "b.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap();
+
+        let paths: Vec<String> = server.requests().iter().map(|r| r.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/update?host=a.example.com&ip=1.2.3.4",
+                "/update?host=b.example.com&ip=1.2.3.4"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_sends_single_request_for_all_domains() {
+        let server = MockServer::start(|_| (200, "OK".to_string()));
+        let mut settings = make_settings(&format!("{}/update", server.url()));
+        settings.method = HttpMethod::Post;
+        settings.body = Some(r#"{"hosts":"{domains}","ip":"{ipv4}"}"#.to_string());
+        settings
+            .headers
+            .insert("Authorization".to_string(), "Bearer {token}".to_string());
+        Webhook::new(&settings)
+            .unwrap()
+            .update(
+                &make_config(ProviderKind::Webhook, &["a.example.com", "b.example.com"]),
+                &ADDRESSES,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer token"));
+        assert_eq!(
+            requests[0].body,
+            r#"{"hosts":"a.example.com,b.example.com","ip":"1.2.3.4"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn update_encodes_values_in_the_url_only() {
+        let server = MockServer::start(|_| (200, "OK".to_string()));
+        let mut settings = make_settings(&format!("{}/update?t={{token}}", server.url()));
+        settings.method = HttpMethod::Post;
+        settings.body = Some("{token}".to_string());
+        settings
+            .headers
+            .insert("X-Token".to_string(), "{token}".to_string());
+        let mut config = make_config(ProviderKind::Webhook, &["a.example.com"]);
+        config.token = Some(Token::new("a&b #%c".to_string()));
+        Webhook::new(&settings)
+            .unwrap()
+            .update(&config, &ADDRESSES)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/update?t=a%26b%20%23%25c");
+        assert_eq!(requests[0].header("X-Token"), Some("a&b #%c"));
+        assert_eq!(requests[0].body, "a&b #%c");
+    }
+
+    #[tokio::test]
+    async fn update_checks_success_pattern() {
+        let server = MockServer::start(|_| (200, "KO".to_string()));
+        let mut settings = make_settings(&format!("{}/update", server.url()));
+        settings.success = Some("^OK".to_string());
+        let result = Webhook::new(&settings)
+            .unwrap()
+            .update(
+                &make_config(ProviderKind::Webhook, &["a.example.com"]),
+                &ADDRESSES,
+            )
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn update_fails_on_http_error() {
+        let server = MockServer::start(|_| (500, "OK".to_string()));
+        let settings = make_settings(&format!("{}/update", server.url()));
+        let result = Webhook::new(&settings)
+            .unwrap()
+            .update(
+                &make_config(ProviderKind::Webhook, &["a.example.com"]),
+                &ADDRESSES,
+            )
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Runs the service under the Windows Service Control Manager (SCM).
+
+use anyhow::{Result, anyhow};
+use std::ffi::OsString;
+use std::sync::mpsc;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::{
+    define_windows_service,
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+};
+use windows_sys::Win32::System::SystemInformation::GetTickCount64;
+
+use super::{ensure_config_directory, event_channel, logger_init, new_context, run_service};
+use crate::common::{self, config::Config};
+
+define_windows_service!(duckdns_service_main, service_main);
+
+pub fn service_dispatcher() -> Result<()> {
+    service_dispatcher::start(common::strings::SERVICE_NAME, duckdns_service_main)
+        .map_err(|e| anyhow!("Dispatching error: {e:#?}"))
+}
+
+/// Time since the system booted.
+pub fn uptime() -> Option<Duration> {
+    let ms_since_boot = unsafe { GetTickCount64() };
+    Some(Duration::from_millis(ms_since_boot))
+}
+
+fn set_service_status(
+    status_handle: &ServiceStatusHandle,
+    current_state: ServiceState,
+    exit_code: u32,
+) -> Result<(), windows_service::Error> {
+    let next_status = ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state,
+        controls_accepted: if current_state == ServiceState::Running {
+            ServiceControlAccept::STOP
+        } else {
+            ServiceControlAccept::empty()
+        },
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: if current_state == ServiceState::StartPending {
+            Duration::from_secs(10)
+        } else {
+            Duration::default()
+        },
+        process_id: None,
+    };
+
+    status_handle.set_service_status(next_status)
+}
+
+fn service_main(_args: Vec<OsString>) {
+    let (shutdown_tx, shutdown_rx) = mpsc::channel();
+
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
+        match control_event {
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            ServiceControl::Stop => {
+                let _ = shutdown_tx.send(());
+                ServiceControlHandlerResult::NoError
+            }
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+
+    // Register system service event handler
+    let status_handle =
+        service_control_handler::register(common::strings::SERVICE_NAME, event_handler).unwrap();
+
+    set_service_status(&status_handle, ServiceState::StartPending, 0).unwrap();
+
+    // Ensure config directory exists
+    if let Err(e) = ensure_config_directory() {
+        eprintln!("{e}");
+        set_service_status(&status_handle, ServiceState::Stopped, 3).unwrap();
+        return;
+    }
+
+    // Initialize logger with default level first so early log messages are captured
+    let events = event_channel();
+    let logger_handle = match logger_init("info", false, events.clone()) {
+        Err(e) => {
+            eprintln!("Failed to initialize logger: {e}");
+            set_service_status(&status_handle, ServiceState::Stopped, 2).unwrap();
+            return;
+        }
+        Ok(handle) => handle,
+    };
+
+    // Read config (may emit log messages)
+    let config = match Config::read() {
+        Ok(c) => c,
+        Err(e) => {
+            log::error!("{e}");
+            set_service_status(&status_handle, ServiceState::Stopped, 1).unwrap();
+            return;
+        }
+    };
+
+    let context = new_context(logger_handle, config, events);
+
+    // Tell the system that the service is running now
+    if let Err(e) = set_service_status(&status_handle, ServiceState::Running, 0) {
+        log::error!("Service failed: {e:?}");
+        return;
+    }
+
+    let shutdown = async move {
+        let _ = tokio::task::spawn_blocking(move || shutdown_rx.recv()).await;
+    };
+
+    // The dashboard is served by the tray process.
+    if let Err(e) = run_service(context, shutdown, false) {
+        log::error!("Service failed: {:?}", e);
+    }
+
+    let _ = set_service_status(&status_handle, ServiceState::Stopped, 0);
+}
@@ -0,0 +1,308 @@
+//! Installs and controls the daemon as a systemd service.
+//!
+//! The IPC socket is owned by a socket unit, so the daemon is started on
+//! demand when a client connects and the socket survives service restarts.
+
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::{
+    arg_parser::InstallArgs,
+    common::{
+        config::Config,
+        consts::SYSTEMD_WATCHDOG_SEC,
+        message::{Request, Response},
+        prompt::{Answer, yes_no_question},
+        strings::{
+            SERVICE_DESCRIPTION, SERVICE_DISPLAY_NAME, SOCKET_PATH, SYSTEMD_SERVICE_UNIT,
+            SYSTEMD_SOCKET_UNIT, SYSTEMD_UNIT_DIR,
+        },
+    },
+};
+
+fn unit_path(unit: &str) -> PathBuf {
+    Path::new(SYSTEMD_UNIT_DIR).join(unit)
+}
+
+/// The service unit. `Type=notify` makes `systemctl start` wait for the
+/// daemon's `READY=1`, and the watchdog restarts it if the update loop hangs.
+fn service_unit(exe: &Path) -> String {
+    format!(
+        "[Unit]
+Description={SERVICE_DISPLAY_NAME} - {SERVICE_DESCRIPTION}
+Requires={SYSTEMD_SOCKET_UNIT}
+After={SYSTEMD_SOCKET_UNIT} network-online.target
+Wants=network-online.target
+
+[Service]
+Type=notify
+ExecStart=\"{}\" service run
+WatchdogSec={}
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target
+",
+        exe.display(),
+        SYSTEMD_WATCHDOG_SEC.as_secs()
+    )
+}
+
+/// The socket unit that listens on the IPC socket on the daemon's behalf.
+fn socket_unit() -> String {
+    format!(
+        "[Unit]
+Description={SERVICE_DISPLAY_NAME} IPC socket
+
+[Socket]
+ListenStream={SOCKET_PATH}
+SocketMode=0600
+RemoveOnStop=true
+
+[Install]
+WantedBy=sockets.target
+"
+    )
+}
+
+/// Runs `systemctl` with `args`, turning a non-zero exit into an error that
+/// carries its stderr.
+fn systemctl(args: &[&str]) -> Result<()> {
+    let output = Command::new("systemctl")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| anyhow!("Failed to run systemctl: {e}"))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "`systemctl {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+pub fn service_is_running() -> Result<bool> {
+    let status = Command::new("systemctl")
+        .args(["is-active", "--quiet", SYSTEMD_SERVICE_UNIT])
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| anyhow!("Failed to run systemctl: {e}"))?;
+    Ok(status.success())
+}
+
+fn service_is_installed() -> bool {
+    unit_path(SYSTEMD_SERVICE_UNIT).exists()
+}
+
+/// Installs the systemd service and socket units.
+///
+/// Writes both units to the systemd unit directory and reloads systemd. Unless
+/// `--no-startup` is given, the units are also enabled so the service starts
+/// on boot.
+pub fn install_service(args: InstallArgs) -> Result<()> {
+    if service_is_installed() {
+        println!("An existing installation of {SERVICE_DISPLAY_NAME} was detected.");
+
+        let keep_config = loop {
+            match yes_no_question("Would you like to keep your existing configuration?") {
+                Ok(Answer::Yes) => break true,
+                Ok(Answer::No) => break false,
+                Err(e) => println!("{e}"),
+            }
+        };
+
+        remove_service()?;
+
+        if !keep_config
+            && let Ok(path) = Config::get_config_directory_path()
+            && path.is_dir()
+        {
+            std::fs::remove_dir_all(&path)
+                .map_err(|e| anyhow!("Failed to delete configuration directory: {e}"))?;
+            println!("Configuration directory deleted.");
+        } else if keep_config {
+            println!("Upgrading \u{2014} existing configuration will be preserved.");
+        }
+    }
+
+    let exe =
+        std::env::current_exe().map_err(|e| anyhow!("Failed to determine executable path: {e}"))?;
+
+    for (unit, content) in [
+        (SYSTEMD_SOCKET_UNIT, socket_unit()),
+        (SYSTEMD_SERVICE_UNIT, service_unit(&exe)),
+    ] {
+        let path = unit_path(unit);
+        std::fs::write(&path, content).map_err(|e| anyhow!("Failed to write {path:?}: {e}"))?;
+    }
+
+    systemctl(&["daemon-reload"])?;
+    if !args.no_startup {
+        systemctl(&["enable", SYSTEMD_SOCKET_UNIT, SYSTEMD_SERVICE_UNIT])?;
+    }
+
+    println!("{SERVICE_DISPLAY_NAME} is installed.");
+    Ok(())
+}
+
+/// Uninstalls the systemd units.
+///
+/// Stops and disables the service and its socket, removes the unit files and
+/// offers to delete the configuration directory.
+pub fn uninstall_service() -> Result<()> {
+    remove_service()?;
+    prompt_delete_config_directory();
+    Ok(())
+}
+
+fn remove_service() -> Result<()> {
+    if !service_is_installed() {
+        return Err(anyhow!("{SERVICE_DISPLAY_NAME} is not installed"));
+    }
+
+    // Stopping and disabling fail harmlessly when the units are not active or
+    // not enabled.
+    let _ = systemctl(&["stop", SYSTEMD_SERVICE_UNIT, SYSTEMD_SOCKET_UNIT]);
+    let _ = systemctl(&["disable", SYSTEMD_SERVICE_UNIT, SYSTEMD_SOCKET_UNIT]);
+
+    for unit in [SYSTEMD_SERVICE_UNIT, SYSTEMD_SOCKET_UNIT] {
+        let path = unit_path(unit);
+        if path.exists() {
+            std::fs::remove_file(&path).map_err(|e| anyhow!("Failed to remove {path:?}: {e}"))?;
+        }
+    }
+    systemctl(&["daemon-reload"])?;
+
+    println!("{SERVICE_DISPLAY_NAME} is uninstalled.");
+    Ok(())
+}
+
+fn prompt_delete_config_directory() {
+    match Config::get_config_directory_path() {
+        Ok(path) if path.is_dir() => loop {
+            match yes_no_question("Do you want to delete the configuration directory?") {
+                Ok(Answer::Yes) => {
+                    if let Err(e) = std::fs::remove_dir_all(&path) {
+                        eprintln!("Failed to delete configuration directory: {e}");
+                    } else {
+                        println!("Configuration directory deleted.");
+                    }
+                    break;
+                }
+                Ok(Answer::No) => break,
+                Err(e) => println!("{e}"),
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Starts the systemd service.
+///
+/// Offers to install the service if it is missing and returns an error if it
+/// is already running. There is no tray icon on Linux, and the dashboard is
+/// controlled by the configuration file rather than `--no-web`, since systemd
+/// starts the daemon with a fixed command line.
+pub fn start_service(_with_tray: bool, with_web: bool) -> Result<()> {
+    if !service_is_installed() {
+        loop {
+            match yes_no_question(&format!(
+                "{SERVICE_DISPLAY_NAME} is not currently installed. Would you like to install it now?"
+            )) {
+                Ok(Answer::Yes) => {
+                    install_service(InstallArgs { no_startup: false })?;
+                    break;
+                }
+                Ok(Answer::No) => {
+                    println!("Start command aborted.");
+                    return Ok(());
+                }
+                Err(e) => println!("{e}."),
+            }
+        }
+    }
+
+    if service_is_running()? {
+        return Err(anyhow!("{SERVICE_DISPLAY_NAME} is already running"));
+    }
+
+    if !with_web {
+        println!(
+            "--no-web has no effect on a systemd service. \
+             Set `disabled = true` in the [dashboard] section of the configuration instead."
+        );
+    }
+
+    systemctl(&["start", SYSTEMD_SOCKET_UNIT, SYSTEMD_SERVICE_UNIT])?;
+
+    if service_is_running()? {
+        println!("{SERVICE_DISPLAY_NAME} is running.");
+        Ok(())
+    } else {
+        Err(anyhow!("Failed to start {SERVICE_DISPLAY_NAME}"))
+    }
+}
+
+/// Stops the systemd service and its socket.
+///
+/// The socket is stopped too, since a client connecting to it would otherwise
+/// start the service again.
+pub fn stop_service() -> Result<()> {
+    if !service_is_installed() {
+        return Err(anyhow!("{SERVICE_DISPLAY_NAME} is not installed"));
+    }
+
+    if !service_is_running()? {
+        return Err(anyhow!("{SERVICE_DISPLAY_NAME} is not running"));
+    }
+
+    systemctl(&["stop", SYSTEMD_SERVICE_UNIT, SYSTEMD_SOCKET_UNIT])?;
+    println!("{SERVICE_DISPLAY_NAME} has stopped");
+    Ok(())
+}
+
+pub async fn version() -> Result<()> {
+    if !service_is_installed() {
+        return Err(anyhow!("{SERVICE_DISPLAY_NAME} is not installed"));
+    }
+
+    if !service_is_running()? {
+        return Err(anyhow!("{SERVICE_DISPLAY_NAME} is not running"));
+    }
+
+    match Request::Version.send().await? {
+        Response::Version(ver) => {
+            println!("Version {ver}");
+            Ok(())
+        }
+        _ => Err(anyhow!("Failed to receive response from the service")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_unit_runs_daemon_with_notify_and_watchdog() {
+        let unit = service_unit(Path::new("/opt/barvaz dns/BarvazDNS"));
+        assert!(unit.contains("ExecStart=\"/opt/barvaz dns/BarvazDNS\" service run\n"));
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains(&format!("WatchdogSec={}\n", SYSTEMD_WATCHDOG_SEC.as_secs())));
+        assert!(unit.contains(&format!("Requires={SYSTEMD_SOCKET_UNIT}\n")));
+        assert!(unit.contains("WantedBy=multi-user.target\n"));
+    }
+
+    #[test]
+    fn socket_unit_listens_on_owner_only_socket() {
+        let unit = socket_unit();
+        assert!(unit.contains(&format!("ListenStream={SOCKET_PATH}\n")));
+        assert!(unit.contains("SocketMode=0600\n"));
+        assert!(unit.contains("WantedBy=sockets.target\n"));
+    }
+}
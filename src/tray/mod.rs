@@ -1,6 +1,6 @@
 use anyhow::{Result, anyhow};
 use std::mem;
-use std::sync::OnceLock;
+use std::sync::{Mutex, OnceLock};
 use windows_sys::Win32::{
     Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, POINT, WPARAM},
     System::LibraryLoader::GetModuleHandleW,
@@ -13,21 +13,22 @@ use windows_sys::Win32::{
             AppendMenuW, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu,
             DestroyWindow, DispatchMessageW, GWLP_USERDATA, GetCursorPos, GetMessageW,
             GetWindowLongPtrW, IDI_APPLICATION, LoadIconW, MF_SEPARATOR, MF_STRING, MSG,
-            PostQuitMessage, RegisterClassW, RegisterWindowMessageW, SW_HIDE, SetForegroundWindow,
-            SetTimer, SetWindowLongPtrW, ShowWindow, TPM_BOTTOMALIGN, TPM_LEFTALIGN,
-            TrackPopupMenu, TranslateMessage, WM_APP, WM_COMMAND, WM_DESTROY, WM_TIMER, WNDCLASSW,
+            PostMessageW, PostQuitMessage, RegisterClassW, RegisterWindowMessageW, SW_HIDE,
+            SetForegroundWindow, SetWindowLongPtrW, ShowWindow, TPM_BOTTOMALIGN, TPM_LEFTALIGN,
+            TrackPopupMenu, TranslateMessage, WM_APP, WM_COMMAND, WM_DESTROY, WNDCLASSW,
             WS_OVERLAPPEDWINDOW,
         },
     },
 };
 
-use crate::common::consts::TRAY_POLL_INTERVAL_MS;
-use crate::common::message::{Request, Response, UpdateStatus};
+use crate::common::consts::TRAY_RECONNECT_DELAY;
+use crate::common::message::{self, Event, Request, UpdateStatus};
 use crate::common::strings::SERVICE_DISPLAY_NAME;
 use crate::service_manager;
 
-const TRAY_TIMER_ID: usize = 1;
 const WM_TRAY_ICON: u32 = WM_APP + 1;
+/// Posted when [`TOOLTIP`] changed.
+const WM_TRAY_TOOLTIP: u32 = WM_APP + 2;
 const IDM_FORCE_UPDATE: usize = 1001;
 const IDM_OPEN_CONFIG: usize = 1002;
 const IDM_EXIT: usize = 1003;
@@ -41,51 +42,85 @@ const IDM_OPEN_DASHBOARD: usize = 1006;
 static WM_TASKBAR_CREATED: OnceLock<u32> = OnceLock::new();
 static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
 static WEB_ENABLED: OnceLock<bool> = OnceLock::new();
+/// The tooltip most recently handed to the window by [`follow_service`].
+static TOOLTIP: Mutex<String> = Mutex::new(String::new());
 
 fn wide_string(s: &str) -> Vec<u16> {
     s.encode_utf16().chain(std::iter::once(0)).collect()
 }
 
-fn query_status() -> Option<(UpdateStatus, Vec<String>)> {
-    let rt = RUNTIME.get()?;
-    let status = match rt.block_on(Request::GetStatus.send()).ok()? {
-        Response::Status(s) => s,
-        _ => return None,
-    };
-    let configured = match rt.block_on(Request::GetConfig.send()).ok()? {
-        Response::Config(cfg) => cfg.domain,
-        _ => return None,
+fn tooltip_text(status: &UpdateStatus, configured: &[String]) -> String {
+    let mut text = match &status.last_success {
+        Some((time, updated)) => {
+            let datetime: chrono::DateTime<chrono::Local> = (*time).into();
+            let ts = datetime.format("%Y-%m-%d %H:%M:%S");
+            let failed = configured.iter().filter(|d| !updated.contains(d)).count();
+            if failed > 0 {
+                format!(
+                    "{SERVICE_DISPLAY_NAME} — last update: {ts} ({failed} domain(s) not updated)"
+                )
+            } else {
+                format!("{SERVICE_DISPLAY_NAME} — last update: {ts}")
+            }
+        }
+        None => format!("{SERVICE_DISPLAY_NAME} — no updates yet"),
     };
-    Some((status, configured.into_iter().collect()))
-}
 
-fn format_tooltip() -> String {
-    let Some((status, configured)) = query_status() else {
-        return format!("{SERVICE_DISPLAY_NAME} — no updates yet");
-    };
-    let Some((time, updated)) = status.last_success else {
-        return format!("{SERVICE_DISPLAY_NAME} — no updates yet");
-    };
-    let datetime: chrono::DateTime<chrono::Local> = time.into();
-    let ts = datetime.format("%Y-%m-%d %H:%M:%S");
-    let failed = configured.iter().filter(|d| !updated.contains(d)).count();
-    if failed > 0 {
-        format!("{SERVICE_DISPLAY_NAME} — last update: {ts} ({failed} domain(s) not updated)")
-    } else {
-        format!("{SERVICE_DISPLAY_NAME} — last update: {ts}")
+    if status.consecutive_failures > 0 {
+        text.push_str(&format!(
+            "\nLast {} attempt(s) failed",
+            status.consecutive_failures
+        ));
+        if let Some(error) = &status.last_error {
+            text.push_str(&format!(": {error}"));
+        }
     }
+    text
 }
 
 fn set_tooltip_text(nid: &mut NOTIFYICONDATAW, text: &str) {
     let tip = wide_string(text);
-    let len = tip.len().min(nid.szTip.len());
+    // Leave room for the terminating null when the text is truncated.
+    let len = tip.len().min(nid.szTip.len() - 1);
     nid.szTip = [0; 128];
     nid.szTip[..len].copy_from_slice(&tip[..len]);
     unsafe { Shell_NotifyIconW(NIM_MODIFY, nid) };
 }
 
-fn update_tooltip(nid: &mut NOTIFYICONDATAW) {
-    set_tooltip_text(nid, &format_tooltip());
+/// Hands `text` to the window, which owns the icon, from any thread.
+fn post_tooltip(hwnd: isize, text: String) {
+    if let Ok(mut tooltip) = TOOLTIP.lock() {
+        *tooltip = text;
+    }
+    unsafe { PostMessageW(hwnd as HWND, WM_TRAY_TOOLTIP, 0, 0) };
+}
+
+/// Keeps the tooltip in step with the events of the service. While the
+/// service is unreachable the tooltip says so, and subscribing is retried.
+async fn follow_service(hwnd: isize) {
+    loop {
+        if let Ok(mut subscription) = message::subscribe().await {
+            let mut status = None;
+            let mut configured = None;
+            while let Ok(Some(event)) = subscription.next().await {
+                match event {
+                    Event::Status(s) => status = Some(s),
+                    Event::Config(config) => {
+                        configured = Some(config.domain.into_iter().collect::<Vec<_>>());
+                    }
+                    Event::Log(_) => continue,
+                }
+                if let (Some(status), Some(configured)) = (&status, &configured) {
+                    post_tooltip(hwnd, tooltip_text(status, configured));
+                }
+            }
+        }
+        post_tooltip(
+            hwnd,
+            format!("{SERVICE_DISPLAY_NAME} \u{2014} service is not running"),
+        );
+        tokio::time::sleep(TRAY_RECONNECT_DELAY).await;
+    }
 }
 
 fn show_context_menu(hwnd: HWND) {
@@ -192,13 +227,31 @@ fn handle_menu_command(hwnd: HWND, id: usize) {
             }
         }
         IDM_OPEN_DASHBOARD => {
-            let port = crate::common::config::Config::get_config_file_path()
+            let base_url = crate::common::config::Config::get_config_file_path()
                 .ok()
                 .and_then(|p| std::fs::read_to_string(p).ok())
                 .and_then(|s| toml::from_str::<crate::common::config::Config>(&s).ok())
-                .map(|c| c.effective_dashboard_port())
-                .unwrap_or(crate::common::consts::WEB_DASHBOARD_PORT);
-            let url = format!("http://127.0.0.1:{port}");
+                .map(|c| c.dashboard_local_url())
+                .unwrap_or_else(|| {
+                    format!(
+                        "http://127.0.0.1:{}",
+                        crate::common::consts::WEB_DASHBOARD_PORT
+                    )
+                });
+            // a short-lived login ticket signs the browser in, so the secret
+            // itself never ends up in the browser history
+            let url = match crate::dashboard::auth::Secret::read() {
+                Ok(secret) => {
+                    let ticket = secret.issue(
+                        crate::dashboard::auth::Purpose::LoginTicket,
+                        std::time::SystemTime::now(),
+                    );
+                    format!("{base_url}/#ticket={ticket}")
+                }
+                // Only Administrators can read the secret, so a tray that was
+                // started without elevation opens the sign-in page instead.
+                Err(_) => base_url,
+            };
             let url_wide = wide_string(&url);
             let verb = wide_string("open");
             unsafe {
@@ -250,20 +303,14 @@ unsafe extern "system" fn wnd_proc(
             handle_menu_command(hwnd, wparam & 0xFFFF);
             0
         }
-        WM_TIMER if wparam == TRAY_TIMER_ID => {
+        WM_TRAY_TOOLTIP => {
             let ptr = unsafe { GetWindowLongPtrW(hwnd, GWLP_USERDATA) };
             if ptr == 0 {
                 return 0;
             }
             let nid = unsafe { &mut *(ptr as *mut NOTIFYICONDATAW) };
-            if service_manager::service_is_running().unwrap_or(false) {
-                update_tooltip(nid);
-            } else {
-                set_tooltip_text(
-                    nid,
-                    &format!("{SERVICE_DISPLAY_NAME} \u{2014} service is not running"),
-                );
-            }
+            let text = TOOLTIP.lock().map(|t| t.clone()).unwrap_or_default();
+            set_tooltip_text(nid, &text);
             // Re-add the icon in case the initial NIM_ADD failed (taskbar not ready).
             unsafe { Shell_NotifyIconW(NIM_ADD, nid) };
             0
@@ -379,12 +426,8 @@ pub fn run(with_web: bool) -> Result<()> {
         nid.uCallbackMessage = WM_TRAY_ICON;
         nid.hIcon = icon;
 
-        let initial_tip = if service_manager::service_is_running().unwrap_or(false) {
-            format_tooltip()
-        } else {
-            format!("{SERVICE_DISPLAY_NAME} \u{2014} service is not running")
-        };
-        let tip = wide_string(&initial_tip);
+        // The first events of the service replace this right away.
+        let tip = wide_string(SERVICE_DISPLAY_NAME);
         let len = tip.len().min(nid.szTip.len());
         nid.szTip[..len].copy_from_slice(&tip[..len]);
 
@@ -392,7 +435,9 @@ pub fn run(with_web: bool) -> Result<()> {
 
         SetWindowLongPtrW(hwnd, GWLP_USERDATA, &mut nid as *mut _ as isize);
 
-        SetTimer(hwnd, TRAY_TIMER_ID, TRAY_POLL_INTERVAL_MS, None);
+        if let Some(rt) = RUNTIME.get() {
+            rt.spawn(follow_service(hwnd as isize));
+        }
 
         let mut msg: MSG = mem::zeroed();
         while GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) > 0 {